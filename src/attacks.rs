@@ -0,0 +1,211 @@
+/*
+ * Attack maps and square control.
+ *
+ * A piece attacks a square when theory_valid_move would let it capture
+ * an enemy piece standing there. Pawns therefore attack diagonally only,
+ * and pinned pieces still attack, like in the usual definitions. Empty
+ * squares and squares held by the attacker's own side (defended pieces)
+ * are asked about by placing a stand-in enemy piece on them.
+ */
+
+use crate::*;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn square(name: &str) -> (usize, usize) {
+        movegen::parse_square(name).unwrap()
+    }
+
+    #[test]
+    fn start_position_map() {
+        let notat = AlgebraicNotation::from_fen(fen::START_FEN).unwrap();
+        let map = notat.attack_map();
+        /* every square on the third rank, f3 by the g-pawn, e-pawn and knight */
+        assert_eq!(map.squares(_WHITE_PIECE).len(), 22);
+        assert_eq!(map.count(_WHITE_PIECE, square("f3")), 3);
+        assert_eq!(map.count(_WHITE_PIECE, square("e4")), 0);
+        assert_eq!(map.count(_BLACK_PIECE, square("c6")), 3);
+        /* the queen defends d2 along with bishop, knight and king */
+        assert_eq!(map.count(_WHITE_PIECE, square("d2")), 4);
+        assert_eq!(map.total(_WHITE_PIECE), map.total(_BLACK_PIECE));
+    }
+
+    #[test]
+    fn attackers_and_defenders() {
+        let notat =
+            AlgebraicNotation::from_fen("4k3/8/2n5/3p4/4P3/2N2B2/8/3RK3 w - - 0 1").unwrap();
+        let control = notat.square_control(square("d5"));
+        assert_eq!(control.side, _BLACK_PIECE);
+        let mut attackers: Vec<char> = control.attackers.iter().map(|p| p.piece).collect();
+        attackers.sort();
+        /* the rook sees d5 through d2, d3 and d4, the bishop is blocked by e4 */
+        assert_eq!(attackers, vec!['N', 'P', 'R']);
+        assert_eq!(control.defenders.len(), 0);
+
+        let control = notat.square_control(square("e4"));
+        assert_eq!(control.side, _WHITE_PIECE);
+        assert_eq!(control.attackers[0].position, square("d5"));
+        assert_eq!(control.defenders.len(), 2);
+
+        /* on an empty square, the side to move defends */
+        let control = notat.square_control(square("d4"));
+        assert_eq!(control.side, _WHITE_PIECE);
+        assert_eq!(control.attackers[0].piece, 'N');
+        assert_eq!(control.defenders[0].position, square("d1"));
+    }
+}
+
+/* The occupant is replaced while asking, so the board is only borrowed mutably */
+pub(crate) fn attackers_on(
+    board: &mut Board,
+    square: (usize, usize),
+    color: usize,
+) -> Vec<FoundPiece> {
+    let occupant = board.table[square.0][square.1].take();
+    let target = match &occupant {
+        Some(p) if p.get_color() != color => p.clone(),
+        _ => {
+            let mut stand_in = board.pieces[&'P'].clone();
+            stand_in.set_color(1 - color);
+            stand_in
+        }
+    };
+    board.table[square.0][square.1] = Some(target);
+
+    let mut attackers = vec![];
+    for (r, row) in board.table.iter().enumerate() {
+        for (c, piece) in row.iter().enumerate() {
+            let piece = match piece {
+                Some(p) if p.get_color() == color && (r, c) != square => p,
+                _ => continue,
+            };
+            let test_move =
+                piece.theory_valid_move(board, true, (r + 1, c + 1), (square.0 + 1, square.1 + 1));
+            if test_move.ok() != Some(false) {
+                attackers.push(FoundPiece {
+                    piece: movegen::piece_letter(piece.as_ref()),
+                    position: (r, c),
+                });
+            }
+        }
+    }
+
+    board.table[square.0][square.1] = occupant;
+    attackers
+}
+
+/* The pieces of color that attack the square, whatever stands on it */
+pub fn attackers_of(board: &Board, square: (usize, usize), color: usize) -> Vec<FoundPiece> {
+    attackers_on(&mut board.clone(), square, color)
+}
+
+/* Whether color attacks the square, e.g. to see if a king stands in check */
+pub fn is_attacked(board: &Board, square: (usize, usize), color: usize) -> bool {
+    match &board.table[square.0][square.1] {
+        /* no stand-in needed, so no copy of the board either */
+        Some(p) if p.get_color() != color => board.table.iter().enumerate().any(|(r, row)| {
+            row.iter().enumerate().any(|(c, piece)| match piece {
+                Some(a) if a.get_color() == color => {
+                    a.theory_valid_move(board, true, (r + 1, c + 1), (square.0 + 1, square.1 + 1))
+                        .ok()
+                        != Some(false)
+                }
+                _ => false,
+            })
+        }),
+        _ => !attackers_of(board, square, color).is_empty(),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AttackMap {
+    /* per color, the number of pieces attacking each square */
+    counts: [[[u8; 8]; 8]; 2],
+}
+
+impl AttackMap {
+    pub fn new(board: &Board) -> AttackMap {
+        let mut scratch = board.clone();
+        let mut counts = [[[0; 8]; 8]; 2];
+        for (color, color_counts) in counts.iter_mut().enumerate() {
+            for (r, row) in color_counts.iter_mut().enumerate() {
+                for (c, count) in row.iter_mut().enumerate() {
+                    *count = attackers_on(&mut scratch, (r, c), color).len() as u8;
+                }
+            }
+        }
+        AttackMap { counts }
+    }
+
+    pub fn count(&self, color: usize, square: (usize, usize)) -> usize {
+        self.counts[color][square.0][square.1] as usize
+    }
+
+    pub fn attacked(&self, color: usize, square: (usize, usize)) -> bool {
+        self.count(color, square) > 0
+    }
+
+    /* Every square color attacks at least once */
+    pub fn squares(&self, color: usize) -> Vec<(usize, usize)> {
+        let mut squares = vec![];
+        for r in 0..8 {
+            for c in 0..8 {
+                if self.attacked(color, (r, c)) {
+                    squares.push((r, c));
+                }
+            }
+        }
+        squares
+    }
+
+    /* All attacks of color added up, a rough measure of activity */
+    pub fn total(&self, color: usize) -> usize {
+        self.counts[color]
+            .iter()
+            .flatten()
+            .map(|count| *count as usize)
+            .sum()
+    }
+
+    /* The color attacking the square more often, if any */
+    pub fn controlled_by(&self, square: (usize, usize)) -> Option<usize> {
+        let white = self.count(_WHITE_PIECE, square);
+        let black = self.count(_BLACK_PIECE, square);
+        match white.cmp(&black) {
+            cmp::Ordering::Greater => Some(_WHITE_PIECE),
+            cmp::Ordering::Less => Some(_BLACK_PIECE),
+            cmp::Ordering::Equal => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SquareControl {
+    pub square: (usize, usize),
+    /* the color of the piece on the square, or the side to move if empty */
+    pub side: usize,
+    pub attackers: Vec<FoundPiece>,
+    pub defenders: Vec<FoundPiece>,
+}
+
+impl AlgebraicNotation {
+    pub fn attack_map(&self) -> AttackMap {
+        AttackMap::new(&self.board)
+    }
+
+    pub fn square_control(&self, square: (usize, usize)) -> SquareControl {
+        let side = match &self.board.table[square.0][square.1] {
+            Some(p) => p.get_color(),
+            None => self.turn,
+        };
+        let mut scratch = self.board.clone();
+        SquareControl {
+            square,
+            side,
+            attackers: attackers_on(&mut scratch, square, 1 - side),
+            defenders: attackers_on(&mut scratch, square, side),
+        }
+    }
+}
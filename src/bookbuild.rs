@@ -0,0 +1,238 @@
+/*
+ * Building Polyglot books from PGN collections. Every game is replayed
+ * up to a ply limit, and each move is counted in the position it was
+ * played from, with how the game went for the side that played it.
+ * Moves seen in too few games are left out, and so are games between
+ * players below a rating, if one is given.
+ *
+ * The weight of a move is the number of games it was played in, or its
+ * points in them the way Polyglot's own make-book counts them: two for a
+ * win and one for a draw. Moves that end up with no weight are left out.
+ */
+
+use crate::book::{encode_move, polyglot_key, Book, BookEntry};
+use crate::game::GameResult;
+use crate::pgn::{PgnError, PgnGame};
+use crate::*;
+use std::collections::HashMap;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pgn::parse_pgn;
+
+    const GAMES: &str = r#"[WhiteElo "2200"] [BlackElo "2100"]
+1. e4 e5 2. Nf3 1-0
+[WhiteElo "2300"] [BlackElo "2250"]
+1. e4 c5 1/2-1/2
+[WhiteElo "2000"] [BlackElo "2400"]
+1. d4 d5 0-1
+[Event "unrated"]
+1. c4 1-0
+[Event "broken"]
+1. e4 e5 2. Ke3 *
+"#;
+
+    fn weights(book: &Book, moves: &[&str]) -> Vec<(String, u16)> {
+        let mut position = AlgebraicNotation::from_fen(fen::START_FEN).unwrap();
+        for m in moves {
+            position.play_move(m).unwrap();
+        }
+        book.moves(&position)
+            .into_iter()
+            .map(|m| (m.legal.san, m.weight))
+            .collect()
+    }
+
+    #[test]
+    fn counts_points_up_to_the_ply_limit() {
+        let games = parse_pgn(GAMES).unwrap();
+        let config = BuildConfig {
+            max_plies: 2,
+            ..BuildConfig::default()
+        };
+        let (book, report) = build_book(&games, &config);
+        assert_eq!(
+            weights(&book, &[]),
+            vec![("e4".to_string(), 3), ("c4".to_string(), 2)]
+        );
+        /* e5 lost and the broken game has no result */
+        assert_eq!(weights(&book, &["e4"]), vec![("c5".to_string(), 1)]);
+        assert!(weights(&book, &["e4", "e5"]).is_empty());
+        assert_eq!((report.games, report.used), (5, 5));
+        /* the illegal king move is beyond the limit */
+        assert!(report.errors.is_empty());
+        assert_eq!(report.entries, book.len());
+
+        let (_, report) = build_book(&games, &BuildConfig::default());
+        assert_eq!(report.errors.len(), 1);
+        assert_eq!(report.errors[0].ply, Some(2));
+    }
+
+    #[test]
+    fn filters_ratings_and_rare_moves() {
+        let games = parse_pgn(GAMES).unwrap();
+        let config = BuildConfig {
+            min_games: 2,
+            min_rating: Some(2100),
+            weight: BookWeight::Games,
+            ..BuildConfig::default()
+        };
+        let (book, report) = build_book(&games, &config);
+        assert_eq!(weights(&book, &[]), vec![("e4".to_string(), 2)]);
+        assert_eq!(book.len(), 1);
+        assert_eq!((report.used, report.below_rating), (2, 3));
+        assert!(report.to_string().starts_with("2 of 5 games used"));
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BookWeight {
+    /* games the move was played in */
+    Games,
+    /* two for every win and one for every draw */
+    Points,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BuildConfig {
+    /* plies from the start of each game that go into the book */
+    pub max_plies: usize,
+    pub min_games: u32,
+    /* both players need at least this, when given */
+    pub min_rating: Option<u32>,
+    pub weight: BookWeight,
+}
+
+impl Default for BuildConfig {
+    fn default() -> Self {
+        BuildConfig {
+            max_plies: 20,
+            min_games: 1,
+            min_rating: None,
+            weight: BookWeight::Points,
+        }
+    }
+}
+
+/* For the side that played the move */
+#[derive(Debug, Clone, Copy, Default)]
+struct MoveStats {
+    games: u32,
+    wins: u32,
+    draws: u32,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct BuildReport {
+    pub games: usize,
+    pub used: usize,
+    pub below_rating: usize,
+    /* games with a move that could not be played; the moves before count */
+    pub errors: Vec<PgnError>,
+    pub positions: usize,
+    pub entries: usize,
+}
+
+impl fmt::Display for BuildReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} of {} games used, {} below the rating and {} with illegal moves; {} moves in {} positions",
+            self.used,
+            self.games,
+            self.below_rating,
+            self.errors.len(),
+            self.entries,
+            self.positions
+        )
+    }
+}
+
+fn rated(game: &PgnGame, min_rating: Option<u32>) -> bool {
+    match min_rating {
+        Some(min) => [_WHITE_PIECE, _BLACK_PIECE]
+            .iter()
+            .all(|color| game.rating(*color).is_some_and(|r| r >= min)),
+        None => true,
+    }
+}
+
+pub fn build_book(games: &[PgnGame], config: &BuildConfig) -> (Book, BuildReport) {
+    let mut report = BuildReport {
+        games: games.len(),
+        ..BuildReport::default()
+    };
+    let mut stats: HashMap<(u64, u16), MoveStats> = HashMap::new();
+
+    for game in games {
+        if !rated(game, config.min_rating) {
+            report.below_rating += 1;
+            continue;
+        }
+        let mut position = match game.start() {
+            Ok(position) => position,
+            Err(e) => {
+                report.errors.push(e);
+                continue;
+            }
+        };
+        report.used += 1;
+        for (ply, san) in game.moves.iter().take(config.max_plies).enumerate() {
+            let mover = position.turn();
+            let key = polyglot_key(&position);
+            let played = match position.play_move(san) {
+                Ok(m) => m,
+                Err(e) => {
+                    report.errors.push(PgnError {
+                        game: game.index,
+                        ply: Some(ply),
+                        message: format!("{}: {}", san, e),
+                    });
+                    break;
+                }
+            };
+            let entry = stats.entry((key, encode_move(&played))).or_default();
+            entry.games += 1;
+            match game.result {
+                GameResult::Draw => entry.draws += 1,
+                GameResult::Ongoing => (),
+                result if result == GameResult::win_for(mover) => entry.wins += 1,
+                _ => (),
+            }
+        }
+    }
+
+    let mut kept: Vec<(u64, u16, u32)> = stats
+        .into_iter()
+        .filter(|(_, s)| s.games >= config.min_games)
+        .map(|((key, raw_move), s)| {
+            let weight = match config.weight {
+                BookWeight::Games => s.games,
+                BookWeight::Points => 2 * s.wins + s.draws,
+            };
+            (key, raw_move, weight)
+        })
+        .filter(|(_, _, weight)| *weight > 0)
+        .collect();
+
+    /* weights have to fit in 16 bits; scaling keeps their proportions */
+    let heaviest = kept.iter().map(|(_, _, w)| *w).max().unwrap_or(0);
+    if heaviest > u16::MAX as u32 {
+        for (_, _, weight) in kept.iter_mut() {
+            *weight = (*weight as u64 * u16::MAX as u64 / heaviest as u64).max(1) as u32;
+        }
+    }
+    kept.sort_by_key(|(key, raw_move, weight)| (*key, std::cmp::Reverse(*weight), *raw_move));
+
+    let mut keys: Vec<u64> = kept.iter().map(|(key, _, _)| *key).collect();
+    keys.dedup();
+    report.positions = keys.len();
+    report.entries = kept.len();
+    let book = Book::new(
+        kept.into_iter()
+            .map(|(key, raw_move, weight)| BookEntry::new(key, raw_move, weight as u16))
+            .collect(),
+    );
+    (book, report)
+}
@@ -0,0 +1,448 @@
+/*
+ * Chess clocks and time controls.
+ *
+ * A time control is a list of stages, each giving a number of moves (or
+ * the rest of the game), the time added when the stage begins and the
+ * bonus per move, like 40 moves in 90 minutes followed by 30 minutes for
+ * the rest, with 30 seconds added per move from the start:
+ *
+ *     40/5400+30:1800+30
+ *
+ * which is also the PGN TimeControl syntax understood by parse. Beyond
+ * PGN, a Bronstein delay is written with b instead of + and a simple
+ * delay with d, like 300b5 or 300d5. Time is read from a TimeSource, so
+ * tests can move it forward by hand.
+ */
+
+use crate::*;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn started(control: TimeControl) -> (Clock, Arc<ManualTime>) {
+        let time = Arc::new(ManualTime::default());
+        let mut clock = Clock::new(control, time.clone()).unwrap();
+        clock.start(_WHITE_PIECE);
+        (clock, time)
+    }
+
+    #[test]
+    fn fischer_and_sudden_death() {
+        let (mut clock, time) = started(TimeControl::fischer(secs(60), secs(2)));
+        time.advance(secs(10));
+        assert_eq!(clock.remaining(_WHITE_PIECE), secs(50));
+        clock.press().unwrap();
+        assert_eq!(clock.remaining(_WHITE_PIECE), secs(52));
+        assert_eq!(clock.running(), Some(_BLACK_PIECE));
+
+        time.advance(secs(61));
+        assert_eq!(clock.flagged(), Some(_BLACK_PIECE));
+        assert_eq!(clock.press(), Err(ClockError::Flagged(_BLACK_PIECE)));
+
+        let (mut clock, time) = started(TimeControl::sudden_death(secs(5)));
+        time.advance(secs(5));
+        assert_eq!(clock.press(), Err(ClockError::Flagged(_WHITE_PIECE)));
+    }
+
+    #[test]
+    fn delays() {
+        let (mut clock, time) = started(TimeControl::simple_delay(secs(60), secs(5)));
+        time.advance(secs(3));
+        assert_eq!(clock.remaining(_WHITE_PIECE), secs(60));
+        clock.press().unwrap();
+        time.advance(secs(8));
+        assert_eq!(clock.remaining(_BLACK_PIECE), secs(57));
+
+        let (mut clock, time) = started(TimeControl::bronstein(secs(60), secs(5)));
+        time.advance(secs(3));
+        assert_eq!(clock.remaining(_WHITE_PIECE), secs(57));
+        clock.press().unwrap();
+        assert_eq!(clock.remaining(_WHITE_PIECE), secs(60));
+        time.advance(secs(8));
+        clock.press().unwrap();
+        assert_eq!(clock.remaining(_BLACK_PIECE), secs(57));
+    }
+
+    #[test]
+    fn printed_controls_parse_back() {
+        for control in [
+            TimeControl::sudden_death(secs(300)),
+            TimeControl::fischer(secs(180), secs(2)),
+            TimeControl::bronstein(secs(60), secs(5)),
+            TimeControl::simple_delay(secs(60), secs(5)),
+            TimeControl::parse("40/5400d30:1800b30").unwrap(),
+        ] {
+            let text = control.to_string();
+            assert_eq!(TimeControl::parse(&text), Ok(control), "{}", text);
+        }
+        assert_eq!(
+            TimeControl::parse("300d5").unwrap().stages[0].bonus,
+            Bonus::Delay(secs(5))
+        );
+    }
+
+    #[test]
+    fn stages() {
+        let control = TimeControl::parse("2/60+1:30").unwrap();
+        assert_eq!(control.stages.len(), 2);
+        assert_eq!(control.to_string(), "2/60+1:30");
+        assert_eq!(
+            TimeControl::parse("40/5400+30:1800+30").unwrap().stages[0],
+            Stage {
+                moves: Some(40),
+                time: secs(5400),
+                bonus: Bonus::Increment(secs(30))
+            }
+        );
+        assert!(TimeControl::parse("40/").is_err());
+        assert!(TimeControl::parse("").is_err());
+        assert!(TimeControl::parse("300x5").is_err());
+        let empty = TimeControl { stages: vec![] };
+        assert!(Clock::new(empty, Arc::new(ManualTime::default())).is_err());
+
+        let (mut clock, time) = started(control);
+        for _ in 0..4 {
+            time.advance(secs(10));
+            clock.press().unwrap();
+        }
+        /* 60 - 20 + 2 increments, then the second stage */
+        assert_eq!(clock.remaining(_WHITE_PIECE), secs(72));
+        assert_eq!(clock.remaining(_BLACK_PIECE), secs(72));
+        let mut resumed =
+            Clock::resume(clock.control().clone(), time.clone(), [2, 2], [secs(72); 2]).unwrap();
+        resumed.start(_WHITE_PIECE);
+        time.advance(secs(10));
+        clock.press().unwrap();
+        assert_eq!(clock.remaining(_WHITE_PIECE), secs(62));
+        /* in the second stage, without increments */
+        resumed.press().unwrap();
+        assert_eq!(resumed.remaining(_WHITE_PIECE), secs(62));
+    }
+}
+
+fn secs(seconds: u64) -> Duration {
+    Duration::from_secs(seconds)
+}
+
+/* Only differences between readings matter */
+pub trait TimeSource: Send + Sync + fmt::Debug {
+    fn now(&self) -> Duration;
+}
+
+/* The real time, for games between people */
+#[derive(Debug)]
+pub struct MonotonicTime {
+    origin: Instant,
+}
+
+impl Default for MonotonicTime {
+    fn default() -> Self {
+        MonotonicTime {
+            origin: Instant::now(),
+        }
+    }
+}
+
+impl TimeSource for MonotonicTime {
+    fn now(&self) -> Duration {
+        self.origin.elapsed()
+    }
+}
+
+/* Time that only moves when told to */
+#[derive(Debug, Default)]
+pub struct ManualTime {
+    micros: AtomicU64,
+}
+
+impl ManualTime {
+    pub fn advance(&self, by: Duration) {
+        self.micros
+            .fetch_add(by.as_micros() as u64, Ordering::SeqCst);
+    }
+}
+
+impl TimeSource for ManualTime {
+    fn now(&self) -> Duration {
+        Duration::from_micros(self.micros.load(Ordering::SeqCst))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Bonus {
+    None,
+    /* Fischer: added after every move */
+    Increment(Duration),
+    /* given back after the move, but never more than was used */
+    Bronstein(Duration),
+    /* the clock waits this long before it starts counting down */
+    Delay(Duration),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Stage {
+    /* None for the rest of the game */
+    pub moves: Option<u32>,
+    pub time: Duration,
+    pub bonus: Bonus,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimeControl {
+    /* the last stage repeats when it has a move count */
+    pub stages: Vec<Stage>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ClockError {
+    NotRunning,
+    Flagged(usize),
+    InvalidTimeControl(String),
+}
+
+impl fmt::Display for ClockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClockError::NotRunning => write!(f, "the clock is not running"),
+            ClockError::Flagged(color) => write!(
+                f,
+                "{} ran out of time",
+                if *color == _WHITE_PIECE {
+                    "white"
+                } else {
+                    "black"
+                }
+            ),
+            ClockError::InvalidTimeControl(e) => write!(f, "invalid time control: {}", e),
+        }
+    }
+}
+
+impl TimeControl {
+    fn single(time: Duration, bonus: Bonus) -> TimeControl {
+        TimeControl {
+            stages: vec![Stage {
+                moves: None,
+                time,
+                bonus,
+            }],
+        }
+    }
+
+    pub fn sudden_death(time: Duration) -> TimeControl {
+        TimeControl::single(time, Bonus::None)
+    }
+
+    pub fn fischer(time: Duration, increment: Duration) -> TimeControl {
+        TimeControl::single(time, Bonus::Increment(increment))
+    }
+
+    pub fn bronstein(time: Duration, delay: Duration) -> TimeControl {
+        TimeControl::single(time, Bonus::Bronstein(delay))
+    }
+
+    pub fn simple_delay(time: Duration, delay: Duration) -> TimeControl {
+        TimeControl::single(time, Bonus::Delay(delay))
+    }
+
+    /* PGN TimeControl syntax in seconds, e.g. 300+2 or 40/5400+30:1800+30,
+     * with b or d for the delays, just as Display writes it */
+    pub fn parse(text: &str) -> Result<TimeControl, ClockError> {
+        let invalid = |why: &str| ClockError::InvalidTimeControl(format!("'{}': {}", text, why));
+        let number = |n: &str| {
+            n.trim()
+                .parse::<u64>()
+                .map_err(|_| invalid("expected a number"))
+        };
+
+        let mut stages = vec![];
+        for part in text.split(':') {
+            let (moves, rest) = match part.split_once('/') {
+                Some((moves, rest)) => (Some(number(moves)? as u32), rest),
+                None => (None, part),
+            };
+            let (time, bonus) = match rest.find(&['+', 'b', 'd'][..]) {
+                Some(at) => {
+                    let bonus = secs(number(&rest[at + 1..])?);
+                    let bonus = match &rest[at..at + 1] {
+                        "+" => Bonus::Increment(bonus),
+                        "b" => Bonus::Bronstein(bonus),
+                        _ => Bonus::Delay(bonus),
+                    };
+                    (number(&rest[..at])?, bonus)
+                }
+                None => (number(rest)?, Bonus::None),
+            };
+            if moves == Some(0) {
+                return Err(invalid("a stage needs at least one move"));
+            }
+            stages.push(Stage {
+                moves,
+                time: secs(time),
+                bonus,
+            });
+        }
+        if stages[..stages.len() - 1].iter().any(|s| s.moves.is_none()) {
+            return Err(invalid("only the last stage can last the rest of the game"));
+        }
+        Ok(TimeControl { stages })
+    }
+}
+
+impl fmt::Display for TimeControl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, stage) in self.stages.iter().enumerate() {
+            if i > 0 {
+                write!(f, ":")?;
+            }
+            if let Some(moves) = stage.moves {
+                write!(f, "{}/", moves)?;
+            }
+            write!(f, "{}", stage.time.as_secs())?;
+            match stage.bonus {
+                Bonus::None => (),
+                Bonus::Increment(d) => write!(f, "+{}", d.as_secs())?,
+                /* not part of the PGN syntax */
+                Bonus::Bronstein(d) => write!(f, "b{}", d.as_secs())?,
+                Bonus::Delay(d) => write!(f, "d{}", d.as_secs())?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Clock {
+    control: TimeControl,
+    source: Arc<dyn TimeSource>,
+    remaining: [Duration; 2],
+    /* per color: the stage and the moves made in it */
+    stage: [usize; 2],
+    moves: [u32; 2],
+    /* the side whose time is running and when its turn began */
+    running: Option<(usize, Duration)>,
+}
+
+impl Clock {
+    pub fn new(control: TimeControl, source: Arc<dyn TimeSource>) -> Result<Clock, ClockError> {
+        let time = match control.stages.first() {
+            Some(stage) => stage.time,
+            None => {
+                return Err(ClockError::InvalidTimeControl(
+                    "a time control needs a stage".to_string(),
+                ))
+            }
+        };
+        Ok(Clock {
+            control,
+            source,
+            remaining: [time, time],
+            stage: [0, 0],
+            moves: [0, 0],
+            running: None,
+        })
+    }
+
+    /* A clock part way through a game, after each side made so many moves
+     * and with so much time left; neither side's time is running */
+    pub fn resume(
+        control: TimeControl,
+        source: Arc<dyn TimeSource>,
+        made: [u32; 2],
+        remaining: [Duration; 2],
+    ) -> Result<Clock, ClockError> {
+        let mut clock = Clock::new(control, source)?;
+        for color in [_WHITE_PIECE, _BLACK_PIECE] {
+            for _ in 0..made[color] {
+                clock.moves[color] += 1;
+                if clock.control.stages[clock.stage[color]].moves == Some(clock.moves[color]) {
+                    clock.stage[color] =
+                        (clock.stage[color] + 1).min(clock.control.stages.len() - 1);
+                    clock.moves[color] = 0;
+                }
+            }
+        }
+        clock.remaining = remaining;
+        Ok(clock)
+    }
+
+    pub fn control(&self) -> &TimeControl {
+        &self.control
+    }
+
+    pub fn running(&self) -> Option<usize> {
+        self.running.map(|(color, _)| color)
+    }
+
+    fn bonus(&self, color: usize) -> Bonus {
+        self.control.stages[self.stage[color]].bonus
+    }
+
+    /* The part of the time spent on this move that counts against the clock */
+    fn used(&self, color: usize) -> (Duration, Duration) {
+        match self.running {
+            Some((c, since)) if c == color => {
+                let elapsed = self.source.now().saturating_sub(since);
+                let charged = match self.bonus(color) {
+                    Bonus::Delay(delay) => elapsed.saturating_sub(delay),
+                    _ => elapsed,
+                };
+                (elapsed, charged)
+            }
+            _ => (Duration::ZERO, Duration::ZERO),
+        }
+    }
+
+    pub fn remaining(&self, color: usize) -> Duration {
+        self.remaining[color].saturating_sub(self.used(color).1)
+    }
+
+    pub fn flagged(&self) -> Option<usize> {
+        self.running()
+            .filter(|c| self.remaining(*c) == Duration::ZERO)
+    }
+
+    pub fn start(&mut self, color: usize) {
+        self.running = Some((color, self.source.now()));
+    }
+
+    /* Charges the running side for the time used so far, without any bonus */
+    pub fn stop(&mut self) {
+        if let Some(color) = self.running() {
+            self.remaining[color] = self.remaining(color);
+            self.running = None;
+        }
+    }
+
+    /* Ends the running side's move and starts the opponent's clock */
+    pub fn press(&mut self) -> Result<(), ClockError> {
+        let color = self.running().ok_or(ClockError::NotRunning)?;
+        let (elapsed, charged) = self.used(color);
+        if charged >= self.remaining[color] {
+            self.remaining[color] = Duration::ZERO;
+            self.running = None;
+            return Err(ClockError::Flagged(color));
+        }
+
+        self.remaining[color] -= charged;
+        match self.bonus(color) {
+            Bonus::Increment(increment) => self.remaining[color] += increment,
+            Bonus::Bronstein(delay) => self.remaining[color] += elapsed.min(delay),
+            Bonus::None | Bonus::Delay(_) => (),
+        }
+
+        self.moves[color] += 1;
+        if self.control.stages[self.stage[color]].moves == Some(self.moves[color]) {
+            self.stage[color] = (self.stage[color] + 1).min(self.control.stages.len() - 1);
+            self.moves[color] = 0;
+            self.remaining[color] += self.control.stages[self.stage[color]].time;
+        }
+
+        self.start(1 - color);
+        Ok(())
+    }
+}
@@ -0,0 +1,345 @@
+/*
+ * Self-play games for tuning the evaluation. Every game starts with a few
+ * random moves and is then played by the engine against itself with a
+ * fixed number of nodes per move. The positions it searched are written
+ * out one per line with the search score and how the game ended:
+ *
+ *     <fen> | <centipawns for white> | <1.0, 0.5 or 0.0 for white>
+ *
+ * Positions in check, with a mate score, or where the engine captures or
+ * promotes are left out, since a static evaluation says little about them.
+ *
+ * Node limited searches do not depend on the clock, so the same seed
+ * gives the same games however many threads play them.
+ */
+
+use crate::engine::{self, SearchLimits, MATE_SCORE};
+use crate::evaluation::Weights;
+use crate::game::{Game, GameResult, Termination};
+use crate::matchplay::Adjudication;
+use crate::*;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seeded_random_numbers() {
+        let mut a = Rng::new(7);
+        let mut b = Rng::new(7);
+        let draws: Vec<u64> = (0..5).map(|_| a.next_u64()).collect();
+        assert_eq!(draws, (0..5).map(|_| b.next_u64()).collect::<Vec<u64>>());
+        assert_ne!(Rng::new(8).next_u64(), draws[0]);
+        assert!((0..100).all(|_| a.below(3) < 3));
+    }
+
+    #[test]
+    fn position_lines() {
+        let line = "4k3/8/8/8/8/8/4P3/4K3 w - - 0 1 | 112 | 0.5";
+        let position = DataPosition::parse(line).unwrap();
+        assert_eq!(position.score, 112);
+        assert_eq!(position.result, GameResult::Draw);
+        assert_eq!(position.white_score(), 0.5);
+        assert_eq!(position.to_string(), line);
+        assert!(DataPosition::parse("4k3/8/8/8/8/8/4P3/4K3 w - - 0 1 | x | 1.0").is_err());
+        assert!(DataPosition::parse("8/8 w | 0 | 1.0").is_err());
+    }
+
+    #[test]
+    fn same_seed_same_games() {
+        let config = DatagenConfig {
+            seed: 42,
+            games: 3,
+            nodes: 60,
+            random_plies: 6,
+            concurrency: 1,
+            adjudication: Adjudication {
+                max_plies: Some(12),
+                ..Adjudication::default()
+            },
+            ..DatagenConfig::default()
+        };
+        let first = generate(&config);
+        let second = generate(&DatagenConfig {
+            concurrency: 3,
+            ..config.clone()
+        });
+        assert_eq!(first.len(), 3);
+        assert_eq!(first, second);
+        assert_eq!(
+            first.iter().map(|g| g.index).collect::<Vec<usize>>(),
+            vec![0, 1, 2]
+        );
+        /* different random openings */
+        assert_ne!(first[0].opening, first[1].opening);
+        for g in &first {
+            assert_ne!(g.result, GameResult::Ongoing);
+            assert!(g.positions.iter().all(|p| p.result == g.result));
+        }
+        let other = generate(&DatagenConfig {
+            seed: 43,
+            games: 1,
+            ..config.clone()
+        });
+        assert_ne!(first[0].opening, other[0].opening);
+        assert_ne!(first[1].opening, other[0].opening);
+
+        /* without nodes to search the games still finish, unlabelled */
+        let blind = generate(&DatagenConfig {
+            nodes: 0,
+            ..config.clone()
+        });
+        for g in &blind {
+            assert_ne!(g.result, GameResult::Ongoing);
+            assert!(g.positions.is_empty());
+        }
+    }
+}
+
+/* xorshift64*, seeded through splitmix64 so that close seeds differ */
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        let mut z = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        Rng {
+            state: if z == 0 { 1 } else { z },
+        }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /* A number below n, which has to be above zero */
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    /* Between 0 and 1, 1 excluded */
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DataPosition {
+    pub fen: String,
+    /* centipawns from white's point of view */
+    pub score: i32,
+    /* how the game ended */
+    pub result: GameResult,
+}
+
+impl DataPosition {
+    pub fn parse(line: &str) -> Result<DataPosition, String> {
+        let fields: Vec<&str> = line.split('|').map(str::trim).collect();
+        if fields.len() != 3 {
+            return Err(format!("expected fen | score | result, got '{}'", line));
+        }
+        AlgebraicNotation::from_fen(fields[0]).map_err(|e| e.to_string())?;
+        let score = fields[1]
+            .parse()
+            .map_err(|_| format!("bad score '{}'", fields[1]))?;
+        let result = match fields[2] {
+            "1.0" | "1" | "1-0" => GameResult::WhiteWins,
+            "0.5" | "1/2-1/2" => GameResult::Draw,
+            "0.0" | "0" | "0-1" => GameResult::BlackWins,
+            other => return Err(format!("bad result '{}'", other)),
+        };
+        Ok(DataPosition {
+            fen: fields[0].to_string(),
+            score,
+            result,
+        })
+    }
+
+    /* 1 for a white win, 0.5 for a draw and 0 for a loss */
+    pub fn white_score(&self) -> f64 {
+        match self.result {
+            GameResult::WhiteWins => 1.0,
+            GameResult::BlackWins => 0.0,
+            _ => 0.5,
+        }
+    }
+}
+
+impl fmt::Display for DataPosition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} | {} | {:.1}",
+            self.fen,
+            self.score,
+            self.white_score()
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DatagenConfig {
+    pub seed: u64,
+    pub games: usize,
+    /* per move */
+    pub nodes: u64,
+    /* random moves before the engine takes over */
+    pub random_plies: usize,
+    pub concurrency: usize,
+    pub adjudication: Adjudication,
+    /* what the engine evaluates with */
+    pub weights: Weights,
+}
+
+impl Default for DatagenConfig {
+    fn default() -> Self {
+        DatagenConfig {
+            seed: 0,
+            games: 100,
+            nodes: 5000,
+            random_plies: 8,
+            concurrency: 1,
+            adjudication: Adjudication::default(),
+            weights: Weights::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SelfPlayGame {
+    pub index: usize,
+    /* the position after the random moves */
+    pub opening: String,
+    pub result: GameResult,
+    pub positions: Vec<DataPosition>,
+}
+
+/* Random moves from the start position, again from the start whenever
+ * they end the game */
+fn random_opening(rng: &mut Rng, plies: usize) -> AlgebraicNotation {
+    loop {
+        let mut notation = AlgebraicNotation::from_fen(fen::START_FEN).unwrap();
+        let mut finished = true;
+        for _ in 0..plies {
+            let moves = notation.legal_moves();
+            if moves.is_empty() {
+                finished = false;
+                break;
+            }
+            let chosen = &moves[rng.below(moves.len())];
+            notation.try_move(&chosen.notation).unwrap();
+        }
+        if finished && notation.has_legal_move() {
+            return notation;
+        }
+    }
+}
+
+pub fn play_game(config: &DatagenConfig, index: usize) -> SelfPlayGame {
+    /* seed + 1 should not replay the games of seed from the second on */
+    let base = Rng::new(config.seed).next_u64();
+    let mut rng = Rng::new(base.wrapping_add(index as u64));
+    let opening = random_opening(&mut rng, config.random_plies);
+    let limits = SearchLimits {
+        depth: 64,
+        nodes: Some(config.nodes),
+        time: None,
+    };
+
+    let mut game = Game::from_notation(opening.clone());
+    let mut scores = vec![];
+    /* positions with their score, until the result is known */
+    let mut kept: Vec<(String, i32)> = vec![];
+    while game.result() == GameResult::Ongoing {
+        if let Some(result) = config.adjudication.verdict(&game, &scores) {
+            game.finish(result, Termination::Adjudication);
+            break;
+        }
+        let notation = game.notation();
+        let result = engine::search_with(notation, &limits, &config.weights);
+        let chosen = match result.best_move {
+            Some(m) => m,
+            /* no search result to label, but the game still has to end */
+            None => match notation.legal_moves().into_iter().next() {
+                Some(m) => {
+                    scores.push(None);
+                    game.play(&m.uci).unwrap();
+                    continue;
+                }
+                None => break,
+            },
+        };
+        let white_score = if notation.turn() == _WHITE_PIECE {
+            result.score
+        } else {
+            -result.score
+        };
+        let quiet = !chosen.capture && chosen.promotion.is_none();
+        if quiet && !notation.in_check() && result.score.abs() < MATE_SCORE - 1000 {
+            kept.push((game.fen(), white_score));
+        }
+        scores.push(Some(white_score));
+        game.play(&chosen.uci).unwrap();
+    }
+
+    let result = game.result();
+    /* an unfinished game has no result to label its positions with */
+    if result == GameResult::Ongoing {
+        kept.clear();
+    }
+    SelfPlayGame {
+        index,
+        opening: opening.to_fen(),
+        result,
+        positions: kept
+            .into_iter()
+            .map(|(fen, score)| DataPosition { fen, score, result })
+            .collect(),
+    }
+}
+
+pub fn generate(config: &DatagenConfig) -> Vec<SelfPlayGame> {
+    let mut games = vec![];
+    generate_with(config, |g| games.push(g.clone()));
+    games
+}
+
+/* Calls back with every game in index order, as soon as it and all
+ * before it are done */
+pub fn generate_with(config: &DatagenConfig, on_game: impl FnMut(&SelfPlayGame) + Send) {
+    let next = AtomicUsize::new(0);
+    /* the next index to report, the games waiting for it, and the callback */
+    let pending: Mutex<(usize, BTreeMap<usize, SelfPlayGame>, _)> =
+        Mutex::new((0, BTreeMap::new(), on_game));
+
+    thread::scope(|scope| {
+        for _ in 0..config.concurrency.clamp(1, config.games.max(1)) {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::SeqCst);
+                if index >= config.games {
+                    return;
+                }
+                let played = play_game(config, index);
+                let mut guard = pending.lock().unwrap();
+                let (ref mut due, ref mut waiting, ref mut on_game) = *guard;
+                waiting.insert(index, played);
+                while let Some(g) = waiting.remove(due) {
+                    on_game(&g);
+                    *due += 1;
+                }
+            });
+        }
+    });
+}
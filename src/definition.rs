@@ -0,0 +1,285 @@
+/*
+ * Loading of board definitions.
+ *
+ * Two formats are understood. The original one lists the white pieces
+ * from the first rank and up, one rank per line, and mirrors them for
+ * black:
+ *
+ *     RNBQKBNR
+ *     PPPPPPPP
+ *
+ * The full format has exactly eight lines, rank 8 first, with white in
+ * upper case, black in lower case and empty squares as '.' (or as a
+ * digit counting empty squares, like in FEN):
+ *
+ *     ....k...
+ *     ....p...
+ *     8
+ *     ...
+ *
+ * Lines starting with '#' are comments.
+ */
+
+use crate::movegen::piece_letter;
+use crate::position::PositionState;
+use crate::*;
+use std::fs;
+use std::path::Path;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fen::START_FEN;
+
+    fn fen_of(board: Board) -> String {
+        let notat: AlgebraicNotation = Notation::new(board, _WHITE_PIECE);
+        notat.to_fen()
+    }
+
+    #[test]
+    fn default_definition() {
+        let board = Board::from_definition(DEFAULT_BOARD_DEFINITION).unwrap();
+        assert_eq!(fen_of(board), START_FEN);
+    }
+
+    #[test]
+    fn full_asymmetric_position() {
+        let definition = "# a rook endgame\n\
+                          ....k...\n\
+                          R.......\n\
+                          8\n\
+                          8\n\
+                          3p4\n\
+                          ........\n\
+                          .....PP.\n\
+                          ......K.\n";
+        let board = Board::from_definition(definition).unwrap();
+        assert_eq!(fen_of(board), "4k3/R7/8/8/3p4/8/5PP1/6K1 w - - 0 1");
+    }
+
+    #[test]
+    fn errors_have_positions() {
+        let mut board = Board::new();
+        assert_eq!(
+            board.load_definition("RNBQKBNR\nPPPXPPPP\n"),
+            Err(DefinitionError::Syntax {
+                line: 2,
+                column: 4,
+                message: "unknown piece 'X'".to_string()
+            })
+        );
+        assert_eq!(
+            board.load_definition("RNBQKBN\n"),
+            Err(DefinitionError::Syntax {
+                line: 1,
+                column: 8,
+                message: "rank has 7 squares, expected 8".to_string()
+            })
+        );
+
+        let two_kings = "k...k...\n8\n8\n8\n8\n8\n8\n4K3\n";
+        assert_eq!(
+            board.load_definition(two_kings),
+            Err(DefinitionError::Syntax {
+                line: 1,
+                column: 5,
+                message: "more than one black king".to_string()
+            })
+        );
+
+        let pawn_on_back_rank = "4k3\n8\n8\n8\n8\n8\n8\n4K2p\n";
+        assert_eq!(
+            board.load_definition(pawn_on_back_rank),
+            Err(DefinitionError::Syntax {
+                line: 8,
+                column: 4,
+                message: "pawns can not stand on the first or last rank".to_string()
+            })
+        );
+
+        match board.load_definition_file("no/such/board_definition") {
+            Err(DefinitionError::Io(_)) => (),
+            other => panic!("expected an io error, got {:?}", other),
+        }
+    }
+}
+
+pub const DEFAULT_BOARD_DEFINITION: &str = include_str!("../board_definition");
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DefinitionError {
+    Io(String),
+    Syntax {
+        line: usize,
+        column: usize,
+        message: String,
+    },
+}
+
+impl fmt::Display for DefinitionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DefinitionError::Io(e) => write!(f, "{}", e),
+            DefinitionError::Syntax {
+                line,
+                column,
+                message,
+            } => write!(f, "line {}, column {}: {}", line, column, message),
+        }
+    }
+}
+
+fn syntax(line: usize, column: usize, message: String) -> DefinitionError {
+    DefinitionError::Syntax {
+        line,
+        column,
+        message,
+    }
+}
+
+impl Board {
+    pub fn from_definition(definition: &str) -> Result<Board, DefinitionError> {
+        let mut board = Board::new();
+        board.load_definition(definition)?;
+        Ok(board)
+    }
+
+    pub fn load_definition_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), DefinitionError> {
+        let contents = fs::read_to_string(&path).map_err(|e| {
+            DefinitionError::Io(format!("could not read {}: {}", path.as_ref().display(), e))
+        })?;
+        self.load_definition(&contents)
+    }
+
+    /* Replaces the whole board; on error the board is left untouched */
+    pub fn load_definition(&mut self, definition: &str) -> Result<(), DefinitionError> {
+        let lines: Vec<(usize, &str)> = definition
+            .lines()
+            .enumerate()
+            .map(|(i, l)| (i + 1, l.trim_end()))
+            .filter(|(_, l)| !l.is_empty() && !l.starts_with('#'))
+            .collect();
+
+        let mirrored = lines.len() != 8;
+        if lines.is_empty() || mirrored && lines.len() > 4 {
+            let line = lines.get(4).map(|l| l.0).unwrap_or(1);
+            return Err(syntax(
+                line,
+                1,
+                format!(
+                    "expected 8 ranks, or at most 4 mirrored ones, found {} lines",
+                    lines.len()
+                ),
+            ));
+        }
+
+        let mut table: Vec<Vec<Option<Box<dyn PieceTrait>>>> = vec![vec![None; 8]; 8];
+        let mut kings = [false, false];
+
+        for (i, (line, text)) in lines.iter().enumerate() {
+            let row = if mirrored { i } else { 7 - i };
+            let mut column = 0;
+
+            for (c_i, c) in text.chars().enumerate() {
+                let position = c_i + 1;
+                if column >= 8 {
+                    return Err(syntax(
+                        *line,
+                        position,
+                        "rank has more than 8 squares".to_string(),
+                    ));
+                }
+                if let Some(empty) = c.to_digit(10) {
+                    if mirrored || empty == 0 || column + empty as usize > 8 {
+                        return Err(syntax(*line, position, format!("unexpected '{}'", c)));
+                    }
+                    column += empty as usize;
+                    continue;
+                }
+                if c == '.' && !mirrored {
+                    column += 1;
+                    continue;
+                }
+
+                let color = if c.is_lowercase() && !mirrored {
+                    _BLACK_PIECE
+                } else {
+                    _WHITE_PIECE
+                };
+                let mut piece = match self.pieces.get(&c.to_ascii_uppercase()) {
+                    Some(p) if c.is_uppercase() || !mirrored => p.clone(),
+                    _ => return Err(syntax(*line, position, format!("unknown piece '{}'", c))),
+                };
+                let letter = piece_letter(piece.as_ref());
+
+                let placements = if mirrored {
+                    vec![(row, _WHITE_PIECE), (7 - row, _BLACK_PIECE)]
+                } else {
+                    vec![(row, color)]
+                };
+                for (r, color) in placements {
+                    if letter == 'P' && (r == 0 || r == 7) {
+                        return Err(syntax(
+                            *line,
+                            position,
+                            "pawns can not stand on the first or last rank".to_string(),
+                        ));
+                    }
+                    if letter == 'K' {
+                        if kings[color] {
+                            return Err(syntax(
+                                *line,
+                                position,
+                                format!(
+                                    "more than one {} king",
+                                    if color == _WHITE_PIECE {
+                                        "white"
+                                    } else {
+                                        "black"
+                                    }
+                                ),
+                            ));
+                        }
+                        kings[color] = true;
+                    }
+
+                    piece.set_color(color);
+                    let mut placed = piece.clone();
+                    if !on_home_square(letter, color, (r, column)) {
+                        placed.movement(1);
+                    }
+                    table[r][column] = Some(placed);
+                }
+                column += 1;
+            }
+
+            if column != 8 {
+                return Err(syntax(
+                    *line,
+                    text.chars().count() + 1,
+                    format!("rank has {} squares, expected 8", column),
+                ));
+            }
+        }
+
+        self.table = table;
+        self.history = vec![];
+        self.state = PositionState::from_placement(self);
+        Ok(())
+    }
+}
+
+/* Pieces away from where they start have moved, which matters for pawns and castling */
+fn on_home_square(letter: char, color: usize, position: (usize, usize)) -> bool {
+    let (back_rank, pawn_rank) = if color == _WHITE_PIECE {
+        (0, 1)
+    } else {
+        (7, 6)
+    };
+    match letter {
+        'P' => position.0 == pawn_rank,
+        'K' => position == (back_rank, 4),
+        'R' => position == (back_rank, 0) || position == (back_rank, 7),
+        _ => position.0 == back_rank,
+    }
+}
@@ -0,0 +1,209 @@
+/*
+ * Text rendering of the board, for logs and the command line
+ */
+
+use crate::fen::fen_char;
+use crate::*;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn start_position() -> AlgebraicNotation {
+        let mut board = Board::new();
+        board.populate_board();
+        Notation::new(board, _WHITE_PIECE)
+    }
+
+    #[test]
+    fn letters_with_coordinates() {
+        let notat = start_position();
+        let text = notat.board().to_string();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 9);
+        assert_eq!(lines[0], "8  r  n  b  q  k  b  n  r ");
+        assert_eq!(lines[4], "4  .  .  .  .  .  .  .  . ");
+        assert_eq!(lines[7], "1  R  N  B  Q  K  B  N  R ");
+        assert_eq!(lines[8], "   a  b  c  d  e  f  g  h ");
+    }
+
+    #[test]
+    fn flipped_unicode_without_coordinates() {
+        let notat = start_position();
+        let options = DisplayOptions {
+            unicode: true,
+            flipped: true,
+            coordinates: false,
+            ..DisplayOptions::default()
+        };
+        let text = notat.board().display(options).to_string();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 8);
+        assert_eq!(lines[0], " ♖  ♘  ♗  ♔  ♕  ♗  ♘  ♖ ");
+        assert_eq!(lines[7], " ♜  ♞  ♝  ♚  ♛  ♝  ♞  ♜ ");
+    }
+
+    #[test]
+    fn last_move_highlighted() {
+        let mut notat = start_position();
+        notat.play_move("Nf3").unwrap();
+        let text = notat.board().to_string();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[5], "3  .  .  .  .  . [N] .  . ");
+        assert_eq!(lines[7], "1  R  N  B  Q  K  B [.] R ");
+
+        let options = DisplayOptions {
+            ansi_colors: true,
+            coordinates: false,
+            ..DisplayOptions::default()
+        };
+        let colored = notat.board().display(options).to_string();
+        assert!(colored.contains(HIGHLIGHT_SQUARE));
+        assert!(colored.ends_with(&format!("{}\n", RESET)));
+    }
+}
+
+const LIGHT_SQUARE: &str = "\x1b[48;5;180m";
+const DARK_SQUARE: &str = "\x1b[48;5;137m";
+const HIGHLIGHT_SQUARE: &str = "\x1b[48;5;143m";
+const WHITE_FOREGROUND: &str = "\x1b[1;38;5;231m";
+const BLACK_FOREGROUND: &str = "\x1b[1;38;5;16m";
+const RESET: &str = "\x1b[0m";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DisplayOptions {
+    /* figurines such as ♘ instead of letters */
+    pub unicode: bool,
+    /* black at the bottom */
+    pub flipped: bool,
+    pub coordinates: bool,
+    pub highlight_last_move: bool,
+    /* colored squares and pieces for terminals */
+    pub ansi_colors: bool,
+}
+
+impl Default for DisplayOptions {
+    fn default() -> Self {
+        DisplayOptions {
+            unicode: false,
+            flipped: false,
+            coordinates: true,
+            highlight_last_move: true,
+            ansi_colors: false,
+        }
+    }
+}
+
+pub struct BoardDisplay<'a> {
+    board: &'a Board,
+    options: DisplayOptions,
+}
+
+impl Board {
+    pub fn display(&self, options: DisplayOptions) -> BoardDisplay<'_> {
+        BoardDisplay {
+            board: self,
+            options,
+        }
+    }
+
+    fn last_move_squares(&self) -> Vec<(usize, usize)> {
+        match self.history.last() {
+            Some(m) => vec![
+                (m.before[0] as usize, m.before[1] as usize),
+                (m.after[0] as usize, m.after[1] as usize),
+            ],
+            None => vec![],
+        }
+    }
+}
+
+fn figurine(piece: &dyn PieceTrait) -> char {
+    match fen_char(piece) {
+        'K' => '♔',
+        'Q' => '♕',
+        'R' => '♖',
+        'B' => '♗',
+        'N' => '♘',
+        'P' => '♙',
+        'k' => '♚',
+        'q' => '♛',
+        'r' => '♜',
+        'b' => '♝',
+        'n' => '♞',
+        'p' => '♟',
+        other => other,
+    }
+}
+
+impl fmt::Display for BoardDisplay<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let options = &self.options;
+        let highlighted = if options.highlight_last_move {
+            self.board.last_move_squares()
+        } else {
+            vec![]
+        };
+        let rows: Vec<usize> = if options.flipped {
+            (0..8).collect()
+        } else {
+            (0..8).rev().collect()
+        };
+        let columns: Vec<usize> = if options.flipped {
+            (0..8).rev().collect()
+        } else {
+            (0..8).collect()
+        };
+
+        for r in rows.iter() {
+            if options.coordinates {
+                write!(f, "{} ", r + 1)?;
+            }
+            for c in columns.iter() {
+                let square = &self.board.table[*r][*c];
+                let symbol = match square {
+                    Some(p) if options.unicode => figurine(p.as_ref()),
+                    Some(p) => fen_char(p.as_ref()),
+                    None if options.unicode => '·',
+                    None => '.',
+                };
+                let marked = highlighted.contains(&(*r, *c));
+
+                if options.ansi_colors {
+                    let background = if marked {
+                        HIGHLIGHT_SQUARE
+                    } else if (r + c) % 2 == 0 {
+                        DARK_SQUARE
+                    } else {
+                        LIGHT_SQUARE
+                    };
+                    let foreground = match square {
+                        Some(p) if p.get_color() == _BLACK_PIECE => BLACK_FOREGROUND,
+                        _ => WHITE_FOREGROUND,
+                    };
+                    write!(f, "{}{} {} {}", background, foreground, symbol, RESET)?;
+                } else if marked {
+                    write!(f, "[{}]", symbol)?;
+                } else {
+                    write!(f, " {} ", symbol)?;
+                }
+            }
+            writeln!(f)?;
+        }
+
+        if options.coordinates {
+            write!(f, "  ")?;
+            for c in columns.iter() {
+                write!(f, " {} ", (b'a' + *c as u8) as char)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Board {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.display(DisplayOptions::default()))
+    }
+}
@@ -0,0 +1,320 @@
+/*
+ * A small alpha-beta engine on top of the legal move generator. Leaves
+ * are scored by the weighted evaluation, with the default weights or any
+ * others, such as those the tuner writes.
+ */
+
+use crate::evaluation::{Weights, DEFAULT_WEIGHTS};
+use crate::movegen::LegalMove;
+use crate::*;
+use std::time::{Duration, Instant};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn takes_hanging_queen() {
+        let mut board = Board::new();
+        board.table[0][4] = Some(Box::new(King {
+            has_moved: 1,
+            color: _WHITE_PIECE,
+        }));
+        board.table[0][0] = Some(Box::new(Rook {
+            has_moved: 1,
+            color: _WHITE_PIECE,
+        }));
+        board.table[7][7] = Some(Box::new(King {
+            has_moved: 1,
+            color: _BLACK_PIECE,
+        }));
+        board.table[4][0] = Some(Box::new(Queen {
+            has_moved: 1,
+            color: _BLACK_PIECE,
+        }));
+        let notat: AlgebraicNotation = Notation::new(board, _WHITE_PIECE);
+        let result = search(&notat, 2);
+        assert_eq!(result.best_move.unwrap().san, "Rxa5");
+        assert!(result.score >= 400);
+    }
+
+    #[test]
+    fn finds_mate_in_one() {
+        let mut board = Board::new();
+        board.table[0][6] = Some(Box::new(King {
+            has_moved: 1,
+            color: _WHITE_PIECE,
+        }));
+        board.table[0][0] = Some(Box::new(Rook {
+            has_moved: 1,
+            color: _WHITE_PIECE,
+        }));
+        board.table[7][6] = Some(Box::new(King {
+            has_moved: 1,
+            color: _BLACK_PIECE,
+        }));
+        for f in 5..8 {
+            board.table[6][f] = Some(Box::new(Pawn {
+                has_moved: 1,
+                color: _BLACK_PIECE,
+            }));
+        }
+        let notat: AlgebraicNotation = Notation::new(board, _WHITE_PIECE);
+        let result = search(&notat, 2);
+        assert_eq!(result.best_move.unwrap().uci, "a1a8");
+        assert!(result.score > MATE_SCORE - 10);
+    }
+
+    #[test]
+    fn stops_at_node_limit() {
+        let notat = AlgebraicNotation::from_fen(fen::START_FEN).unwrap();
+        let limits = SearchLimits {
+            depth: 10,
+            nodes: Some(500),
+            time: None,
+        };
+        let mut depths = vec![];
+        let result = iterative_search(&notat, &limits, &DEFAULT_WEIGHTS, |r| depths.push(r.depth));
+        assert_eq!(depths, vec![1, 2]);
+        assert_eq!(result.depth, 2);
+        assert!(result.best_move.is_some());
+        assert!(result.nodes <= 501);
+    }
+
+    #[test]
+    fn searches_with_other_weights() {
+        /* a knight worth more than a queen is taken first */
+        let notat = AlgebraicNotation::from_fen("4k3/8/8/1n3q2/8/3Q4/8/4K3 w - - 0 1").unwrap();
+        assert_eq!(search(&notat, 1).best_move.unwrap().san, "Qxf5");
+        let mut weights = Weights::default();
+        weights.set("material N", 1000);
+        let result = search_weighted(&notat, 1, &weights);
+        assert_eq!(result.best_move.unwrap().san, "Qxb5+");
+        let result = search_with(&notat, &SearchLimits::depth(1), &weights);
+        assert_eq!(result.best_move.unwrap().san, "Qxb5+");
+    }
+}
+
+pub const MATE_SCORE: i32 = 100_000;
+
+#[derive(Debug, Clone)]
+pub struct SearchResult {
+    pub best_move: Option<LegalMove>,
+    /* centipawns from the side to move's point of view */
+    pub score: i32,
+    pub nodes: u64,
+    /* the depth the score and move come from */
+    pub depth: usize,
+}
+
+pub fn piece_value(letter: char) -> i32 {
+    match letter {
+        'P' => 100,
+        'N' => 320,
+        'B' => 330,
+        'R' => 500,
+        'Q' => 900,
+        _ => 0,
+    }
+}
+
+/* From the side to move's point of view, with the default weights */
+pub fn evaluate(notation: &AlgebraicNotation) -> i32 {
+    evaluation::evaluate(notation, &DEFAULT_WEIGHTS)
+}
+
+/* How far a search may go. Iterative searches deepen one ply at a time
+ * up to depth, and stop early when the nodes or the time run out. */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SearchLimits {
+    pub depth: usize,
+    pub nodes: Option<u64>,
+    pub time: Option<Duration>,
+}
+
+impl SearchLimits {
+    pub fn depth(depth: usize) -> SearchLimits {
+        SearchLimits {
+            depth,
+            nodes: None,
+            time: None,
+        }
+    }
+}
+
+struct Budget<'a> {
+    weights: &'a Weights,
+    nodes: u64,
+    max_nodes: Option<u64>,
+    deadline: Option<Instant>,
+    stopped: bool,
+}
+
+impl Budget<'_> {
+    fn spend(&mut self) -> bool {
+        self.nodes += 1;
+        if self.max_nodes.is_some_and(|n| self.nodes > n)
+            || self.deadline.is_some_and(|d| Instant::now() >= d)
+        {
+            self.stopped = true;
+        }
+        !self.stopped
+    }
+}
+
+pub fn search(notation: &AlgebraicNotation, depth: usize) -> SearchResult {
+    search_weighted(notation, depth, &DEFAULT_WEIGHTS)
+}
+
+pub fn search_weighted(
+    notation: &AlgebraicNotation,
+    depth: usize,
+    weights: &Weights,
+) -> SearchResult {
+    let mut budget = Budget {
+        weights,
+        nodes: 0,
+        max_nodes: None,
+        deadline: None,
+        stopped: false,
+    };
+    root(notation, depth, &mut budget)
+}
+
+/* Deepens until the limits are reached, calling back after every finished
+ * depth. An unfinished depth only counts when no depth was finished. */
+pub fn iterative_search(
+    notation: &AlgebraicNotation,
+    limits: &SearchLimits,
+    weights: &Weights,
+    mut on_depth: impl FnMut(&SearchResult),
+) -> SearchResult {
+    let mut budget = Budget {
+        weights,
+        nodes: 0,
+        max_nodes: limits.nodes,
+        deadline: limits.time.map(|t| Instant::now() + t),
+        stopped: false,
+    };
+    let mut best: Option<SearchResult> = None;
+    for depth in 1..=limits.depth.max(1) {
+        let mut result = root(notation, depth, &mut budget);
+        if budget.stopped {
+            if let Some(mut best) = best {
+                best.nodes = budget.nodes;
+                return best;
+            }
+            result.depth = depth - 1;
+            return result;
+        }
+        on_depth(&result);
+        /* a forced mate will not get any better */
+        let mate = result.score.abs() > MATE_SCORE - 1000 || result.best_move.is_none();
+        best = Some(result);
+        if mate {
+            break;
+        }
+    }
+    best.unwrap()
+}
+
+pub fn search_with(
+    notation: &AlgebraicNotation,
+    limits: &SearchLimits,
+    weights: &Weights,
+) -> SearchResult {
+    iterative_search(notation, limits, weights, |_| ())
+}
+
+fn root(notation: &AlgebraicNotation, depth: usize, budget: &mut Budget) -> SearchResult {
+    let mut best_move = None;
+    let mut alpha = -MATE_SCORE - 1;
+    let beta = MATE_SCORE + 1;
+
+    let mut successors = notation.successors();
+    if successors.is_empty() {
+        budget.nodes += 1;
+        return SearchResult {
+            best_move: None,
+            score: if notation.in_check() { -MATE_SCORE } else { 0 },
+            nodes: budget.nodes,
+            depth,
+        };
+    }
+    order_moves(notation, &mut successors);
+
+    for (m, next) in successors {
+        let score = -negamax(&next, depth.max(1) - 1, 1, -beta, -alpha, budget);
+        if budget.stopped {
+            break;
+        }
+        if best_move.is_none() || score > alpha {
+            alpha = score;
+            best_move = Some(m);
+        }
+    }
+
+    SearchResult {
+        best_move,
+        score: alpha,
+        nodes: budget.nodes,
+        depth,
+    }
+}
+
+fn negamax(
+    notation: &AlgebraicNotation,
+    depth: usize,
+    ply: i32,
+    mut alpha: i32,
+    beta: i32,
+    budget: &mut Budget,
+) -> i32 {
+    if !budget.spend() {
+        return 0;
+    }
+    if depth == 0 {
+        return evaluation::evaluate(notation, budget.weights);
+    }
+
+    let mut successors = notation.successors();
+    if successors.is_empty() {
+        return if notation.in_check() {
+            -MATE_SCORE + ply
+        } else {
+            0
+        };
+    }
+    order_moves(notation, &mut successors);
+
+    for (_, next) in successors {
+        let score = -negamax(&next, depth - 1, ply + 1, -beta, -alpha, budget);
+        if budget.stopped {
+            return 0;
+        }
+        if score >= beta {
+            return beta;
+        }
+        if score > alpha {
+            alpha = score;
+        }
+    }
+    alpha
+}
+
+/* Captures that do not lose material and promotions first, best exchange
+ * first, then quiet moves, then losing captures. It makes the cutoffs come
+ * earlier. */
+fn order_moves(notation: &AlgebraicNotation, moves: &mut [(LegalMove, AlgebraicNotation)]) {
+    moves.sort_by_cached_key(|(m, _)| {
+        let exchange = notation.see(m);
+        let group = if exchange < 0 {
+            2
+        } else if m.capture || m.promotion.is_some() {
+            0
+        } else {
+            1
+        };
+        (group, -exchange)
+    });
+}
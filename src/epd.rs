@@ -0,0 +1,504 @@
+/*
+ * Extended Position Description and test suites.
+ *
+ * An EPD line is the first four fields of a FEN followed by operations,
+ * each an opcode with operands and ended by a semicolon:
+ *
+ *     r1b1k2r/ppppnppp/2n2q2/2b5/3NP3/2P1B3/PP3PPP/RN1QKB1R w KQkq - bm Nxc6; id "WAC.004";
+ *
+ * The runner searches every position of a suite and checks the answer
+ * against bm (best moves), am (moves to avoid) and dm (mate in so many
+ * moves). Suites that give points per move in c0, like STS does with
+ * c0 "Nf3=10, Nc3=5", are scored by those points.
+ */
+
+use crate::engine::{self, SearchLimits, SearchResult, MATE_SCORE};
+use crate::evaluation::Weights;
+use crate::movegen::LegalMove;
+use crate::*;
+use std::time::{Duration, Instant};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_operations() {
+        let record = EpdRecord::parse(
+            "2rr3k/pp3pp1/1nnqbN1p/3pN3/2pP4/2P3Q1/PPB4P/R4RK1 w - - bm Qg6; id \"WAC.001\"; c0 \"mate; quick\";",
+        )
+        .unwrap();
+        assert_eq!(record.id(), Some("WAC.001"));
+        assert_eq!(record.best_moves(), vec!["Qg6"]);
+        assert_eq!(record.comment(), Some("mate; quick"));
+        assert_eq!(
+            record.fen(),
+            "2rr3k/pp3pp1/1nnqbN1p/3pN3/2pP4/2P3Q1/PPB4P/R4RK1 w - - 0 1"
+        );
+        assert_eq!(
+            record.to_string(),
+            "2rr3k/pp3pp1/1nnqbN1p/3pN3/2pP4/2P3Q1/PPB4P/R4RK1 w - - bm Qg6; id \"WAC.001\"; c0 \"mate; quick\";"
+        );
+
+        let record =
+            EpdRecord::parse("8/8/8/8/8/8/8/K6k w - - am Kb2 Ka2; dm 3; hmvc 12;").unwrap();
+        assert_eq!(record.avoid_moves(), vec!["Kb2", "Ka2"]);
+        assert_eq!(record.mate_in(), Some(3));
+        assert_eq!(record.fen(), "8/8/8/8/8/8/8/K6k w - - 12 1");
+
+        /* any run of whitespace separates the fields */
+        let spaced = EpdRecord::parse("8/8/8/8/8/8/8/K6k  w\t-  -   bm Kb2;  id \"x\";").unwrap();
+        assert_eq!(spaced.fen(), "8/8/8/8/8/8/8/K6k w - - 0 1");
+        assert_eq!(spaced.best_moves(), vec!["Kb2"]);
+        assert_eq!(spaced.id(), Some("x"));
+        assert!(EpdRecord::parse("8/8/8/8/8/8/8/K6k  w - ").is_err());
+
+        assert!(EpdRecord::parse("8/8/8/8 w - -").is_err());
+        assert!(EpdRecord::parse("8/8/8/8/8/8/8/K6k w - - bm Kb2").is_err());
+        let suite =
+            parse_epd("# a comment\n\n8/8/8/8/8/8/8/K6k w - - id \"x\";\nbroken").unwrap_err();
+        assert_eq!(suite.line, 4);
+    }
+
+    #[test]
+    fn run_a_suite() {
+        let suite = parse_epd(
+            "6k1/5ppp/8/8/8/8/8/R5K1 w - - bm Ra8; id \"back rank\";
+6k1/5ppp/8/8/8/8/8/R5K1 w - - am Ra8; id \"avoid\";
+6k1/5ppp/8/8/8/8/8/R5K1 w - - dm 1; id \"mate\";
+4k3/8/8/3q4/8/8/8/3RK3 w - - c0 \"Rxd5=10, Kf2=1\"; id \"sts\";",
+        )
+        .unwrap();
+        let report = run_suite(&suite, &SearchLimits::depth(2), &Weights::default());
+        let verdicts: Vec<Option<bool>> = report.results.iter().map(|r| r.passed).collect();
+        assert_eq!(
+            verdicts,
+            vec![Some(true), Some(false), Some(true), Some(true)]
+        );
+        assert_eq!(report.results[0].played.as_deref(), Some("Ra8#"));
+        assert!(report.results[0].solve_time.is_some());
+        assert_eq!(report.passed(), 3);
+        assert_eq!(report.points(), (12, 13));
+        assert!(report
+            .to_string()
+            .ends_with("3 of 4 passed, 12 of 13 points\n"));
+
+        /* a record changed after parsing fails instead of stopping the run */
+        let mut broken = suite[0].clone();
+        broken.position = "8/8/8/8 w - -".to_string();
+        let report = run_suite(&[broken], &SearchLimits::depth(2), &Weights::default());
+        assert_eq!(report.results[0].passed, Some(false));
+        assert!(report.results[0].error.is_some());
+        assert!(report.to_string().starts_with("back rank        ERROR "));
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EpdError {
+    /* counting from 1, or 0 for a single record */
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for EpdError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.line > 0 {
+            write!(f, "line {}: {}", self.line, self.message)
+        } else {
+            write!(f, "{}", self.message)
+        }
+    }
+}
+
+fn error(message: &str) -> EpdError {
+    EpdError {
+        line: 0,
+        message: message.to_string(),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EpdRecord {
+    /* placement, side to move, castling and en passant, as in FEN */
+    pub position: String,
+    /* opcode and operands, in the order they were given */
+    pub operations: Vec<(String, Vec<String>)>,
+}
+
+/* Operands are separated by spaces, except inside double quotes */
+fn operands(text: &str) -> Result<Vec<String>, EpdError> {
+    let mut operands = vec![];
+    let mut chars = text.trim().chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            ' ' | '\t' => (),
+            '"' => {
+                let mut operand = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some(c) => operand.push(c),
+                        None => return Err(error("unterminated string")),
+                    }
+                }
+                operands.push(operand);
+            }
+            c => {
+                let mut operand = c.to_string();
+                while let Some(c) = chars.peek().filter(|c| !c.is_whitespace()) {
+                    operand.push(*c);
+                    chars.next();
+                }
+                operands.push(operand);
+            }
+        }
+    }
+    Ok(operands)
+}
+
+impl EpdRecord {
+    pub fn parse(line: &str) -> Result<EpdRecord, EpdError> {
+        let position: Vec<&str> = line.split_whitespace().take(4).collect();
+        if position.len() < 4 {
+            return Err(error("expected placement, side, castling and en passant"));
+        }
+        /* the operations are whatever follows the fourth field */
+        let mut rest = line;
+        for field in &position {
+            rest = &rest.trim_start()[field.len()..];
+        }
+        let mut rest = rest.trim();
+        let mut record = EpdRecord {
+            position: position.join(" "),
+            operations: vec![],
+        };
+        while !rest.is_empty() {
+            /* the semicolon ending an operation is the first one outside quotes */
+            let mut quoted = false;
+            let end = rest.char_indices().find(|(_, c)| {
+                if *c == '"' {
+                    quoted = !quoted;
+                }
+                *c == ';' && !quoted
+            });
+            let (operation, next) = match end {
+                Some((i, _)) => (&rest[..i], &rest[i + 1..]),
+                None => return Err(error("operation without a closing semicolon")),
+            };
+            let mut words = operation.trim().splitn(2, char::is_whitespace);
+            let opcode = words.next().unwrap_or("");
+            if opcode.is_empty() {
+                return Err(error("operation without an opcode"));
+            }
+            record
+                .operations
+                .push((opcode.to_string(), operands(words.next().unwrap_or(""))?));
+            rest = next.trim();
+        }
+        AlgebraicNotation::from_fen(&record.fen()).map_err(|e| error(&e.to_string()))?;
+        Ok(record)
+    }
+
+    pub fn operation(&self, opcode: &str) -> Option<&[String]> {
+        self.operations
+            .iter()
+            .find(|(op, _)| op == opcode)
+            .map(|(_, operands)| operands.as_slice())
+    }
+
+    fn first(&self, opcode: &str) -> Option<&str> {
+        self.operation(opcode)
+            .and_then(|o| o.first())
+            .map(|o| o.as_str())
+    }
+
+    /* The full FEN, with the move counters from hmvc and fmvn if given */
+    pub fn fen(&self) -> String {
+        format!(
+            "{} {} {}",
+            self.position,
+            self.first("hmvc").unwrap_or("0"),
+            self.first("fmvn").unwrap_or("1")
+        )
+    }
+
+    pub fn id(&self) -> Option<&str> {
+        self.first("id")
+    }
+
+    pub fn comment(&self) -> Option<&str> {
+        self.first("c0")
+    }
+
+    pub fn best_moves(&self) -> Vec<&str> {
+        self.operation("bm")
+            .map_or(vec![], |o| o.iter().map(|m| m.as_str()).collect())
+    }
+
+    pub fn avoid_moves(&self) -> Vec<&str> {
+        self.operation("am")
+            .map_or(vec![], |o| o.iter().map(|m| m.as_str()).collect())
+    }
+
+    pub fn mate_in(&self) -> Option<u32> {
+        self.first("dm").and_then(|n| n.parse().ok())
+    }
+
+    /* Points per move from a c0 like "Nf3=10, Nc3=5", as STS has them */
+    pub fn move_points(&self) -> Option<Vec<(&str, u32)>> {
+        let mut points = vec![];
+        for part in self.comment()?.split(',') {
+            let (san, score) = part.trim().split_once('=')?;
+            points.push((san.trim(), score.trim().parse().ok()?));
+        }
+        Some(points)
+    }
+}
+
+impl fmt::Display for EpdRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.position)?;
+        for (opcode, operands) in self.operations.iter() {
+            write!(f, " {}", opcode)?;
+            for operand in operands {
+                /* ids and comments are strings, the rest only when they must be */
+                let string = opcode == "id" || opcode.starts_with('c');
+                if string || operand.contains(char::is_whitespace) || operand.contains(';') {
+                    write!(f, " \"{}\"", operand)?;
+                } else {
+                    write!(f, " {}", operand)?;
+                }
+            }
+            write!(f, ";")?;
+        }
+        Ok(())
+    }
+}
+
+/* A whole file, skipping empty lines and lines starting with # */
+pub fn parse_epd(text: &str) -> Result<Vec<EpdRecord>, EpdError> {
+    let mut records = vec![];
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let record = EpdRecord::parse(line).map_err(|e| EpdError {
+            line: i + 1,
+            message: e.message,
+        })?;
+        records.push(record);
+    }
+    Ok(records)
+}
+
+#[derive(Debug, Clone)]
+pub struct PositionResult {
+    pub id: String,
+    /* None when the record has nothing to check */
+    pub passed: Option<bool>,
+    /* the engine's move in SAN */
+    pub played: Option<String>,
+    pub score: i32,
+    pub depth: usize,
+    pub nodes: u64,
+    /* when the engine settled on a right answer it kept */
+    pub solve_time: Option<Duration>,
+    pub time: Duration,
+    pub points: u32,
+    pub max_points: u32,
+    /* why the position could not be searched, which counts as a failure */
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SuiteReport {
+    pub results: Vec<PositionResult>,
+}
+
+impl SuiteReport {
+    pub fn passed(&self) -> usize {
+        self.results
+            .iter()
+            .filter(|r| r.passed == Some(true))
+            .count()
+    }
+
+    /* Points scored and the most there were to get */
+    pub fn points(&self) -> (u32, u32) {
+        self.results
+            .iter()
+            .fold((0, 0), |(p, m), r| (p + r.points, m + r.max_points))
+    }
+
+    pub fn total_time(&self) -> Duration {
+        self.results.iter().map(|r| r.time).sum()
+    }
+}
+
+impl fmt::Display for SuiteReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for r in self.results.iter() {
+            if let Some(error) = &r.error {
+                writeln!(f, "{:<16} ERROR {}", r.id, error)?;
+                continue;
+            }
+            writeln!(
+                f,
+                "{:<16} {:<5} {:<8} depth {:>2} {:>9} nodes {:>8.2}s{}",
+                r.id,
+                match r.passed {
+                    Some(true) => "pass",
+                    Some(false) => "FAIL",
+                    None => "-",
+                },
+                r.played.as_deref().unwrap_or("(none)"),
+                r.depth,
+                r.nodes,
+                r.time.as_secs_f64(),
+                match r.solve_time {
+                    Some(t) => format!(", solved in {:.2}s", t.as_secs_f64()),
+                    None => String::new(),
+                }
+            )?;
+        }
+        let (points, max_points) = self.points();
+        writeln!(
+            f,
+            "{} of {} passed, {} of {} points",
+            self.passed(),
+            self.results.len(),
+            points,
+            max_points
+        )
+    }
+}
+
+/* The legal move an operand names, if it names one */
+fn resolve(notation: &AlgebraicNotation, san: &str) -> Option<LegalMove> {
+    notation.resolve_move(san).ok()
+}
+
+/* Whether a search result answers the record, and the points for it */
+fn judge(
+    record: &EpdRecord,
+    notation: &AlgebraicNotation,
+    result: &SearchResult,
+) -> (Option<bool>, u32, u32) {
+    let played = result.best_move.as_ref().map(|m| m.uci.as_str());
+    let names = |moves: Vec<&str>| -> Vec<String> {
+        moves
+            .into_iter()
+            .filter_map(|m| resolve(notation, m))
+            .map(|m| m.uci)
+            .collect()
+    };
+
+    let mut verdict = None;
+    let mut check = |ok: bool| verdict = Some(verdict.unwrap_or(true) && ok);
+    let best = names(record.best_moves());
+    if record.operation("bm").is_some() {
+        check(played.is_some_and(|p| best.iter().any(|b| b == p)));
+    }
+    let avoid = names(record.avoid_moves());
+    if record.operation("am").is_some() {
+        check(played.is_some_and(|p| !avoid.iter().any(|a| a == p)));
+    }
+    if let Some(n) = record.mate_in() {
+        check(result.score >= MATE_SCORE - (2 * n as i32 - 1));
+    }
+
+    match record.move_points() {
+        Some(table) => {
+            let max_points = table.iter().map(|(_, p)| *p).max().unwrap_or(0);
+            let points = table
+                .iter()
+                .find(|(san, _)| resolve(notation, san).map(|m| m.uci).as_deref() == played)
+                .map_or(0, |(_, p)| *p);
+            if verdict.is_none() {
+                verdict = Some(points == max_points);
+            }
+            (verdict, points, max_points)
+        }
+        None => {
+            let points = (verdict == Some(true)) as u32;
+            (verdict, points, verdict.is_some() as u32)
+        }
+    }
+}
+
+pub fn run_position(
+    record: &EpdRecord,
+    index: usize,
+    limits: &SearchLimits,
+    weights: &Weights,
+) -> PositionResult {
+    let id = record
+        .id()
+        .map(|id| id.to_string())
+        .unwrap_or_else(|| format!("#{}", index + 1));
+    let started = Instant::now();
+    /* records can be built without parse, so the position may be broken */
+    let notation = match AlgebraicNotation::from_fen(&record.fen()) {
+        Ok(notation) => notation,
+        Err(e) => {
+            return PositionResult {
+                id,
+                passed: Some(false),
+                played: None,
+                score: 0,
+                depth: 0,
+                nodes: 0,
+                solve_time: None,
+                time: started.elapsed(),
+                points: 0,
+                max_points: 0,
+                error: Some(e.to_string()),
+            }
+        }
+    };
+
+    let mut solve_time = None;
+    let result = engine::iterative_search(&notation, limits, weights, |result| {
+        if judge(record, &notation, result).0 == Some(true) {
+            solve_time = solve_time.or_else(|| Some(started.elapsed()));
+        } else {
+            solve_time = None;
+        }
+    });
+    let time = started.elapsed();
+
+    let (passed, points, max_points) = judge(record, &notation, &result);
+    if passed != Some(true) {
+        solve_time = None;
+    }
+    PositionResult {
+        id,
+        passed,
+        played: result.best_move.as_ref().map(|m| {
+            notation
+                .legal_moves()
+                .into_iter()
+                .find(|l| l.uci == m.uci)
+                .map_or(m.san.clone(), |l| l.san)
+        }),
+        score: result.score,
+        depth: result.depth,
+        nodes: result.nodes,
+        solve_time,
+        time,
+        points,
+        max_points,
+        error: None,
+    }
+}
+
+pub fn run_suite(records: &[EpdRecord], limits: &SearchLimits, weights: &Weights) -> SuiteReport {
+    SuiteReport {
+        results: records
+            .iter()
+            .enumerate()
+            .map(|(i, record)| run_position(record, i, limits, weights))
+            .collect(),
+    }
+}
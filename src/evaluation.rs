@@ -0,0 +1,401 @@
+/*
+ * The evaluation as a vector of weights. A position is turned into
+ * features, counts for white minus counts for black, and the evaluation
+ * is their sum weighted by the vector. The features are
+ *
+ *     material        one per piece type but the king
+ *     piece-square    one per piece type and square, mirrored for black
+ *     mobility        squares a knight, bishop, rook or queen attacks
+ *                     that are not held by its own side
+ *     king safety     attacks of enemy pieces on the squares around the
+ *                     king, and own pawns on the two ranks in front of it
+ *
+ * Being linear in the weights lets the tuner compute the features once.
+ */
+
+use crate::movegen::piece_letter;
+use crate::*;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn notation(fen: &str) -> AlgebraicNotation {
+        AlgebraicNotation::from_fen(fen).unwrap()
+    }
+
+    #[test]
+    fn default_is_material() {
+        let start = notation(fen::START_FEN);
+        assert_eq!(evaluate(&start, &Weights::default()), 0);
+        let up_a_knight = notation("4k3/8/8/8/8/8/8/1N2K3 b - - 0 1");
+        assert_eq!(evaluate(&up_a_knight, &Weights::default()), -320);
+    }
+
+    #[test]
+    fn feature_counts() {
+        let position = notation("4k3/8/8/8/8/8/5PPP/1N4K1 w - - 0 1");
+        let features = features(&position);
+        let value = |name: &str| {
+            let index = Weights::names().iter().position(|n| n == name).unwrap();
+            features
+                .iter()
+                .find(|(i, _)| *i == index)
+                .map_or(0, |(_, v)| *v)
+        };
+        assert_eq!(value("material P"), 3);
+        assert_eq!(value("material N"), 1);
+        assert_eq!(value("pst N b1"), 1);
+        assert_eq!(value("pst K g1"), 1);
+        /* black's king on e8 counts as one on e1 */
+        assert_eq!(value("pst K e1"), -1);
+        /* a3, c3 and d2 */
+        assert_eq!(value("mobility N"), 3);
+        assert_eq!(value("king shield"), 3);
+        assert_eq!(value("king attack"), 0);
+
+        let mut weights = Weights::default();
+        weights.set("king shield", 10);
+        weights.set("mobility N", 4);
+        assert_eq!(evaluate(&position, &weights), 620 + 30 + 12);
+    }
+
+    #[test]
+    fn weights_text() {
+        let mut weights = Weights::default();
+        weights.set("pst Q d4", -7);
+        let text = weights.to_string();
+        assert!(text.contains("pst Q d4 -7\n"));
+        assert_eq!(Weights::parse(&text), Ok(weights.clone()));
+        assert!(Weights::parse("material X 5").is_err());
+        /* names left out keep their default */
+        assert_eq!(
+            Weights::parse("pst Q d4 -7").unwrap().values[..],
+            weights.values[..]
+        );
+    }
+
+    #[test]
+    fn quiet_leaf() {
+        /* the knight on d5 is lost to the pawn */
+        let position = notation("4k3/8/2p5/3N4/8/8/8/4K3 b - - 0 1");
+        let (score, leaf) = quiescence(&position, &Weights::default());
+        assert_eq!(score, 100);
+        assert!(leaf.board().table[4][3]
+            .as_ref()
+            .is_some_and(|p| p.get_color() == _BLACK_PIECE));
+    }
+}
+
+const KINDS: [char; 6] = ['P', 'N', 'B', 'R', 'Q', 'K'];
+
+pub const MATERIAL: usize = 0;
+pub const PIECE_SQUARE: usize = MATERIAL + 5;
+pub const MOBILITY: usize = PIECE_SQUARE + 6 * 64;
+pub const KING_ATTACK: usize = MOBILITY + 4;
+pub const KING_SHIELD: usize = KING_ATTACK + 1;
+pub const WEIGHT_COUNT: usize = KING_SHIELD + 1;
+
+/* Only the material has a value to begin with; the rest is for tuning */
+const fn default_values() -> [i32; WEIGHT_COUNT] {
+    let mut values = [0; WEIGHT_COUNT];
+    values[MATERIAL] = 100;
+    values[MATERIAL + 1] = 320;
+    values[MATERIAL + 2] = 330;
+    values[MATERIAL + 3] = 500;
+    values[MATERIAL + 4] = 900;
+    values
+}
+
+pub const DEFAULT_WEIGHTS: Weights = Weights {
+    values: default_values(),
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Weights {
+    pub values: [i32; WEIGHT_COUNT],
+}
+
+impl Default for Weights {
+    fn default() -> Self {
+        DEFAULT_WEIGHTS
+    }
+}
+
+impl Weights {
+    /* One per weight, like "material N", "pst K g1" or "mobility Q" */
+    pub fn names() -> Vec<String> {
+        let mut names = vec![];
+        for kind in &KINDS[..5] {
+            names.push(format!("material {}", kind));
+        }
+        for kind in &KINDS {
+            for square in 0..64 {
+                names.push(format!(
+                    "pst {} {}",
+                    kind,
+                    movegen::square_name((square / 8, square % 8))
+                ));
+            }
+        }
+        for kind in &KINDS[1..5] {
+            names.push(format!("mobility {}", kind));
+        }
+        names.push("king attack".to_string());
+        names.push("king shield".to_string());
+        names
+    }
+
+    pub fn get(&self, name: &str) -> Option<i32> {
+        let index = Weights::names().iter().position(|n| n == name)?;
+        Some(self.values[index])
+    }
+
+    /* Panics on a name that is not in names() */
+    pub fn set(&mut self, name: &str, value: i32) {
+        let index = Weights::names().iter().position(|n| n == name).unwrap();
+        self.values[index] = value;
+    }
+
+    /* "name value" lines as Display writes them, over the defaults */
+    pub fn parse(text: &str) -> Result<Weights, String> {
+        let names = Weights::names();
+        let mut weights = Weights::default();
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (name, value) = match line.rsplit_once(' ') {
+                Some(split) => split,
+                None => return Err(format!("expected a name and a value, got '{}'", line)),
+            };
+            let index = names
+                .iter()
+                .position(|n| n == name.trim())
+                .ok_or_else(|| format!("unknown weight '{}'", name.trim()))?;
+            weights.values[index] = value
+                .parse()
+                .map_err(|_| format!("bad value in '{}'", line))?;
+        }
+        Ok(weights)
+    }
+
+    /* A file as the tuner writes it */
+    pub fn open(path: &str) -> Result<Weights, String> {
+        std::fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|text| Weights::parse(&text))
+            .map_err(|e| format!("{}: {}", path, e))
+    }
+}
+
+impl fmt::Display for Weights {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, value) in Weights::names().iter().zip(self.values.iter()) {
+            writeln!(f, "{} {}", name, value)?;
+        }
+        Ok(())
+    }
+}
+
+const KNIGHT_STEPS: [(i32, i32); 8] = [
+    (1, 2),
+    (2, 1),
+    (2, -1),
+    (1, -2),
+    (-1, -2),
+    (-2, -1),
+    (-2, 1),
+    (-1, 2),
+];
+const DIAGONALS: [(i32, i32); 4] = [(1, 1), (1, -1), (-1, 1), (-1, -1)];
+const LINES: [(i32, i32); 4] = [(1, 0), (-1, 0), (0, 1), (0, -1)];
+
+fn on_board(r: i32, c: i32) -> bool {
+    (0..8).contains(&r) && (0..8).contains(&c)
+}
+
+/* The squares a knight, bishop, rook or queen on square attacks */
+fn attacked_squares(board: &Board, kind: char, square: (usize, usize)) -> Vec<(usize, usize)> {
+    let (r, c) = (square.0 as i32, square.1 as i32);
+    let mut squares = vec![];
+    if kind == 'N' {
+        for (dr, dc) in KNIGHT_STEPS.iter() {
+            if on_board(r + dr, c + dc) {
+                squares.push(((r + dr) as usize, (c + dc) as usize));
+            }
+        }
+        return squares;
+    }
+    let directions: Vec<&(i32, i32)> = match kind {
+        'B' => DIAGONALS.iter().collect(),
+        'R' => LINES.iter().collect(),
+        _ => DIAGONALS.iter().chain(LINES.iter()).collect(),
+    };
+    for (dr, dc) in directions {
+        let (mut tr, mut tc) = (r + dr, c + dc);
+        while on_board(tr, tc) {
+            squares.push((tr as usize, tc as usize));
+            if board.table[tr as usize][tc as usize].is_some() {
+                break;
+            }
+            tr += dr;
+            tc += dc;
+        }
+    }
+    squares
+}
+
+/* Calls add with every feature index and value, white minus black */
+fn accumulate(board: &Board, mut add: impl FnMut(usize, i32)) {
+    let mut kings = [None; 2];
+    for r in 0..8 {
+        for c in 0..8 {
+            if let Some(p) = &board.table[r][c] {
+                if piece_letter(p.as_ref()) == 'K' {
+                    kings[p.get_color()] = Some((r, c));
+                }
+            }
+        }
+    }
+    let near = |king: Option<(usize, usize)>, square: (usize, usize)| {
+        king.is_some_and(|k: (usize, usize)| {
+            (k.0 as i32 - square.0 as i32).abs() <= 1 && (k.1 as i32 - square.1 as i32).abs() <= 1
+        })
+    };
+
+    for r in 0..8 {
+        for c in 0..8 {
+            let piece = match &board.table[r][c] {
+                Some(p) => p,
+                None => continue,
+            };
+            let color = piece.get_color();
+            let sign = if color == _WHITE_PIECE { 1 } else { -1 };
+            let kind = piece_letter(piece.as_ref());
+            let index = match KINDS.iter().position(|k| *k == kind) {
+                Some(index) => index,
+                None => continue,
+            };
+            if index < 5 {
+                add(MATERIAL + index, sign);
+            }
+            let relative_row = if color == _WHITE_PIECE { r } else { 7 - r };
+            add(PIECE_SQUARE + index * 64 + relative_row * 8 + c, sign);
+
+            if (1..5).contains(&index) {
+                let mut mobility = 0;
+                let mut king_attacks = 0;
+                for square in attacked_squares(board, kind, (r, c)) {
+                    let own = board.table[square.0][square.1]
+                        .as_ref()
+                        .is_some_and(|p| p.get_color() == color);
+                    if !own {
+                        mobility += 1;
+                    }
+                    if near(kings[1 - color], square) {
+                        king_attacks += 1;
+                    }
+                }
+                add(MOBILITY + index - 1, sign * mobility);
+                /* counted for the side whose king it is */
+                add(KING_ATTACK, -sign * king_attacks);
+            }
+
+            if index == 5 {
+                let forward: i32 = if color == _WHITE_PIECE { 1 } else { -1 };
+                let mut shield = 0;
+                for step in 1..=2 {
+                    let tr = r as i32 + forward * step;
+                    for tc in c as i32 - 1..=c as i32 + 1 {
+                        if !on_board(tr, tc) {
+                            continue;
+                        }
+                        let pawn =
+                            board.table[tr as usize][tc as usize]
+                                .as_ref()
+                                .is_some_and(|p| {
+                                    p.get_color() == color && piece_letter(p.as_ref()) == 'P'
+                                });
+                        if pawn {
+                            shield += 1;
+                        }
+                    }
+                }
+                add(KING_SHIELD, sign * shield);
+            }
+        }
+    }
+}
+
+/* Sparse feature values of the position, white minus black */
+pub fn features(notation: &AlgebraicNotation) -> Vec<(usize, i32)> {
+    let mut dense = [0; WEIGHT_COUNT];
+    accumulate(notation.board(), |index, value| dense[index] += value);
+    dense
+        .iter()
+        .enumerate()
+        .filter(|(_, value)| **value != 0)
+        .map(|(index, value)| (index, *value))
+        .collect()
+}
+
+/* From the side to move's point of view */
+pub fn evaluate(notation: &AlgebraicNotation, weights: &Weights) -> i32 {
+    let mut score = 0;
+    accumulate(notation.board(), |index, value| {
+        score += weights.values[index] * value
+    });
+    if notation.turn() == _WHITE_PIECE {
+        score
+    } else {
+        -score
+    }
+}
+
+/* How many captures deep quiescence looks at most */
+const QUIESCENCE_PLIES: usize = 8;
+
+/* The score once the captures that do not lose material are played out,
+ * from the side to move's point of view, and the quiet position it
+ * comes from */
+pub fn quiescence(notation: &AlgebraicNotation, weights: &Weights) -> (i32, AlgebraicNotation) {
+    quiesce(
+        notation,
+        weights,
+        -engine::MATE_SCORE,
+        engine::MATE_SCORE,
+        QUIESCENCE_PLIES,
+    )
+}
+
+fn quiesce(
+    notation: &AlgebraicNotation,
+    weights: &Weights,
+    mut alpha: i32,
+    beta: i32,
+    plies: usize,
+) -> (i32, AlgebraicNotation) {
+    let stand_pat = evaluate(notation, weights);
+    let mut best = (stand_pat, notation.clone());
+    if stand_pat >= beta || plies == 0 {
+        return best;
+    }
+    alpha = alpha.max(stand_pat);
+
+    for (m, next) in notation.successors() {
+        if !(m.capture || m.promotion.is_some()) || notation.see(&m) < 0 {
+            continue;
+        }
+        let (score, leaf) = quiesce(&next, weights, -beta, -alpha, plies - 1);
+        let score = -score;
+        if score > best.0 {
+            best = (score, leaf);
+        }
+        if score >= beta {
+            break;
+        }
+        alpha = alpha.max(score);
+    }
+    best
+}
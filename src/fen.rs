@@ -0,0 +1,216 @@
+/*
+ * Forsyth-Edwards Notation (FEN) export and import
+ */
+
+use crate::definition::DefinitionError;
+use crate::movegen::{parse_square, piece_letter, square_name};
+use crate::position::CastlingRights;
+use crate::*;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn start_position_fen() {
+        let mut board = Board::new();
+        board.populate_board();
+        let mut notat: AlgebraicNotation = Notation::new(board, _WHITE_PIECE);
+        assert_eq!(notat.to_fen(), START_FEN);
+        notat.play_move("e4").unwrap();
+        assert_eq!(
+            notat.to_fen(),
+            "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1"
+        );
+        notat.play_move("Nf6").unwrap();
+        notat.play_move("Ke2").unwrap();
+        assert_eq!(
+            notat.to_fen(),
+            "rnbqkb1r/pppppppp/5n2/8/4P3/8/PPPPKPPP/RNBQ1BNR b kq - 2 2"
+        );
+    }
+
+    #[test]
+    fn fen_round_trip() {
+        for fen in [
+            START_FEN,
+            "rnbqkbnr/ppp1pppp/8/3pP3/8/8/PPPP1PPP/RNBQKBNR w Qk d6 0 2",
+            "r3k2r/8/8/8/8/8/8/R3K2R b K - 0 1",
+            "8/8/3k4/8/8/8/1p4K1/8 b - - 0 1",
+            "4k3/8/8/8/8/8/8/R3K3 w Q - 17 42",
+        ] {
+            assert_eq!(AlgebraicNotation::from_fen(fen).unwrap().to_fen(), fen);
+        }
+
+        let mut notat = AlgebraicNotation::from_fen("r3k2r/8/8/8/8/8/8/R3K2R b K - 0 1").unwrap();
+        assert_eq!(notat.play_move("O-O"), Err(MoveError::CastlingNotAllowed));
+        assert!(notat.play_move("Kd8").is_ok());
+        assert!(notat.play_move("O-O").is_ok());
+    }
+
+    #[test]
+    fn fen_errors() {
+        assert!(AlgebraicNotation::from_fen("").is_err());
+        assert!(AlgebraicNotation::from_fen("8/8/8/8/8/8/8 w - - 0 1").is_err());
+        assert_eq!(
+            AlgebraicNotation::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNX w KQkq - 0 1")
+                .err(),
+            Some(FenError("rank 1, column 8: unknown piece 'X'".to_string()))
+        );
+        assert!(AlgebraicNotation::from_fen("4k3/8/8/8/8/8/8/4K3 x - - 0 1").is_err());
+        assert!(AlgebraicNotation::from_fen("4k3/8/8/8/8/8/8/4K3 w K - 0 1").is_err());
+        assert!(AlgebraicNotation::from_fen("4k3/8/8/8/8/8/8/4K3 w - e3 0 1").is_err());
+    }
+}
+
+pub const START_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FenError(pub String);
+
+impl fmt::Display for FenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid FEN: {}", self.0)
+    }
+}
+
+pub fn fen_char(piece: &dyn PieceTrait) -> char {
+    let c = piece_letter(piece);
+    if piece.get_color() == _BLACK_PIECE {
+        c.to_ascii_lowercase()
+    } else {
+        c
+    }
+}
+
+impl AlgebraicNotation {
+    pub fn from_fen(fen: &str) -> Result<AlgebraicNotation, FenError> {
+        let fields: Vec<&str> = fen.split_whitespace().collect();
+        if fields.len() < 4 || fields.len() > 6 {
+            return Err(FenError(format!(
+                "expected 4 to 6 fields, found {}",
+                fields.len()
+            )));
+        }
+
+        let ranks: Vec<&str> = fields[0].split('/').collect();
+        if ranks.len() != 8 {
+            return Err(FenError(format!("expected 8 ranks, found {}", ranks.len())));
+        }
+        let board = Board::from_definition(&ranks.join("\n")).map_err(|e| match e {
+            DefinitionError::Syntax {
+                line,
+                column,
+                message,
+            } => FenError(format!("rank {}, column {}: {}", 9 - line, column, message)),
+            DefinitionError::Io(e) => FenError(e),
+        })?;
+
+        let turn = match fields[1] {
+            "w" => _WHITE_PIECE,
+            "b" => _BLACK_PIECE,
+            other => return Err(FenError(format!("unknown side to move '{}'", other))),
+        };
+        let mut notation: AlgebraicNotation = Notation::new(board, turn);
+
+        let castling = fields[2];
+        if castling != "-" && (castling.is_empty() || !castling.chars().all(|c| "KQkq".contains(c)))
+        {
+            return Err(FenError(format!("unknown castling rights '{}'", castling)));
+        }
+        /* placement alone gives every right that is still possible */
+        let possible = notation.board.state.castling;
+        let mut rights = CastlingRights::none();
+        for (color, kingside, right) in [
+            (_WHITE_PIECE, true, 'K'),
+            (_WHITE_PIECE, false, 'Q'),
+            (_BLACK_PIECE, true, 'k'),
+            (_BLACK_PIECE, false, 'q'),
+        ] {
+            if castling.contains(right) {
+                if !possible.allows(color, kingside) {
+                    return Err(FenError(format!(
+                        "castling right {} without king and rook in place",
+                        right
+                    )));
+                }
+                rights.set(color, kingside, true);
+            }
+        }
+        notation.board.state.castling = rights;
+
+        if fields[3] != "-" {
+            let target = parse_square(fields[3])
+                .ok_or_else(|| FenError(format!("bad en passant square '{}'", fields[3])))?;
+            /* the pawn that just made a double step, and where it came from */
+            let (pawn_row, from_row, color) = if turn == _WHITE_PIECE {
+                (4, 6, _BLACK_PIECE)
+            } else {
+                (3, 1, _WHITE_PIECE)
+            };
+            match &notation.board.table[pawn_row][target.1] {
+                Some(p)
+                    if target.0 == (pawn_row + from_row) / 2
+                        && piece_letter(p.as_ref()) == 'P'
+                        && p.get_color() == color => {}
+                _ => {
+                    return Err(FenError(format!(
+                        "no pawn can be captured en passant on {}",
+                        fields[3]
+                    )))
+                }
+            }
+            notation.board.state.en_passant = Some(target);
+        }
+
+        let counter = |i: usize, default: u32| match fields.get(i) {
+            Some(n) => n
+                .parse::<u32>()
+                .map_err(|_| FenError(format!("bad move counter '{}'", n))),
+            None => Ok(default),
+        };
+        notation.board.state.halfmove_clock = counter(4, 0)?;
+        /* some writers use 0, which is no move at all */
+        notation.board.state.fullmove_number = counter(5, 1)?.max(1);
+
+        Ok(notation)
+    }
+
+    pub fn to_fen(&self) -> String {
+        let mut placement: Vec<String> = vec![];
+        for row in self.board.table.iter().rev() {
+            let mut rank = String::new();
+            let mut empty = 0;
+            for square in row {
+                match square {
+                    Some(p) => {
+                        if empty > 0 {
+                            rank.push_str(&empty.to_string());
+                            empty = 0;
+                        }
+                        rank.push(fen_char(p.as_ref()));
+                    }
+                    None => empty += 1,
+                }
+            }
+            if empty > 0 {
+                rank.push_str(&empty.to_string());
+            }
+            placement.push(rank);
+        }
+
+        let state = &self.board.state;
+        format!(
+            "{} {} {} {} {} {}",
+            placement.join("/"),
+            if self.turn == _WHITE_PIECE { "w" } else { "b" },
+            state.castling.to_fen(),
+            state
+                .en_passant
+                .map(square_name)
+                .unwrap_or_else(|| "-".to_string()),
+            state.halfmove_clock,
+            state.fullmove_number
+        )
+    }
+}
@@ -0,0 +1,492 @@
+/*
+ * A game of chess: the position, the moves played so far and how it ended
+ */
+
+use crate::clock::{Clock, ClockError};
+use crate::fen::START_FEN;
+use crate::movegen::{piece_letter, LegalMove};
+use crate::*;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{ManualTime, TimeControl};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn fools_mate_and_undo() {
+        let mut game = Game::new();
+        for m in ["f3", "e5", "g4"] {
+            game.play(m).unwrap();
+        }
+        assert_eq!(game.play("Qh4").unwrap().san, "Qh4#");
+        assert_eq!(game.result(), GameResult::BlackWins);
+        assert_eq!(game.termination(), Some(Termination::Checkmate));
+        assert_eq!(game.play("a3"), Err(MoveError::GameOver));
+
+        assert_eq!(game.undo().unwrap().san, "Qh4#");
+        assert_eq!(game.result(), GameResult::Ongoing);
+        assert_eq!(game.moves().len(), 3);
+        assert_eq!(game.notation().turn(), _BLACK_PIECE);
+    }
+
+    #[test]
+    fn pgn_export() {
+        let mut game = Game::new();
+        game.white = "Gustaf".to_string();
+        for m in ["e4", "e5", "Nf3"] {
+            game.play(m).unwrap();
+        }
+        game.resign(_BLACK_PIECE);
+        let pgn = game.to_pgn();
+        assert!(pgn.contains("[White \"Gustaf\"]"));
+        assert!(pgn.contains("[Result \"1-0\"]"));
+        assert!(pgn.ends_with("1. e4 e5 2. Nf3 1-0\n"));
+    }
+
+    #[test]
+    fn draw_by_agreement() {
+        let mut game = Game::new();
+        assert!(!game.offer_draw(_WHITE_PIECE));
+        game.play("e4").unwrap();
+        game.play("e5").unwrap();
+        /* black moved instead of accepting */
+        assert_eq!(game.draw_offer(), None);
+        assert!(!game.offer_draw(_BLACK_PIECE));
+        assert!(game.offer_draw(_WHITE_PIECE));
+        assert_eq!(game.result(), GameResult::Draw);
+    }
+
+    #[test]
+    fn time_forfeit() {
+        let time = Arc::new(ManualTime::default());
+        let mut game = Game::new();
+        game.set_clock(
+            Clock::new(
+                TimeControl::fischer(Duration::from_secs(60), Duration::from_secs(1)),
+                time.clone(),
+            )
+            .unwrap(),
+        );
+        game.play("e4").unwrap();
+        time.advance(Duration::from_secs(30));
+        game.play("e5").unwrap();
+        assert_eq!(
+            game.clock().unwrap().remaining(_BLACK_PIECE),
+            Duration::from_secs(31)
+        );
+
+        /* white still has the minute and the second from its first move */
+        time.advance(Duration::from_secs(61));
+        assert_eq!(game.play("Nf3"), Err(MoveError::GameOver));
+        assert_eq!(game.result(), GameResult::BlackWins);
+        assert_eq!(game.termination(), Some(Termination::TimeForfeit));
+    }
+
+    #[test]
+    fn flag_against_lone_king_draws() {
+        let time = Arc::new(ManualTime::default());
+        let notation = AlgebraicNotation::from_fen("4k3/8/8/8/8/8/8/2B1K3 w - - 0 1").unwrap();
+        let mut game = Game::from_notation(notation);
+        game.set_clock(
+            Clock::new(
+                TimeControl::sudden_death(Duration::from_secs(1)),
+                time.clone(),
+            )
+            .unwrap(),
+        );
+        time.advance(Duration::from_secs(2));
+        assert!(game.check_flag());
+        /* black only has a king and can not win */
+        assert_eq!(game.result(), GameResult::Draw);
+        assert_eq!(game.termination(), Some(Termination::TimeForfeit));
+
+        let board = AlgebraicNotation::from_fen("4k3/8/8/8/8/8/8/1NB1K3 w - - 0 1").unwrap();
+        assert!(has_mating_material(board.board(), _WHITE_PIECE));
+        let board = AlgebraicNotation::from_fen("4k3/8/2b5/8/8/8/8/1B2K3 w - - 0 1").unwrap();
+        assert!(!has_mating_material(board.board(), _WHITE_PIECE));
+        let board = AlgebraicNotation::from_fen("4k3/8/3b4/8/8/8/8/1B2K3 w - - 0 1").unwrap();
+        assert!(has_mating_material(board.board(), _WHITE_PIECE));
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GameResult {
+    Ongoing,
+    WhiteWins,
+    BlackWins,
+    Draw,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Termination {
+    Checkmate,
+    Stalemate,
+    Resignation,
+    Agreement,
+    TimeForfeit,
+    /* decided by whoever runs the game, e.g. an engine match */
+    Adjudication,
+}
+
+impl Termination {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Termination::Checkmate => "checkmate",
+            Termination::Stalemate => "stalemate",
+            Termination::Resignation => "resignation",
+            Termination::Agreement => "agreement",
+            Termination::TimeForfeit => "time_forfeit",
+            Termination::Adjudication => "adjudication",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Termination> {
+        [
+            Termination::Checkmate,
+            Termination::Stalemate,
+            Termination::Resignation,
+            Termination::Agreement,
+            Termination::TimeForfeit,
+            Termination::Adjudication,
+        ]
+        .iter()
+        .find(|t| t.name() == name)
+        .copied()
+    }
+}
+
+impl GameResult {
+    pub fn from_pgn(token: &str) -> Option<GameResult> {
+        [
+            GameResult::Ongoing,
+            GameResult::WhiteWins,
+            GameResult::BlackWins,
+            GameResult::Draw,
+        ]
+        .iter()
+        .find(|r| r.pgn() == token)
+        .copied()
+    }
+
+    pub fn win_for(color: usize) -> GameResult {
+        if color == _WHITE_PIECE {
+            GameResult::WhiteWins
+        } else {
+            GameResult::BlackWins
+        }
+    }
+
+    pub fn pgn(&self) -> &'static str {
+        match self {
+            GameResult::Ongoing => "*",
+            GameResult::WhiteWins => "1-0",
+            GameResult::BlackWins => "0-1",
+            GameResult::Draw => "1/2-1/2",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Game {
+    notation: AlgebraicNotation,
+    start: AlgebraicNotation,
+    snapshots: Vec<AlgebraicNotation>,
+    moves: Vec<LegalMove>,
+    result: GameResult,
+    termination: Option<Termination>,
+    draw_offer: Option<usize>,
+    clock: Option<Clock>,
+    pub white: String,
+    pub black: String,
+    pub event: String,
+    pub round: String,
+}
+
+impl Default for Game {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Game {
+    pub fn new() -> Game {
+        let mut board = Board::new();
+        board.populate_board();
+        Game::from_notation(Notation::new(board, _WHITE_PIECE))
+    }
+
+    pub fn from_notation(notation: AlgebraicNotation) -> Game {
+        Game {
+            start: notation.clone(),
+            notation,
+            snapshots: vec![],
+            moves: vec![],
+            result: GameResult::Ongoing,
+            termination: None,
+            draw_offer: None,
+            clock: None,
+            white: "?".to_string(),
+            black: "?".to_string(),
+            event: "Casual game".to_string(),
+            round: "-".to_string(),
+        }
+    }
+
+    pub fn notation(&self) -> &AlgebraicNotation {
+        &self.notation
+    }
+
+    /* The position the game started from */
+    pub fn start(&self) -> &AlgebraicNotation {
+        &self.start
+    }
+
+    pub fn moves(&self) -> &[LegalMove] {
+        &self.moves
+    }
+
+    pub fn result(&self) -> GameResult {
+        self.result
+    }
+
+    pub fn termination(&self) -> Option<Termination> {
+        self.termination
+    }
+
+    pub fn draw_offer(&self) -> Option<usize> {
+        self.draw_offer
+    }
+
+    pub(crate) fn set_draw_offer(&mut self, color: Option<usize>) {
+        self.draw_offer = color;
+    }
+
+    pub fn clock(&self) -> Option<&Clock> {
+        self.clock.as_ref()
+    }
+
+    /* The clock starts for the side to move right away */
+    pub fn set_clock(&mut self, mut clock: Clock) {
+        if self.result == GameResult::Ongoing {
+            clock.start(self.notation.turn());
+        }
+        self.clock = Some(clock);
+    }
+
+    /* Ends the game when the side to move has run out of time */
+    pub fn check_flag(&mut self) -> bool {
+        match self.clock.as_ref().and_then(|c| c.flagged()) {
+            Some(color) if self.result == GameResult::Ongoing => {
+                self.flag_fell(color);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /* Losing on time is a draw when the opponent could never mate */
+    fn flag_fell(&mut self, color: usize) {
+        let opponent = other_color(color);
+        let result = if has_mating_material(self.notation.board(), opponent) {
+            GameResult::win_for(opponent)
+        } else {
+            GameResult::Draw
+        };
+        self.finish(result, Termination::TimeForfeit);
+    }
+
+    pub fn fen(&self) -> String {
+        self.notation.to_fen()
+    }
+
+    pub fn play(&mut self, input: &str) -> Result<LegalMove, MoveError> {
+        if self.check_flag() || self.result != GameResult::Ongoing {
+            return Err(MoveError::GameOver);
+        }
+
+        let before = self.notation.clone();
+        let played = self.notation.play_move(input)?;
+        self.snapshots.push(before);
+        self.moves.push(played.clone());
+
+        if let Some(Err(ClockError::Flagged(color))) = self.clock.as_mut().map(|c| c.press()) {
+            self.flag_fell(color);
+            return Ok(played);
+        }
+
+        /* moving instead of accepting declines the opponent's offer */
+        if self.draw_offer == Some(self.notation.turn()) {
+            self.draw_offer = None;
+        }
+
+        if !self.notation.has_legal_move() {
+            if self.notation.in_check() {
+                self.finish(
+                    GameResult::win_for(other_color(self.notation.turn())),
+                    Termination::Checkmate,
+                );
+            } else {
+                self.finish(GameResult::Draw, Termination::Stalemate);
+            }
+        }
+
+        Ok(played)
+    }
+
+    /* Takes back the last move, reopening the game if it had ended */
+    pub fn undo(&mut self) -> Option<LegalMove> {
+        let previous = self.snapshots.pop()?;
+        self.notation = previous;
+        self.result = GameResult::Ongoing;
+        self.termination = None;
+        self.draw_offer = None;
+        /* the time already used stays used */
+        let turn = self.notation.turn();
+        if let Some(clock) = self.clock.as_mut() {
+            clock.stop();
+            clock.start(turn);
+        }
+        self.moves.pop()
+    }
+
+    pub fn resign(&mut self, color: usize) {
+        if self.result == GameResult::Ongoing {
+            self.finish(
+                GameResult::win_for(other_color(color)),
+                Termination::Resignation,
+            );
+        }
+    }
+
+    /* Returns true when the offer accepts one already made by the opponent */
+    pub fn offer_draw(&mut self, color: usize) -> bool {
+        if self.result != GameResult::Ongoing {
+            return false;
+        }
+        if self.draw_offer == Some(other_color(color)) {
+            self.finish(GameResult::Draw, Termination::Agreement);
+            return true;
+        }
+        self.draw_offer = Some(color);
+        false
+    }
+
+    pub(crate) fn finish(&mut self, result: GameResult, termination: Termination) {
+        self.result = result;
+        self.termination = Some(termination);
+        self.draw_offer = None;
+        if let Some(clock) = self.clock.as_mut() {
+            clock.stop();
+        }
+    }
+
+    pub fn to_pgn(&self) -> String {
+        let mut pgn = String::new();
+        let start_fen = self.start.to_fen();
+        let tags = [
+            ("Event", self.event.as_str()),
+            ("Site", "?"),
+            ("Date", "????.??.??"),
+            ("Round", self.round.as_str()),
+            ("White", self.white.as_str()),
+            ("Black", self.black.as_str()),
+            ("Result", self.result.pgn()),
+        ];
+        for (tag, value) in tags.iter() {
+            pgn.push_str(&format!("[{} \"{}\"]\n", tag, value.replace('"', "'")));
+        }
+        if start_fen != START_FEN {
+            pgn.push_str("[SetUp \"1\"]\n");
+            pgn.push_str(&format!("[FEN \"{}\"]\n", start_fen));
+        }
+        pgn.push('\n');
+
+        let mut tokens: Vec<String> = vec![];
+        let mut turn = self.start.turn();
+        let mut fullmove: usize = start_fen
+            .split(' ')
+            .next_back()
+            .and_then(|n| n.parse().ok())
+            .unwrap_or(1);
+        for (i, m) in self.moves.iter().enumerate() {
+            if turn == _WHITE_PIECE {
+                tokens.push(format!("{}.", fullmove));
+            } else if i == 0 {
+                tokens.push(format!("{}...", fullmove));
+            }
+            tokens.push(m.san.clone());
+            if turn == _BLACK_PIECE {
+                fullmove += 1;
+            }
+            turn = other_color(turn);
+        }
+        tokens.push(self.result.pgn().to_string());
+
+        let mut line = String::new();
+        for token in tokens {
+            if !line.is_empty() && line.len() + 1 + token.len() > 79 {
+                pgn.push_str(&line);
+                pgn.push('\n');
+                line.clear();
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(&token);
+        }
+        pgn.push_str(&line);
+        pgn.push('\n');
+        pgn
+    }
+}
+
+/*
+ * Whether color could mate with any help from the opponent. Lone kings,
+ * a single knight against a bare king and bishops all on squares of one
+ * color can not, unless an opposing piece could block the king's escape
+ * squares of the other color.
+ */
+pub fn has_mating_material(board: &Board, color: usize) -> bool {
+    let mut own = vec![];
+    let mut theirs = vec![];
+    for (r, row) in board.table.iter().enumerate() {
+        for (c, square) in row.iter().enumerate() {
+            if let Some(p) = square {
+                let letter = piece_letter(p.as_ref());
+                if letter == 'K' {
+                    continue;
+                }
+                let entry = (letter, (r + c) % 2);
+                if p.get_color() == color {
+                    own.push(entry);
+                } else {
+                    theirs.push(entry);
+                }
+            }
+        }
+    }
+
+    if own.iter().any(|(l, _)| matches!(l, 'P' | 'R' | 'Q')) {
+        return true;
+    }
+    match own.as_slice() {
+        [] => false,
+        [('N', _)] => !theirs.is_empty(),
+        _ if own.iter().all(|(l, _)| *l == 'B') => {
+            let shade = own[0].1;
+            own.iter().any(|(_, s)| *s != shade)
+                || theirs.iter().any(|(l, s)| *l != 'B' || *s != shade)
+        }
+        _ => true,
+    }
+}
+
+pub fn other_color(color: usize) -> usize {
+    if color == _WHITE_PIECE {
+        _BLACK_PIECE
+    } else {
+        _WHITE_PIECE
+    }
+}
@@ -3,15 +3,46 @@
  * @author Gustaf Haglund <ghaglu@kth.se>
  */
 
+/* tests are kept at the top of each module */
+#![allow(clippy::items_after_test_module)]
+
 use array2d::Array2D;
 use std::cmp;
 use std::collections::HashMap;
 use std::fmt;
-use std::fs;
 
 pub const _WHITE_PIECE: usize = 0;
 pub const _BLACK_PIECE: usize = 1;
 
+pub mod attacks;
+pub mod book;
+pub mod bookbuild;
+pub mod clock;
+pub mod datagen;
+pub mod definition;
+pub mod display;
+pub mod engine;
+pub mod epd;
+pub mod evaluation;
+pub mod fen;
+pub mod game;
+pub mod matchplay;
+pub mod mcts;
+pub mod motifs;
+pub mod movegen;
+pub mod pgn;
+pub mod pins;
+pub mod position;
+pub mod problem;
+pub mod protocol;
+pub mod puzzle;
+pub mod see;
+pub mod serialization;
+pub mod server;
+pub mod sprt;
+pub mod tablebase;
+pub mod tuning;
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -76,7 +107,7 @@ mod tests {
             }])
         ); /* double step */
 
-        board.table[(2)][(0)] = Some(Box::new(xa)); /* insert pawn into table. human-wise: (3,1) => (2,0) in table */
+        board.table[2][0] = Some(Box::new(xa)); /* insert pawn into table. human-wise: (3,1) => (2,0) in table */
         assert_eq!(
             xa.theory_valid_move(&board, false, (2, 1), (4, 1)).ok(),
             Some(false)
@@ -93,12 +124,12 @@ mod tests {
         );
 
         /* capture moves */
-        board.table[(2)][(1)] = Some(Box::new(q));
+        board.table[2][1] = Some(Box::new(q));
         assert_eq!(
             xb.theory_valid_move(&board, true, (2, 1), (3, 2)).ok(),
             Some(true)
         );
-        board.table[(2)][(0)] = Some(Box::new(q));
+        board.table[2][0] = Some(Box::new(q));
         assert_eq!(
             xc.theory_valid_move(&board, true, (2, 2), (3, 1)).ok(),
             Some(true)
@@ -118,6 +149,12 @@ mod tests {
         };
         board.table[3][2] = Some(Box::new(xd));
         board.table[3][3] = Some(Box::new(xe));
+        // only right after the double step
+        assert_eq!(
+            xe.theory_valid_move(&board, true, (4, 4), (3, 3)).ok(),
+            Some(false)
+        );
+        board.state.en_passant = Some((2, 2));
         // black should capture the white
         assert_eq!(
             xe.theory_valid_move(&board, true, (4, 4), (3, 3)).err(),
@@ -139,6 +176,7 @@ mod tests {
         };
         board.table[4][4] = Some(Box::new(xf));
         board.table[4][5] = Some(Box::new(xg));
+        board.state.en_passant = Some((5, 5));
         assert_eq!(
             xf.theory_valid_move(&board, true, (5, 5), (6, 6)).err(),
             Some(vec![AdjustPiece {
@@ -486,12 +524,12 @@ mod tests {
             color: _WHITE_PIECE,
         }));
         let mut algnot: AlgebraicNotation = Notation::new(board, _WHITE_PIECE);
-        assert_eq!(algnot.do_move("e8Q"), true);
+        assert!(algnot.do_move("e8Q"));
         assert_eq!(
             algnot.board.table[7][4].as_ref().unwrap().get_identity(),
             "Q".to_string()
         );
-        assert_eq!(algnot.board.table[6][4].as_ref().is_some(), false);
+        assert!(algnot.board.table[6][4].as_ref().is_none());
     }
 
     #[test]
@@ -506,8 +544,8 @@ mod tests {
             color: _BLACK_PIECE,
         }));
         let mut algnot: AlgebraicNotation = Notation::new(board, _WHITE_PIECE);
-        assert_eq!(algnot.do_move("Ke3f3"), false);
-        assert_eq!(algnot.do_move("Ke3xd3"), true);
+        assert!(!algnot.do_move("Ke3f3"));
+        assert!(algnot.do_move("Ke3xd3"));
     }
 
     #[test]
@@ -528,7 +566,7 @@ mod tests {
         let mut algnot_king: AlgebraicNotation = Notation::new(board2, _WHITE_PIECE);
 
         /* Queenside castling */
-        assert_eq!(algnot_queen.do_move("0-0-0"), true);
+        assert!(algnot_queen.do_move("0-0-0"));
         assert_eq!(
             algnot_queen.board.table[0][2]
                 .as_ref()
@@ -545,7 +583,7 @@ mod tests {
         );
 
         /* Kingside castling */
-        assert_eq!(algnot_king.do_move("0-0"), true);
+        assert!(algnot_king.do_move("0-0"));
         assert_eq!(
             algnot_king.board.table[0][6]
                 .as_ref()
@@ -574,13 +612,6 @@ struct Move {
     column: u32
 }*/
 
-#[derive(Debug, Clone)]
-pub struct Piece {
-    //position: Position,
-    identity: String,
-    color: usize,
-}
-
 #[derive(Debug, Clone, Copy)]
 pub struct Pawn {
     color: usize,
@@ -627,6 +658,7 @@ pub struct Board {
     //pub table: Array2D<Option<Box<dyn PieceTrait>>>,
     pub table: Vec<Vec<Option<Box<dyn PieceTrait>>>>,
     history: Vec<Move>,
+    state: position::PositionState,
     short_pieces: Vec<char>,
     pieces: HashMap<char, Box<dyn PieceTrait>>,
 }
@@ -648,7 +680,8 @@ pub struct AdjustPiece {
     remove_piece: bool,
 }
 
-pub trait PieceTrait: PieceClone + PieceCommon {
+/* Send so that boards can be handed to other threads, e.g. by the server */
+pub trait PieceTrait: PieceClone + PieceCommon + Send {
     fn theory_valid_move(
         &self,
         board: &Board,
@@ -723,22 +756,24 @@ impl PieceTrait for Pawn {
             }
 
             if cmp::max(new_position.0, position.0) - cmp::min(new_position.0, position.0) == 1
-                && new_position.1 - position.1 == 0
+                && new_position.1 == position.1
             {
                 return Ok(true);
             }
 
             /* Double step */
+            let start_rank = if self.get_color() == _WHITE_PIECE {
+                2
+            } else {
+                7
+            };
             if cmp::max(new_position.0, position.0) - cmp::min(new_position.0, position.0) == 2
-                && new_position.1 - position.1 == 0
-                && board.table[position.0 - 1][position.1 - 1]
-                    .clone()
-                    .unwrap()
-                    .movement(0)
-                    == 0
+                && new_position.1 == position.1
+                && position.0 == start_rank
             {
                 /* Checking the step before */
-                if board.table[new_position.0 - 2][new_position.1 - 1].is_some() {
+                if board.table[(position.0 + new_position.0) / 2 - 1][new_position.1 - 1].is_some()
+                {
                     return Ok(false);
                 }
 
@@ -768,51 +803,22 @@ impl PieceTrait for Pawn {
                 return Ok(true);
             }
 
-            /* ... Passant? */
-            /* Known bug: Will accept two single steps as one double, I think..? */
-            if position.0 == 0 {
-                return Ok(false);
-            }
-            if self.get_color() == _BLACK_PIECE {
-                if position.1 <= 1 {
-                    return Ok(false);
-                }
-                let check_w_black_piece = board.table[position.0 - 1][position.1 - 2].clone();
-                if position.0 == 4
-                    && check_w_black_piece.is_some()
-                    && &check_w_black_piece.as_ref().unwrap().get_color() == &_WHITE_PIECE
-                    && check_w_black_piece.unwrap().movement(0) == 2
-                    && cmp::max(new_position.0, position.0) - cmp::min(new_position.0, position.0)
-                        == 1
-                    && cmp::max(new_position.1, position.1) - cmp::min(new_position.1, position.1)
-                        == 1
-                {
-                    //return Ok(true);
-                    return Err(vec![AdjustPiece {
-                        piece: (position.0 - 1, position.1 - 2),
-                        increase_movement: 0,
-                        remove_piece: true,
-                    }]);
-                }
+            /* En passant, onto the square the opponent's pawn skipped on the last ply */
+            let passant_rank = if self.get_color() == _WHITE_PIECE {
+                6
             } else {
-                let check_w_white_piece = board.table[position.0 - 1][position.1].clone();
-                if position.0 == 5
-                    && position.1 - 1 <= 7
-                    && check_w_white_piece.is_some()
-                    && &check_w_white_piece.as_ref().unwrap().get_color() == &_BLACK_PIECE
-                    && check_w_white_piece.unwrap().movement(0) == 2
-                    && cmp::max(new_position.0, position.0) - cmp::min(new_position.0, position.0)
-                        == 1
-                    && cmp::max(new_position.1, position.1) - cmp::min(new_position.1, position.1)
-                        == 1
-                {
-                    //return Ok(true);
-                    return Err(vec![AdjustPiece {
-                        piece: (position.0 - 1, position.1),
-                        increase_movement: 0,
-                        remove_piece: true,
-                    }]);
-                }
+                3
+            };
+            if board.state.en_passant == Some((new_position.0 - 1, new_position.1 - 1))
+                && new_position.0 == passant_rank
+                && cmp::max(new_position.0, position.0) - cmp::min(new_position.0, position.0) == 1
+                && cmp::max(new_position.1, position.1) - cmp::min(new_position.1, position.1) == 1
+            {
+                return Err(vec![AdjustPiece {
+                    piece: (position.0 - 1, new_position.1 - 1),
+                    increase_movement: 0,
+                    remove_piece: true,
+                }]);
             }
         }
 
@@ -870,7 +876,7 @@ impl PieceTrait for Rook {
                 }
             }
         } else {
-            for i in (new_position.0 - 1..position.0 - 1).rev() {
+            for i in (new_position.0..position.0 - 1).rev() {
                 if (board.table[i][position.1 - 1]).is_some() {
                     return Ok(false);
                 }
@@ -892,15 +898,13 @@ impl PieceTrait for Rook {
                     remove_piece: false,
                 }]);
             }
-        } else {
-            if !board.table[new_position.0 - 1][new_position.1 - 1].is_some() {
-                //return Ok(true);
-                return Err(vec![AdjustPiece {
-                    piece: (new_position.0 - 1, new_position.1 - 1),
-                    increase_movement: 1,
-                    remove_piece: false,
-                }]);
-            }
+        } else if board.table[new_position.0 - 1][new_position.1 - 1].is_none() {
+            //return Ok(true);
+            return Err(vec![AdjustPiece {
+                piece: (new_position.0 - 1, new_position.1 - 1),
+                increase_movement: 1,
+                remove_piece: false,
+            }]);
         }
         Ok(false)
     }
@@ -919,28 +923,28 @@ impl PieceTrait for Knight {
         position: (usize, usize),
         new_position: (usize, usize),
     ) -> Result<bool, Vec<AdjustPiece>> {
-        if cmp::max(position.1, new_position.1) - cmp::min(position.1, new_position.1) == 1 {
-            if cmp::max(position.0, new_position.0) - cmp::min(position.0, new_position.0) == 2 {
-                if board.table[new_position.0 - 1][new_position.1 - 1].is_some() && !capture {
-                    return Ok(false);
-                }
-                return Ok(true);
+        if cmp::max(position.1, new_position.1) - cmp::min(position.1, new_position.1) == 1
+            && cmp::max(position.0, new_position.0) - cmp::min(position.0, new_position.0) == 2
+        {
+            if board.table[new_position.0 - 1][new_position.1 - 1].is_some() && !capture {
+                return Ok(false);
             }
+            return Ok(true);
         }
 
-        if cmp::max(position.1, new_position.1) - cmp::min(position.1, new_position.1) == 2 {
-            if cmp::max(position.0, new_position.0) - cmp::min(position.0, new_position.0) == 1 {
-                if board.table[new_position.0 - 1][new_position.1 - 1].is_some() && !capture {
-                    return Ok(false);
-                }
-                return Ok(true);
+        if cmp::max(position.1, new_position.1) - cmp::min(position.1, new_position.1) == 2
+            && cmp::max(position.0, new_position.0) - cmp::min(position.0, new_position.0) == 1
+        {
+            if board.table[new_position.0 - 1][new_position.1 - 1].is_some() && !capture {
+                return Ok(false);
             }
+            return Ok(true);
         }
 
         Ok(false)
     }
     fn get_identity(&self) -> String {
-        "K".to_string()
+        "N".to_string()
     }
 }
 impl_piececommon!(Knight);
@@ -954,12 +958,12 @@ impl PieceTrait for Bishop {
         new_position: (usize, usize),
     ) -> Result<bool, Vec<AdjustPiece>> {
         if board.table[new_position.0 - 1][new_position.1 - 1].is_some() && !capture
-            || (!board.table[new_position.0 - 1][new_position.1 - 1].is_some() && capture)
+            || (board.table[new_position.0 - 1][new_position.1 - 1].is_none() && capture)
         {
             return Ok(false);
         }
 
-        if new_position.0 > position.0 || new_position.0 < position.0 {
+        if new_position.0 != position.0 {
             let delta_x =
                 cmp::max(position.1, new_position.1) - cmp::min(position.1, new_position.1);
             let delta_y =
@@ -1008,17 +1012,17 @@ impl PieceTrait for Queen {
     ) -> Result<bool, Vec<AdjustPiece>> {
         /* Well, a glorified bishop and rook, and I'm lazy... */
         let r = Rook {
-            color: _WHITE_PIECE,
+            color: self.color,
             has_moved: 0,
         };
         let b = Bishop {
-            color: _WHITE_PIECE,
+            color: self.color,
             has_moved: 0,
         };
 
-        if r.theory_valid_move(&board, capture, position, new_position)
+        if r.theory_valid_move(board, capture, position, new_position)
             .is_err()
-            || b.theory_valid_move(&board, capture, position, new_position)
+            || b.theory_valid_move(board, capture, position, new_position)
                 .ok()
                 == Some(true)
         {
@@ -1063,6 +1067,12 @@ impl PieceTrait for King {
 }
 impl_piececommon!(King);
 
+impl Default for Board {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Board {
     pub fn new() -> Board {
         let mut pieces = HashMap::<char, Box<dyn PieceTrait>>::new();
@@ -1112,41 +1122,72 @@ impl Board {
         Board {
             table: Array2D::filled_with(None, 8, 8).as_rows(), //vec![vec![None]],
             history: vec![],
-            short_pieces: vec!['R', 'K', 'B', 'K', 'Q', 'P'],
-            pieces: pieces,
+            state: position::PositionState::default(),
+            short_pieces: vec!['R', 'N', 'B', 'K', 'Q', 'P'],
+            pieces,
         }
     }
 
+    /* The standard position, from the board_definition embedded in the crate */
     pub fn populate_board(&mut self) {
-        let mut row: usize = 0;
-        let mut column: usize = 0;
-        let mut iter = 0;
-
-        let contents =
-            fs::read_to_string("board_definition").expect("Something went wrong reading the file");
-        for c in contents.replace("\n", "").chars() {
-            if iter == 8 {
-                row += 1;
-                column = 0;
-                iter = 0;
-            }
-
-            self.table[row][column] = Some(self.pieces.get(&c).unwrap().clone());
+        self.load_definition(definition::DEFAULT_BOARD_DEFINITION)
+            .expect("the embedded board_definition is valid");
+    }
+}
 
-            let mut localpiece;
-            localpiece = self.pieces.get(&c).unwrap().clone();
-            localpiece.set_color(_BLACK_PIECE);
-            self.table[7 - row][column] = Some(localpiece);
+#[derive(Debug, Clone, PartialEq)]
+pub enum MoveError {
+    Unparsable,
+    NoSuchPiece,
+    IllegalMove,
+    CastlingNotAllowed,
+    KingInCheck,
+    OwnPieceOnTarget,
+    NothingToCapture,
+    CaptureNotMarked,
+    InvalidPromotion,
+    Ambiguous(Vec<String>),
+    GameOver,
+}
 
-            column += 1;
-            iter += 1;
+impl fmt::Display for MoveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MoveError::Unparsable => write!(f, "could not understand the move"),
+            MoveError::NoSuchPiece => write!(f, "there is no such piece to move"),
+            MoveError::IllegalMove => write!(f, "that piece cannot move there"),
+            MoveError::CastlingNotAllowed => write!(f, "castling is not allowed right now"),
+            MoveError::KingInCheck => write!(f, "the move would leave the king in check"),
+            MoveError::OwnPieceOnTarget => {
+                write!(f, "the target square is occupied by your own piece")
+            }
+            MoveError::NothingToCapture => write!(f, "there is nothing to capture there"),
+            MoveError::CaptureNotMarked => {
+                write!(
+                    f,
+                    "the target square is occupied, captures are written with 'x'"
+                )
+            }
+            MoveError::InvalidPromotion => {
+                write!(
+                    f,
+                    "pawns reaching the last rank must promote to Q, R, B or N"
+                )
+            }
+            MoveError::Ambiguous(moves) => {
+                write!(f, "ambiguous move, could be any of {}", moves.join(", "))
+            }
+            MoveError::GameOver => write!(f, "the game is already over"),
         }
     }
 }
 
 pub trait Notation {
     fn new(board: Board, turn: usize) -> Self;
-    fn do_move(&mut self, p_move: &str) -> bool;
+    fn do_move(&mut self, p_move: &str) -> bool {
+        self.try_move(p_move).is_ok()
+    }
+    fn try_move(&mut self, p_move: &str) -> Result<(), MoveError>;
     fn find_piece(
         &self,
         board: &Board,
@@ -1155,30 +1196,75 @@ pub trait Notation {
         file: usize,
         color: usize,
     ) -> Vec<FoundPiece>;
-    fn check_king_threat(&mut self, board: Board) -> Result<bool, FoundPiece>;
+    fn check_king_threat(&self, board: &Board) -> Result<bool, FoundPiece>;
 }
 
+#[derive(Debug, Clone)]
 pub struct AlgebraicNotation {
     base_notation: char,
+    analysis: bool,
     board: Board,
     turn: usize,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct FoundPiece {
-    piece: char,
-    position: (usize, usize),
+    pub piece: char,
+    pub position: (usize, usize),
+}
+
+impl AlgebraicNotation {
+    /* saved is the board to go back to should the own king be left in
+     * check; without it the move is taken to be legal already */
+    fn finish_move(
+        &mut self,
+        saved: Option<Board>,
+        letter: char,
+        captured: bool,
+        from: (usize, usize),
+        to: (usize, usize),
+    ) -> Result<(), MoveError> {
+        /* No move may leave the own king in check, not only the ones made while in check */
+        if let Some(saved) = saved {
+            if self.check_king_threat(&self.board).is_err() {
+                self.board = saved;
+                return Err(MoveError::KingInCheck);
+            }
+        }
+
+        self.board.history.push(Move {
+            before: vec![from.0 as u32, from.1 as u32],
+            after: vec![to.0 as u32, to.1 as u32],
+        });
+
+        self.board
+            .state
+            .update(self.turn, letter, from, to, captured);
+
+        if self.turn == _WHITE_PIECE {
+            self.turn = _BLACK_PIECE;
+        } else {
+            self.turn = _WHITE_PIECE;
+        }
+
+        Ok(())
+    }
 }
 
 impl Notation for AlgebraicNotation {
-    fn new(board: Board, turn: usize) -> AlgebraicNotation {
+    fn new(mut board: Board, turn: usize) -> AlgebraicNotation {
+        /* boards set up square by square have no rights of their own */
+        board.state.castling = position::PositionState::from_placement(&board).castling;
         AlgebraicNotation {
             base_notation: 'a',
-            board: board,
-            turn: turn,
+            analysis: false,
+            board,
+            turn,
         }
     }
 
+    /* Pieces of the given color, optionally restricted to a kind (0 for any),
+     * a rank and a file (0 for any). Positions are table coordinates. */
     fn find_piece(
         &self,
         board: &Board,
@@ -1187,120 +1273,85 @@ impl Notation for AlgebraicNotation {
         file: usize,
         color: usize,
     ) -> Vec<FoundPiece> {
-        /* Will currently silently fail if the piece to be moved is not at turn colorwise */
         let mut matches: Vec<FoundPiece> = vec![];
-        let mut i: usize = 0;
-
-        if rank == 0 && file == 0 {
-            let pieces = &board.table;
-            let mut ir = 0;
-            i = 0;
-            for r in pieces {
-                for p in r {
-                    if p.as_ref().is_none() {
-                        i += 1;
-                        continue;
-                    }
-                    if piece != 0 as char && p.as_ref().unwrap().get_identity() != piece.to_string()
-                    {
-                        i += 1;
-                        continue;
-                    }
-                    if p.as_ref().unwrap().get_color() == color {
-                        matches.push(FoundPiece {
-                            piece: (p.as_ref().unwrap().get_identity())
-                                .chars()
-                                .collect::<Vec<char>>()[0],
-                            position: (ir + 1, i + 1),
-                        });
-                    }
-                    i += 1;
-                }
-                ir += 1;
-                i = 0;
-            }
-        }
 
-        if rank != 0 && file == 0 {
-            let pieces = &self.board.table[rank - 1];
-            for p in pieces {
-                if p.as_ref().unwrap().get_identity() == piece.to_string()
-                    && p.as_ref().unwrap().get_color() == self.turn
-                {
-                    println!("Found {:?} at rank {:?}", piece, rank);
-                    matches.push(FoundPiece {
-                        piece: piece,
-                        position: (rank - 1, i),
-                    });
-                }
-                i += 1;
+        for (ir, r) in board.table.iter().enumerate() {
+            if rank != 0 && ir != rank - 1 {
+                continue;
             }
-        }
-
-        if rank != 0 && file != 0 {
-            if self.board.table[rank - 1][file - 1].is_some() {
-                let p_piece = self.board.table[rank - 1][file - 1].as_ref().unwrap();
-                if p_piece.get_identity() == piece.to_string() && p_piece.get_color() == self.turn {
+            for (i, p) in r.iter().enumerate() {
+                if file != 0 && i != file - 1 {
+                    continue;
+                }
+                let p = match p {
+                    Some(p) if p.get_color() == color => p,
+                    _ => continue,
+                };
+                let identity = p.get_identity().chars().next().unwrap();
+                if piece == 0 as char || identity == piece {
                     matches.push(FoundPiece {
-                        piece: piece,
-                        position: (rank - 1, file - 1),
+                        piece: identity,
+                        position: (ir, i),
                     });
                 }
             }
         }
 
-        //self.board.table[(rank, file)].as_ref().unwrap().clone()
         matches
     }
 
-    fn check_king_threat(&mut self, board: Board) -> Result<bool, FoundPiece> {
-        let king;
+    fn check_king_threat(&self, board: &Board) -> Result<bool, FoundPiece> {
         /* Edge case; e.g. promotion tests... will not impact real sessions */
         /* it could be in some case possible to endanger the king without triggering the code, but then it's a bug */
-        let temp = self.find_piece(&board, 'K', 0, 0, self.turn);
-        if temp.len() > 0 {
-            king = temp[0];
+        let temp = self.find_piece(board, 'K', 0, 0, self.turn);
+        let king = if !temp.is_empty() {
+            temp[0]
         } else {
             return Ok(false);
+        };
+        if attacks::is_attacked(board, king.position, 1 - self.turn) {
+            return Err(king);
         }
-        let enemy_pieces = self.find_piece(
-            &board,
-            0 as char,
-            0,
-            0,
-            if self.turn == _WHITE_PIECE {
-                _BLACK_PIECE
-            } else {
-                _WHITE_PIECE
-            },
-        );
-        for p in enemy_pieces {
-            if p.position != king.position {
-                /* to account that the king might decide to himself strike down the enemy */
-                let test_move = board.table[p.position.0 - 1][p.position.1 - 1]
-                    .as_ref()
-                    .unwrap()
-                    .theory_valid_move(&board, true, p.position, king.position);
-                if test_move.as_ref().is_ok() == true && test_move.as_ref().ok() != Some(&false)
-                    || test_move.as_ref().is_err() == true
-                {
-                    println!(
-                        "{:?} at ({:?}) threatens king at {:?}",
-                        board.table[p.position.0 - 1][p.position.1 - 1]
-                            .as_ref()
-                            .unwrap()
-                            .get_identity(),
-                        p.position,
-                        king.position
-                    );
-                    return Err(king);
-                }
+        Ok(false)
+    }
+
+    fn try_move(&mut self, p_move: &str) -> Result<(), MoveError> {
+        let result = self.move_for_turn(p_move);
+        /* in analysis mode the other side may move too, the side to move is tried first */
+        if result.is_err() && self.analysis {
+            self.turn = 1 - self.turn;
+            if self.move_for_turn(p_move).is_ok() {
+                return Ok(());
             }
+            self.turn = 1 - self.turn;
         }
-        Ok(false)
+        result
+    }
+}
+
+impl AlgebraicNotation {
+    /* Lets moves be made for either side, e.g. when setting up a position */
+    pub fn set_analysis_mode(&mut self, analysis: bool) {
+        self.analysis = analysis;
     }
 
-    fn do_move(&mut self, p_move: &str) -> bool {
+    pub fn analysis_mode(&self) -> bool {
+        self.analysis
+    }
+
+    /* Parses and plays a move for the side to move */
+    fn move_for_turn(&mut self, p_move: &str) -> Result<(), MoveError> {
+        self.make_move(p_move, true)
+    }
+
+    /* Plays a move of the side to move that is known not to leave its
+     * king in check, skipping the copy of the board and the check test
+     * that taking an illegal move back needs */
+    pub(crate) fn play_legal(&mut self, p_move: &str) -> Result<(), MoveError> {
+        self.make_move(p_move, false)
+    }
+
+    fn make_move(&mut self, p_move: &str, verify: bool) -> Result<(), MoveError> {
         let mut p_move_chars: Vec<char> = p_move.chars().collect();
 
         let lookup_piece: char;
@@ -1310,22 +1361,33 @@ impl Notation for AlgebraicNotation {
         let mut before: Vec<char> = vec![];
         let mut after: Vec<char> = vec![];
         let mut promotion: char = 0 as char;
-        let mut threatened = false;
-        let mut move_occured = false;
-        let mut start: Vec<FoundPiece> = vec![];
+        let saved = if verify {
+            Some(self.board.clone())
+        } else {
+            None
+        };
 
-        let chmthreat = self.check_king_threat(self.board.clone());
-        if chmthreat.is_err() {
-            threatened = true;
+        if p_move_chars.len() < 3 || !p_move.is_ascii() {
+            return Err(MoveError::Unparsable);
         }
 
         if p_move.contains('-') {
             /* Castling */
-            let mut king_pos = self.find_piece(&self.board, 'K', 0, 0, self.turn)[0].position;
-            king_pos = (king_pos.0 - 1, king_pos.1 - 1);
-            let king = self.board.table[king_pos.0][king_pos.1].as_mut().unwrap();
-            if king.movement(0) > 0 || threatened || !(p_move == "0-0" || p_move == "0-0-0") {
-                return false;
+            if !(p_move == "0-0" || p_move == "0-0-0") {
+                return Err(MoveError::Unparsable);
+            }
+            let king_vec = self.find_piece(&self.board, 'K', 0, 0, self.turn);
+            if king_vec.is_empty() {
+                return Err(MoveError::NoSuchPiece);
+            }
+            let king_pos = king_vec[0].position;
+            let threatened = self.check_king_threat(&self.board).is_err();
+            let home_row = if self.turn == _WHITE_PIECE { 0 } else { 7 };
+            if !self.board.state.castling.allows(self.turn, p_move == "0-0")
+                || threatened
+                || king_pos != (home_row, 4)
+            {
+                return Err(MoveError::CastlingNotAllowed);
             }
 
             let rook_pos_vec = self.find_piece(
@@ -1335,17 +1397,11 @@ impl Notation for AlgebraicNotation {
                 if p_move == "0-0" { 8 } else { 1 },
                 self.turn,
             );
-            let rook_pos;
-            if rook_pos_vec.len() == 1 {
-                rook_pos = rook_pos_vec[0].position;
+            let rook_pos = if rook_pos_vec.len() == 1 {
+                rook_pos_vec[0].position
             } else {
-                return false;
-            }
-            //well, different behaviour depending on rank, file.. rook_pos = (rook_pos.0-1, rook_pos.1-1);
-            let rook = self.board.table[rook_pos.0][rook_pos.1].as_mut().unwrap();
-            if rook.movement(0) > 0 {
-                return false;
-            }
+                return Err(MoveError::CastlingNotAllowed);
+            };
             // delta x is 3
             for i in 1..3 {
                 let offset = if p_move == "0-0" {
@@ -1354,46 +1410,65 @@ impl Notation for AlgebraicNotation {
                     king_pos.1 - i
                 };
                 if self.board.table[king_pos.0][offset].is_some() {
-                    return false;
+                    return Err(MoveError::CastlingNotAllowed);
                 }
                 let mut tboard = self.board.clone();
                 tboard.table[king_pos.0][offset] = tboard.table[king_pos.0][king_pos.1].clone();
                 tboard.table[king_pos.0][king_pos.1] = None;
-                if self.check_king_threat(tboard).is_err() {
-                    return false;
+                if self.check_king_threat(&tboard).is_err() {
+                    return Err(MoveError::CastlingNotAllowed);
+                }
+            }
+            // well, queen-sidewise does the rook need to pass this...
+            if p_move == "0-0-0" {
+                for i in 1..4 {
+                    if self.board.table[rook_pos.0][rook_pos.1 + i].is_some() {
+                        return Err(MoveError::CastlingNotAllowed);
+                    }
                 }
             }
-			// well, queen-sidewise does the rook need to pass this...
-			if p_move == "0-0-0" {
-				for i in 1..4 {
-					if self.board.table[rook_pos.0][rook_pos.1+i].is_some() {
-						return false;
-					}
-				}
-			}
 
             let final_offset_king: isize = if p_move == "0-0" { 2 } else { -2 };
             let final_offset_rook: isize = if p_move == "0-0" { -2 } else { 3 };
-            self.board.table[king_pos.0][(king_pos.1 as isize + final_offset_king) as usize] =
+            let new_king_pos = (
+                king_pos.0,
+                (king_pos.1 as isize + final_offset_king) as usize,
+            );
+            let new_rook_pos = (
+                rook_pos.0,
+                (rook_pos.1 as isize + final_offset_rook) as usize,
+            );
+            self.board.table[new_king_pos.0][new_king_pos.1] =
                 self.board.table[king_pos.0][king_pos.1].clone();
-            self.board.table[rook_pos.0][(rook_pos.1 as isize + final_offset_rook) as usize] =
+            self.board.table[new_rook_pos.0][new_rook_pos.1] =
                 self.board.table[rook_pos.0][rook_pos.1].clone();
             self.board.table[king_pos.0][king_pos.1] = None;
             self.board.table[rook_pos.0][rook_pos.1] = None;
+            for pos in [new_king_pos, new_rook_pos] {
+                self.board.table[pos.0][pos.1].as_mut().unwrap().movement(1);
+            }
 
-            move_occured = true;
-            lookup_piece = 0 as char; // dumb rust compilator
+            return self.finish_move(saved, 'K', false, king_pos, new_king_pos);
         } else if p_move_chars.len() == 3 {
             /* Promotion */
             before = p_move_chars[..2].to_vec();
             after = before.clone();
-            before[1] = ((before[1] as u8) - 1) as char;
+            before[1] = if self.turn == _WHITE_PIECE {
+                ((before[1] as u8) - 1) as char
+            } else {
+                ((before[1] as u8) + 1) as char
+            };
             lookup_piece = 'P';
             promotion = p_move_chars[2];
-            if promotion == 'P' {
-                return false; // disallow promoting to pawn
-            }
         } else {
+            /* Promotion on the long form, e.g. Pe7e8Q or Pd7xe8=Q */
+            if p_move_chars[p_move_chars.len() - 1].is_uppercase() {
+                promotion = p_move_chars.pop().unwrap();
+                if p_move_chars.last() == Some(&'=') {
+                    p_move_chars.pop();
+                }
+            }
+
             if p_move_chars[1] == 'x' {
                 /* Well, pawn */
                 lookup_piece = 'P';
@@ -1406,148 +1481,140 @@ impl Notation for AlgebraicNotation {
                 capture = true;
                 let capture_i = p_move.find('x');
                 match capture_i {
+                    Some(0) => return Err(MoveError::Unparsable),
                     Some(i) => {
                         before = p_move_chars[..i - 1].to_vec();
                         after = p_move_chars[(i)..].to_vec();
                     }
                     None => (),
                 };
+            } else if p_move_chars.len() == 4 {
+                before = p_move_chars[..2].to_vec();
+                after = p_move_chars[2..].to_vec();
             } else {
-                if p_move_chars.len() == 4 {
-                    before = p_move_chars[..2].to_vec();
-                    after = p_move_chars[2..].to_vec();
-                } else {
-                    before = p_move_chars[..1].to_vec();
-                    after = p_move_chars[1..].to_vec();
-                }
+                before = p_move_chars[..1].to_vec();
+                after = p_move_chars[1..].to_vec();
             }
         }
 
-        if before.len() >= 1 {
-            for c in before.iter() {
-                if c.is_alphabetic() {
-                    //println!("{:?}", before);
-                    file = *c as usize - self.base_notation as usize + 1;
-                } else {
-                    match c.to_digit(10) {
-                        Some(i) => {
-                            rank = i as usize;
-                        }
-                        None => (),
-                    }
-                }
+        for c in before.iter() {
+            if c.is_alphabetic() {
+                //println!("{:?}", before);
+                file = (*c as usize + 1).saturating_sub(self.base_notation as usize);
+            } else if let Some(i) = c.to_digit(10) {
+                rank = i as usize;
             }
         }
 
-        if !move_occured {
-            start = self.find_piece(&self.board, lookup_piece, rank, file, 0);
-            if start.len() == 0 {
-                return false;
-            }
+        if rank > 8 || file > 8 {
+            return Err(MoveError::Unparsable);
+        }
+
+        let start = self.find_piece(&self.board, lookup_piece, rank, file, self.turn);
+        if start.is_empty() {
+            return Err(MoveError::NoSuchPiece);
         }
 
-        let b_rf = (rank, file);
         rank = 0;
         file = 0;
 
         for c in after.iter() {
             if c.is_alphabetic() {
-                file = *c as usize - self.base_notation as usize + 1;
+                file = (*c as usize + 1).saturating_sub(self.base_notation as usize);
             /* theory_valid_move accepts human-wise arg., hence +1 */
-            } else {
-                match c.to_digit(10) {
-                    Some(i) => {
-                        rank = i as usize;
-                    }
-                    None => (),
-                }
+            } else if let Some(i) = c.to_digit(10) {
+                rank = i as usize;
             }
         }
 
-        if (rank == 0 || file == 0) && !move_occured {
-            println!("Insufficient");
-            return false;
+        if rank == 0 || file == 0 || rank > 8 || file > 8 {
+            return Err(MoveError::Unparsable);
         }
 
-        if threatened {
-            if chmthreat.err().unwrap().position != (b_rf.0, b_rf.1) {
-                return false;
-            }
-            // see if the (not yet) validated move would work
-            let mut tboard = self.board.clone();
-            tboard.table[rank - 1][file - 1] = tboard.table[b_rf.0][b_rf.1].clone();
-            tboard.table[b_rf.0][b_rf.1] = None;
+        let last_rank = if self.turn == _WHITE_PIECE { 8 } else { 1 };
+        if promotion != 0 as char
+            && (lookup_piece != 'P' || rank != last_rank || !"QRBN".contains(promotion))
+            || promotion == 0 as char && lookup_piece == 'P' && rank == last_rank
+        {
+            return Err(MoveError::InvalidPromotion);
+        }
 
-            if self.check_king_threat(tboard).is_err() {
-                return false;
+        match &self.board.table[rank - 1][file - 1] {
+            Some(target) => {
+                if target.get_color() == self.turn {
+                    return Err(MoveError::OwnPieceOnTarget);
+                }
+                if !capture {
+                    return Err(MoveError::CaptureNotMarked);
+                }
+            }
+            None => {
+                /* pawns may still capture en passant */
+                if capture && lookup_piece != 'P' {
+                    return Err(MoveError::NothingToCapture);
+                }
             }
         }
 
         // Is this valid?
         for fp in start {
-            if move_occured {
-                break;
-            }
-            let test_move = self.board.table[fp.position.0][fp.position.1]
-                .as_ref()
-                .unwrap()
-                .theory_valid_move(
+            let test_move = match self.board.table[fp.position.0][fp.position.1].as_ref() {
+                Some(p) => p.theory_valid_move(
                     &self.board,
                     capture,
                     ((fp.position.0 + 1), (fp.position.1 + 1)),
                     (rank, file),
-                );
+                ),
+                None => continue,
+            };
 
-            if test_move.as_ref().is_ok() == true && test_move.as_ref().ok() != Some(&false)
-                || test_move.as_ref().is_err() == true
-            {
-                if promotion == 0 as char {
-                    self.board.table[rank - 1][file - 1] = Some(
-                        self.board.table[fp.position.0][fp.position.1]
-                            .as_ref()
-                            .unwrap()
-                            .clone(),
-                    );
-                } else {
-                    self.board.table[rank - 1][file - 1] =
-                        Some(self.board.pieces.get(&promotion).unwrap().clone());
+            if test_move.as_ref().ok() == Some(&false) {
+                continue;
+            }
 
-                    if self.turn == _BLACK_PIECE {
-                        self.board.table[rank - 1][file - 1]
+            let letter = fp.piece;
+            let captured = self.board.table[rank - 1][file - 1].is_some()
+                || letter == 'P' && fp.position.1 != file - 1;
+            if promotion == 0 as char {
+                self.board.table[rank - 1][file - 1] = Some(
+                    self.board.table[fp.position.0][fp.position.1]
+                        .as_ref()
+                        .unwrap()
+                        .clone(),
+                );
+            } else {
+                self.board.table[rank - 1][file - 1] =
+                    Some(self.board.pieces.get(&promotion).unwrap().clone());
+
+                if self.turn == _BLACK_PIECE {
+                    self.board.table[rank - 1][file - 1]
+                        .as_mut()
+                        .unwrap()
+                        .set_color(_BLACK_PIECE);
+                }
+            }
+            self.board.table[fp.position.0][fp.position.1] = None;
+            if let Err(correct_board) = test_move {
+                for p in correct_board {
+                    if p.increase_movement >= 1 {
+                        self.board.table[p.piece.0][p.piece.1]
                             .as_mut()
                             .unwrap()
-                            .set_color(_BLACK_PIECE);
+                            .movement(p.increase_movement);
                     }
-                }
-                self.board.table[fp.position.0][fp.position.1] = None;
-                if test_move.is_err() {
-                    let correct_board = test_move.err().unwrap();
-                    for p in correct_board {
-                        if p.increase_movement >= 1 {
-                            self.board.table[p.piece.0][p.piece.1]
-                                .as_mut()
-                                .unwrap()
-                                .movement(p.increase_movement);
-                        }
-                        if p.remove_piece {
-                            self.board.table[p.piece.0][p.piece.1] = None;
-                        }
+                    if p.remove_piece {
+                        self.board.table[p.piece.0][p.piece.1] = None;
                     }
                 }
-                move_occured = true;
             }
+            /* so that e.g. a pawn can not double step after a single step */
+            let moved = self.board.table[rank - 1][file - 1].as_mut().unwrap();
+            if moved.movement(0) == 0 {
+                moved.movement(1);
+            }
+            return self.finish_move(saved, letter, captured, fp.position, (rank - 1, file - 1));
         }
 
-        if !move_occured {
-            return false;
-        }
-
-        if self.turn == _WHITE_PIECE {
-            self.turn = _BLACK_PIECE;
-        } else {
-            self.turn = _WHITE_PIECE;
-        }
-
-        true
+        Err(MoveError::IllegalMove)
     }
 }
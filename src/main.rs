@@ -1,22 +1,788 @@
+use schackmotor::book::{self, Book, BookMode};
+use schackmotor::bookbuild;
+use schackmotor::clock::{Clock, MonotonicTime, TimeControl};
+use schackmotor::datagen::{self, Rng};
+use schackmotor::display::DisplayOptions;
+use schackmotor::engine::{self, SearchLimits};
+use schackmotor::epd;
+use schackmotor::evaluation::{self, Weights};
+use schackmotor::game::{Game, GameResult, Termination};
+use schackmotor::matchplay;
+use schackmotor::mcts::{self, MctsConfig};
+use schackmotor::pgn;
+use schackmotor::problem::{self, Stipulation};
+use schackmotor::protocol::Session;
+use schackmotor::server;
+use schackmotor::sprt;
+use schackmotor::tablebase::{self, Tablebase};
+use schackmotor::tuning;
 use schackmotor::*;
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::net::TcpListener;
+use std::sync::Arc;
+use std::time::Duration;
+
+const HELP: &str = "Commands:
+  <move>   play a move, e.g. e4, Nf3, exd5, O-O, e8=Q or g1f3
+  moves    list the legal moves
+  book     list the book moves, when a book is open
+  undo     take back the last move
+  fen      print the position as FEN
+  pgn      print the game as PGN
+  flip     turn the board around
+  resign   resign the game
+  draw     offer or accept a draw
+  help     show this text
+  quit     leave";
+
+const USAGE: &str =
+    "usage: schackmotor [--board FILE] [--engine white|black] [--depth N] [--time CONTROL] [--book FILE] [--book-mode best|weighted|uniform] [--tablebase DIR] [--mcts OPTIONS] [--weights FILE] [--unicode] [--color] [--plain]\n       schackmotor --epd FILE [--depth N] [--seconds N] [--weights FILE]\n       schackmotor --match PLAYER PLAYER [--games N] [--concurrency N] [--openings FILE] [--pgn FILE] [--sprt ELO0,ELO1[,ALPHA,BETA]]\n       schackmotor --datagen FILE [--games N] [--nodes N] [--seed N] [--concurrency N] [--weights FILE]\n       schackmotor --tune FILE [--iterations N] [--out FILE]\n       schackmotor --make-book PGN --out FILE [--plies N] [--min-games N] [--min-rating N]\n       schackmotor --make-tablebase KQK,KRKP,... --out DIR\n       schackmotor --solve FEN --stipulation #N|h#N|s#N\n       schackmotor --json\n       schackmotor --serve ADDRESS";
+
+fn color_name(color: usize) -> &'static str {
+    if color == _WHITE_PIECE {
+        "White"
+    } else {
+        "Black"
+    }
+}
+
+fn format_time(time: Duration) -> String {
+    let tenths = time.as_millis() / 100;
+    format!("{}:{:02}.{}", tenths / 600, tenths / 10 % 60, tenths % 10)
+}
+
+fn print_board(game: &Game, options: DisplayOptions) {
+    print!("{}", game.notation().board().display(options));
+    if let Some(clock) = game.clock() {
+        println!(
+            "White {}  Black {}",
+            format_time(clock.remaining(_WHITE_PIECE)),
+            format_time(clock.remaining(_BLACK_PIECE))
+        );
+    }
+}
+
+/* Like "e4 60%, d4 30%, c4 10%", shares by weight */
+fn describe_book_moves(moves: &[book::BookMove]) -> String {
+    let total: u32 = moves.iter().map(|m| m.weight as u32).sum();
+    moves
+        .iter()
+        .map(|m| match (100 * m.weight as u32).checked_div(total) {
+            Some(share) => format!("{} {}%", m.legal.san, share),
+            None => m.legal.san.clone(),
+        })
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+fn print_outcome(game: &Game) {
+    let how = match game.termination() {
+        Some(Termination::Checkmate) => "checkmate",
+        Some(Termination::Stalemate) => "stalemate",
+        Some(Termination::Resignation) => "resignation",
+        Some(Termination::Agreement) => "agreement",
+        Some(Termination::TimeForfeit) => "time forfeit",
+        Some(Termination::Adjudication) => "adjudication",
+        None => return,
+    };
+    let who = match game.result() {
+        GameResult::WhiteWins => "White wins",
+        GameResult::BlackWins => "Black wins",
+        _ => "Draw",
+    };
+    println!("{} by {} ({})", who, how, game.result().pgn());
+}
+
+/* Searches every position of an EPD file and prints how it went */
+fn run_suite(path: &str, depth: Option<usize>, seconds: Option<u64>, weights: &Weights) {
+    let records = match fs::read_to_string(path)
+        .map_err(|e| e.to_string())
+        .and_then(|text| epd::parse_epd(&text).map_err(|e| e.to_string()))
+    {
+        Ok(records) => records,
+        Err(e) => {
+            eprintln!("{}: {}", path, e);
+            return;
+        }
+    };
+    let limits = SearchLimits {
+        /* with only a time limit, search as deep as the time allows */
+        depth: match (depth, seconds) {
+            (Some(depth), _) => depth,
+            (None, Some(_)) => 64,
+            (None, None) => 3,
+        },
+        nodes: None,
+        time: seconds.map(Duration::from_secs),
+    };
+    print!("{}", epd::run_suite(&records, &limits, weights));
+}
+
+/* Openings are FEN or EPD lines, players as matchplay::PlayerSpec::parse takes them */
+fn match_config(
+    first: &str,
+    second: &str,
+    openings: Option<&str>,
+) -> Result<matchplay::MatchConfig, String> {
+    let mut fens = vec![];
+    if let Some(path) = openings {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if AlgebraicNotation::from_fen(line).is_ok() {
+                fens.push(line.to_string());
+            } else {
+                let record = epd::EpdRecord::parse(line).map_err(|e| format!("{}: {}", path, e))?;
+                fens.push(record.fen());
+            }
+        }
+    }
+    Ok(matchplay::MatchConfig {
+        first: matchplay::PlayerSpec::parse(first)?,
+        second: matchplay::PlayerSpec::parse(second)?,
+        games: 2,
+        openings: fens,
+        concurrency: 1,
+        adjudication: matchplay::Adjudication::default(),
+    })
+}
+
+/* Prints every game's result as it ends, then the summary */
+fn print_game(g: &matchplay::MatchGame) {
+    println!(
+        "game {}: {} - {} {}",
+        g.round + 1,
+        g.game.white,
+        g.game.black,
+        g.game.result().pgn()
+    )
+}
+
+/* With an SPRT the games are a maximum and the match may stop earlier */
+fn run_match(
+    config: &matchplay::MatchConfig,
+    sprt: Option<&sprt::Sprt>,
+    pgn_file: Option<&str>,
+) -> Result<(), String> {
+    let report = match sprt {
+        Some(sprt) => {
+            let result = sprt::run_sprt_with(config, sprt, print_game)?;
+            println!("{}", result);
+            result.report
+        }
+        None => {
+            let report = matchplay::run_match_with(config, print_game)?;
+            println!("{}", report.summary());
+            report
+        }
+    };
+    if let Some(path) = pgn_file {
+        fs::write(path, report.to_pgn()).map_err(|e| format!("{}: {}", path, e))?;
+    }
+    Ok(())
+}
+
+/* Writes the positions as the games finish, counting them on stderr */
+fn generate_data(path: &str, config: &datagen::DatagenConfig) -> io::Result<()> {
+    let mut out = io::BufWriter::new(fs::File::create(path)?);
+    let mut written = 0;
+    let mut failure = None;
+    datagen::generate_with(config, |g| {
+        for position in &g.positions {
+            if let Err(e) = writeln!(out, "{}", position) {
+                failure = Some(e);
+            }
+        }
+        written += g.positions.len();
+        eprintln!(
+            "game {} of {}: {}, {} positions in all",
+            g.index + 1,
+            config.games,
+            g.result.pgn(),
+            written
+        );
+    });
+    match failure {
+        Some(e) => Err(e),
+        None => out.flush(),
+    }
+}
+
+/* Prints the error as tuning goes on, then the weights */
+fn tune(path: &str, config: &tuning::TunerConfig, out_file: Option<&str>) -> Result<(), String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    let labelled = tuning::load_dataset(&text).map_err(|e| format!("{}: {}", path, e))?;
+    let start = evaluation::Weights::default();
+    let positions = tuning::prepare(&labelled, &start);
+    let report = tuning::tune(&positions, &start, config, |iteration, error| {
+        if iteration.is_multiple_of(100) || iteration == config.iterations {
+            eprintln!("iteration {}: error {:.6}", iteration, error);
+        }
+    });
+    match out_file {
+        Some(out) => fs::write(out, report.to_string()).map_err(|e| format!("{}: {}", out, e)),
+        None => {
+            print!("{}", report);
+            Ok(())
+        }
+    }
+}
+
+/* Reports the games that could not be read to the end on stderr */
+fn make_book(path: &str, config: &bookbuild::BuildConfig, out_file: &str) -> Result<(), String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    let games = pgn::parse_pgn(&text).map_err(|e| format!("{}: {}", path, e))?;
+    let (book, report) = bookbuild::build_book(&games, config);
+    for e in &report.errors {
+        eprintln!("{}: {}", path, e);
+    }
+    book.save(out_file)
+        .map_err(|e| format!("{}: {}", out_file, e))?;
+    println!("{}", report);
+    Ok(())
+}
+
+/* Makes the tables with the smaller ones they need, each saved as it is done */
+fn make_tablebase(list: &str, dir: &str) -> Result<(), String> {
+    let mut tablebase = Tablebase::new();
+    for name in list.split(',') {
+        let material = tablebase::Material::parse(name).map_err(|e| e.to_string())?;
+        for made in tablebase.generate(&material) {
+            tablebase.save(&made, dir).map_err(|e| e.to_string())?;
+            let table = tablebase.get(&made).unwrap();
+            let (wins, draws, losses) = table.counts();
+            let longest = table
+                .longest()
+                .map_or("no mates".to_string(), |p| format!("longest {}", p));
+            println!(
+                "{}: {} won, {} drawn, {} lost, {}",
+                made, wins, draws, losses, longest
+            );
+        }
+    }
+    Ok(())
+}
+
+fn solve(fen: &str, stipulation: Stipulation) -> Result<(), String> {
+    let start = AlgebraicNotation::from_fen(fen).map_err(|e| e.to_string())?;
+    match stipulation {
+        Stipulation::Directmate(moves) => println!("{}", problem::solve_directmate(&start, moves)),
+        Stipulation::Helpmate(moves) => println!("{}", problem::solve_helpmate(&start, moves)),
+        Stipulation::Selfmate(moves) => println!("{}", problem::solve_selfmate(&start, moves)),
+    }
+    Ok(())
+}
 
 fn main() {
-    let mut board = Board::new();
-    board.populate_board();
-    //println!("{:?}", board.table);
+    let mut engine_color: Option<usize> = None;
+    let mut depth: usize = 3;
+    let mut depth_given = false;
+    let mut options = DisplayOptions::default();
+    let mut definition: Option<String> = None;
+    let mut time_control: Option<TimeControl> = None;
+    let mut suite: Option<String> = None;
+    let mut players: Option<(String, String)> = None;
+    let mut games: Option<usize> = None;
+    let mut concurrency: usize = 1;
+    let mut openings: Option<String> = None;
+    let mut pgn_file: Option<String> = None;
+    let mut seconds: Option<u64> = None;
+    let mut data_file: Option<String> = None;
+    let mut nodes: Option<u64> = None;
+    let mut seed: u64 = 0;
+    let mut opening_book: Option<Book> = None;
+    let mut book_mode = BookMode::Weighted;
+    let mut dataset: Option<String> = None;
+    let mut iterations: Option<usize> = None;
+    let mut out_file: Option<String> = None;
+    let mut sprt_bounds: Option<sprt::Sprt> = None;
+    let mut collection: Option<String> = None;
+    let mut tablebase: Option<Tablebase> = None;
+    let mut materials: Option<String> = None;
+    let mut problem_fen: Option<String> = None;
+    let mut stipulation: Option<Stipulation> = None;
+    let mut tree_search: Option<MctsConfig> = None;
+    let mut weights = Weights::default();
+    let mut build_config = bookbuild::BuildConfig::default();
+
+    let args: Vec<String> = env::args().skip(1).collect();
+    let mut i = 0;
+    while i < args.len() {
+        match (args[i].as_str(), args.get(i + 1).map(|a| a.as_str())) {
+            ("--json", _) => {
+                /* machine protocol instead of the interactive board */
+                let stdin = io::stdin();
+                if let Err(e) = Session::new().run(stdin.lock(), io::stdout()) {
+                    eprintln!("{}", e);
+                }
+                return;
+            }
+            ("--serve", Some(address)) => {
+                let result = TcpListener::bind(address).and_then(|listener| {
+                    println!("Serving games on {}", listener.local_addr()?);
+                    server::serve(listener)
+                });
+                if let Err(e) = result {
+                    eprintln!("{}: {}", address, e);
+                }
+                return;
+            }
+            ("--unicode", _) => options.unicode = true,
+            ("--color", _) => options.ansi_colors = true,
+            ("--plain", _) => {
+                options.coordinates = false;
+                options.highlight_last_move = false;
+            }
+            ("--engine", Some("white")) => {
+                engine_color = Some(_WHITE_PIECE);
+                i += 1;
+            }
+            ("--engine", Some("black")) => {
+                engine_color = Some(_BLACK_PIECE);
+                i += 1;
+            }
+            ("--board", Some(path)) => {
+                definition = Some(path.to_string());
+                i += 1;
+            }
+            ("--time", Some(control)) => {
+                match TimeControl::parse(control) {
+                    Ok(control) => time_control = Some(control),
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        return;
+                    }
+                }
+                i += 1;
+            }
+            ("--epd", Some(path)) => {
+                suite = Some(path.to_string());
+                i += 1;
+            }
+            ("--match", Some(first)) if i + 2 < args.len() => {
+                players = Some((first.to_string(), args[i + 2].clone()));
+                i += 2;
+            }
+            ("--games", Some(n)) if n.parse::<usize>().is_ok() => {
+                games = Some(n.parse().unwrap());
+                i += 1;
+            }
+            ("--concurrency", Some(n)) if n.parse::<usize>().is_ok() => {
+                concurrency = n.parse().unwrap();
+                i += 1;
+            }
+            ("--openings", Some(path)) => {
+                openings = Some(path.to_string());
+                i += 1;
+            }
+            ("--pgn", Some(path)) => {
+                pgn_file = Some(path.to_string());
+                i += 1;
+            }
+            ("--sprt", Some(bounds)) => {
+                match sprt::Sprt::parse(bounds) {
+                    Ok(bounds) => sprt_bounds = Some(bounds),
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        return;
+                    }
+                }
+                i += 1;
+            }
+            ("--book", Some(path)) => {
+                match Book::open(path) {
+                    Ok(book) => opening_book = Some(book),
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        return;
+                    }
+                }
+                i += 1;
+            }
+            ("--weights", Some(path)) => {
+                match Weights::open(path) {
+                    Ok(loaded) => weights = loaded,
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        return;
+                    }
+                }
+                i += 1;
+            }
+            ("--tablebase", Some(dir)) => {
+                match Tablebase::open(dir) {
+                    Ok(tables) => tablebase = Some(tables),
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        return;
+                    }
+                }
+                i += 1;
+            }
+            ("--make-tablebase", Some(list)) => {
+                materials = Some(list.to_string());
+                i += 1;
+            }
+            ("--solve", Some(fen)) => {
+                problem_fen = Some(fen.to_string());
+                i += 1;
+            }
+            ("--stipulation", Some(text)) => {
+                match Stipulation::parse(text) {
+                    Ok(parsed) => stipulation = Some(parsed),
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        return;
+                    }
+                }
+                i += 1;
+            }
+            ("--mcts", Some(text)) => {
+                match MctsConfig::parse(text) {
+                    Ok(config) => tree_search = Some(config),
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        return;
+                    }
+                }
+                i += 1;
+            }
+            ("--book-mode", Some(mode)) if BookMode::parse(mode).is_some() => {
+                book_mode = BookMode::parse(mode).unwrap();
+                i += 1;
+            }
+            ("--datagen", Some(path)) => {
+                data_file = Some(path.to_string());
+                i += 1;
+            }
+            ("--tune", Some(path)) => {
+                dataset = Some(path.to_string());
+                i += 1;
+            }
+            ("--make-book", Some(path)) => {
+                collection = Some(path.to_string());
+                i += 1;
+            }
+            ("--plies", Some(n)) if n.parse::<usize>().is_ok() => {
+                build_config.max_plies = n.parse().unwrap();
+                i += 1;
+            }
+            ("--min-games", Some(n)) if n.parse::<u32>().is_ok() => {
+                build_config.min_games = n.parse().unwrap();
+                i += 1;
+            }
+            ("--min-rating", Some(n)) if n.parse::<u32>().is_ok() => {
+                build_config.min_rating = Some(n.parse().unwrap());
+                i += 1;
+            }
+            ("--iterations", Some(n)) if n.parse::<usize>().is_ok() => {
+                iterations = Some(n.parse().unwrap());
+                i += 1;
+            }
+            ("--out", Some(path)) => {
+                out_file = Some(path.to_string());
+                i += 1;
+            }
+            ("--nodes", Some(n)) if n.parse::<u64>().is_ok() => {
+                nodes = Some(n.parse().unwrap());
+                i += 1;
+            }
+            ("--seed", Some(n)) if n.parse::<u64>().is_ok() => {
+                seed = n.parse().unwrap();
+                i += 1;
+            }
+            ("--seconds", Some(n)) if n.parse::<u64>().is_ok() => {
+                seconds = Some(n.parse().unwrap());
+                i += 1;
+            }
+            ("--depth", Some(n)) if n.parse::<usize>().is_ok() => {
+                depth = n.parse().unwrap();
+                depth_given = true;
+                i += 1;
+            }
+            _ => {
+                eprintln!("{}", USAGE);
+                return;
+            }
+        }
+        i += 1;
+    }
+
+    if let Some((first, second)) = players {
+        let result = match_config(&first, &second, openings.as_deref()).and_then(|mut config| {
+            config.games = games.unwrap_or(2);
+            config.concurrency = concurrency;
+            run_match(&config, sprt_bounds.as_ref(), pgn_file.as_deref())
+        });
+        if let Err(e) = result {
+            eprintln!("{}", e);
+        }
+        return;
+    }
+
+    if let Some(path) = dataset {
+        let config = tuning::TunerConfig {
+            iterations: iterations.unwrap_or(tuning::TunerConfig::default().iterations),
+            ..tuning::TunerConfig::default()
+        };
+        if let Err(e) = tune(&path, &config, out_file.as_deref()) {
+            eprintln!("{}", e);
+        }
+        return;
+    }
+
+    if let Some(fen) = problem_fen {
+        let result = match stipulation {
+            Some(stipulation) => solve(&fen, stipulation),
+            None => Err(USAGE.to_string()),
+        };
+        if let Err(e) = result {
+            eprintln!("{}", e);
+        }
+        return;
+    }
+
+    if let Some(list) = materials {
+        let result = match &out_file {
+            Some(dir) => make_tablebase(&list, dir),
+            None => Err(USAGE.to_string()),
+        };
+        if let Err(e) = result {
+            eprintln!("{}", e);
+        }
+        return;
+    }
+
+    if let Some(path) = collection {
+        let result = match out_file {
+            Some(out) => make_book(&path, &build_config, &out),
+            None => Err(USAGE.to_string()),
+        };
+        if let Err(e) = result {
+            eprintln!("{}", e);
+        }
+        return;
+    }
+
+    if let Some(path) = data_file {
+        let defaults = datagen::DatagenConfig::default();
+        let config = datagen::DatagenConfig {
+            seed,
+            games: games.unwrap_or(defaults.games),
+            nodes: nodes.unwrap_or(defaults.nodes),
+            concurrency,
+            weights: weights.clone(),
+            ..defaults
+        };
+        if let Err(e) = generate_data(&path, &config) {
+            eprintln!("{}: {}", path, e);
+        }
+        return;
+    }
+
+    if let Some(path) = suite {
+        run_suite(&path, depth_given.then_some(depth), seconds, &weights);
+        return;
+    }
+
+    let mut game = match definition {
+        Some(path) => {
+            let mut board = Board::new();
+            if let Err(e) = board.load_definition_file(&path) {
+                eprintln!("{}: {}", path, e);
+                return;
+            }
+            Game::from_notation(Notation::new(board, _WHITE_PIECE))
+        }
+        None => Game::new(),
+    };
+    match engine_color {
+        Some(c) if c == _WHITE_PIECE => game.white = "schackmotor".to_string(),
+        Some(_) => game.black = "schackmotor".to_string(),
+        None => (),
+    }
+    options.flipped = engine_color == Some(_WHITE_PIECE);
+    if let Some(control) = time_control {
+        match Clock::new(control, Arc::new(MonotonicTime::default())) {
+            Ok(clock) => game.set_clock(clock),
+            Err(e) => {
+                eprintln!("{}", e);
+                return;
+            }
+        }
+    }
+
+    println!("{}\n", HELP);
+    print_board(&game, options);
+
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    let mut rng = Rng::new(seed);
+    let mut shown_for: Option<String> = None;
+    loop {
+        let turn = game.notation().turn();
 
-    board.table[1][3] = None;
+        if engine_color == Some(turn) && game.result() == GameResult::Ongoing {
+            /* no point thinking on a flag that has already fallen */
+            if game.check_flag() {
+                print_outcome(&game);
+                break;
+            }
+            /* out of the book, the engine thinks for itself */
+            let from_book = opening_book
+                .as_ref()
+                .and_then(|b| b.choose(game.notation(), book_mode, &mut rng));
+            /* in an ending with a table, it plays perfectly */
+            let from_tablebase = tablebase
+                .as_ref()
+                .and_then(|t| t.best_move(game.notation()));
+            let (chosen, note) = match (from_book, from_tablebase) {
+                (Some(m), _) => (Some(m.legal), " (book)".to_string()),
+                (None, Some((m, probe))) => (Some(m), format!(" (tablebase, {})", probe)),
+                (None, None) => match &tree_search {
+                    Some(config) => {
+                        let result = mcts::search(game.notation(), config, &weights);
+                        let top: Vec<String> =
+                            result.moves.iter().take(3).map(|m| m.to_string()).collect();
+                        match result.best_move {
+                            Some(m) => (Some(m), format!(" (mcts: {})", top.join(", "))),
+                            /* the tree has nothing to say, so alpha-beta decides */
+                            None => (
+                                engine::search_weighted(game.notation(), depth, &weights).best_move,
+                                String::new(),
+                            ),
+                        }
+                    }
+                    None => (
+                        engine::search_weighted(game.notation(), depth, &weights).best_move,
+                        String::new(),
+                    ),
+                },
+            };
+            if let Some(m) = chosen {
+                /* the flag can fall while the engine searches */
+                match game.play(&m.uci) {
+                    Ok(_) => (),
+                    Err(MoveError::GameOver) => {
+                        print_outcome(&game);
+                        break;
+                    }
+                    Err(e) => panic!("the engine chose {}, which is illegal: {:?}", m.uci, e),
+                }
+                println!("{} plays {}{}", color_name(turn), m.san, note);
+                print_board(&game, options);
+                print_outcome(&game);
+            } else {
+                /* no move to play, and asking again would give the same answer */
+                print_outcome(&game);
+                break;
+            }
+            continue;
+        }
 
-    println!("{:?}", board.table[0][4]);
-    // 0, 4 => (1, 5) i människans ögon. (rad, kolumn)
+        /* once per position, not again after every command */
+        if shown_for.as_deref() != Some(game.fen().as_str()) && game.result() == GameResult::Ongoing
+        {
+            if let Some(book) = &opening_book {
+                let moves = book.moves(game.notation());
+                if !moves.is_empty() {
+                    println!("Book: {}", describe_book_moves(&moves));
+                }
+            }
+            if let Some(probe) = tablebase.as_ref().and_then(|t| t.probe(game.notation())) {
+                println!("Tablebase: {} to move, {}", color_name(turn), probe);
+            }
+            shown_for = Some(game.fen());
+        }
 
-    /* arg: bräda, intialisera vems tur det är */
-    let mut notat: AlgebraicNotation = Notation::new(board, schackmotor::_WHITE_PIECE);
-    //notat.do_move("Be1xe5");
-    /* följer standardnotation, se Wikipedia. */
-    assert_eq!(notat.do_move("Bc1d2"), true);
-    // returnerar true eller false beroende på utfall
-    //notat.do_move("Ke1xd2");
+        print!("{} to move> ", color_name(turn));
+        io::stdout().flush().unwrap();
+        let line = match lines.next() {
+            Some(Ok(line)) => line,
+            _ => break,
+        };
 
-    //let x = schackmotor::Pawn {};
+        match line.trim() {
+            "" => (),
+            "help" => println!("{}", HELP),
+            "quit" | "exit" => break,
+            "moves" => {
+                let sans: Vec<String> = game
+                    .notation()
+                    .legal_moves()
+                    .into_iter()
+                    .map(|m| m.san)
+                    .collect();
+                println!("{}", sans.join(" "));
+            }
+            "book" => match &opening_book {
+                Some(book) => {
+                    let moves = book.moves(game.notation());
+                    if moves.is_empty() {
+                        println!("Out of book");
+                    } else {
+                        println!("{}", describe_book_moves(&moves));
+                    }
+                }
+                None => println!("No book is open, start with --book FILE"),
+            },
+            "undo" => {
+                /* against the engine, take back its reply as well */
+                let plies = if engine_color.is_some() && game.moves().len() >= 2 {
+                    2
+                } else {
+                    1
+                };
+                for _ in 0..plies {
+                    match game.undo() {
+                        Some(m) => println!("Took back {}", m.san),
+                        None => println!("Nothing to take back"),
+                    }
+                }
+                print_board(&game, options);
+            }
+            "fen" => println!("{}", game.fen()),
+            "pgn" => print!("{}", game.to_pgn()),
+            "flip" => {
+                options.flipped = !options.flipped;
+                print_board(&game, options);
+            }
+            "resign" => {
+                game.resign(turn);
+                print_outcome(&game);
+            }
+            "draw" => {
+                if game.offer_draw(turn) {
+                    print_outcome(&game);
+                } else if let Some(c) = engine_color {
+                    /* the engine accepts when it thinks it is worse */
+                    let score = engine::search_weighted(game.notation(), 1, &weights).score;
+                    let engine_score = if c == turn { score } else { -score };
+                    if engine_score < -100 && game.offer_draw(c) {
+                        print_outcome(&game);
+                    } else {
+                        println!("The engine declines the draw");
+                    }
+                } else {
+                    println!(
+                        "{} offers a draw, {} can accept by typing draw",
+                        color_name(turn),
+                        color_name(1 - turn)
+                    );
+                }
+            }
+            input => match game.play(input) {
+                Ok(m) => {
+                    println!("{} plays {}", color_name(turn), m.san);
+                    print_board(&game, options);
+                    print_outcome(&game);
+                }
+                Err(e) => println!("Rejected {}: {}", input, e),
+            },
+        }
+    }
 }
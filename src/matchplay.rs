@@ -0,0 +1,762 @@
+/*
+ * Engine matches: two players take turns with both colors from a list of
+ * openings, and the results are summed up as wins, draws and losses for
+ * the first player with an Elo difference and its 95% error bars.
+ *
+ * A player is either this engine with some search limits, its Monte Carlo
+ * tree search, or an external engine speaking UCI, described like
+ *
+ *     depth=3,name=base
+ *     mcts=2000,selection=puct,leaf=eval
+ *     uci=/usr/local/bin/stockfish,movetime=100
+ *
+ * Games run on several threads at once, each with its own players.
+ */
+
+use crate::book::{Book, BookMode};
+use crate::datagen::Rng;
+use crate::engine::{self, SearchLimits, MATE_SCORE};
+use crate::evaluation::Weights;
+use crate::game::{has_mating_material, Game, GameResult, Termination};
+use crate::mcts::{self, MctsConfig};
+use crate::tablebase::Tablebase;
+use crate::*;
+use std::io::{self, BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn player_specs() {
+        let spec = PlayerSpec::parse("depth=2,nodes=500,name=base").unwrap();
+        assert_eq!(spec.name, "base");
+        assert_eq!(spec.limits.depth, 2);
+        assert_eq!(spec.limits.nodes, Some(500));
+        assert_eq!(spec.command, None);
+        assert_eq!(spec.book, None);
+        assert_eq!(spec.book_mode, BookMode::Best);
+        let spec = PlayerSpec::parse("depth=2,book=openings.bin,book-mode=weighted").unwrap();
+        assert_eq!(spec.book, Some("openings.bin".to_string()));
+        assert_eq!(spec.book_mode, BookMode::Weighted);
+        assert!(PlayerSpec::parse("book-mode=random").is_err());
+        let spec = PlayerSpec::parse("weights=tuned.txt").unwrap();
+        assert_eq!(spec.weights, Some("tuned.txt".to_string()));
+        let missing = PlayerSpec::parse("weights=/no/such/weights.txt").unwrap();
+        assert!(missing.spawn().is_err());
+        let spec = PlayerSpec::parse("tablebase=/no/such/tables").unwrap();
+        assert_eq!(spec.tablebase, Some("/no/such/tables".to_string()));
+        assert!(spec.spawn().is_err());
+        let spec = PlayerSpec::parse("mcts=200,leaf=eval,name=tree").unwrap();
+        let mcts = spec.mcts.unwrap();
+        assert_eq!((mcts.iterations, mcts.leaf), (200, mcts::Leaf::Evaluation));
+        assert_eq!(
+            PlayerSpec::parse("selection=puct")
+                .unwrap()
+                .mcts
+                .unwrap()
+                .iterations,
+            1000
+        );
+        assert!(PlayerSpec::parse("mcts=200,leaf=deep").is_err());
+        let spec = PlayerSpec::parse("uci=/bin/engine,movetime=50").unwrap();
+        assert_eq!(spec.name, "/bin/engine");
+        assert_eq!(spec.limits.time, Some(Duration::from_millis(50)));
+        assert!(PlayerSpec::parse("depth=x").is_err());
+        assert!(PlayerSpec::parse("color=red").is_err());
+
+        assert_eq!(
+            uci_score("info depth 5 score cp -35 nodes 100 pv e2e4"),
+            Some(-35)
+        );
+        assert_eq!(uci_score("info depth 9 score mate 3"), Some(MATE_SCORE - 5));
+        assert_eq!(
+            uci_score("info depth 9 score mate -2"),
+            Some(-MATE_SCORE + 4)
+        );
+        assert_eq!(uci_score("info string hello"), None);
+    }
+
+    #[test]
+    fn elo_and_error_bars() {
+        let report = MatchReport {
+            first: "a".to_string(),
+            second: "b".to_string(),
+            games: vec![],
+        };
+        assert_eq!(report.elo(), None);
+        assert!((elo_from_score(0.75) - 190.85).abs() < 0.01);
+        assert!(elo_from_score(0.5).abs() < 1e-9);
+        let (elo, error) = elo_with_error(60, 20, 20).unwrap();
+        assert!((elo - 147.19).abs() < 0.01, "{}", elo);
+        assert!(error > 50.0 && error < 90.0, "{}", error);
+    }
+
+    #[test]
+    fn short_match() {
+        let config = MatchConfig {
+            first: PlayerSpec::parse("depth=1,name=one").unwrap(),
+            second: PlayerSpec::parse("depth=1,name=other").unwrap(),
+            games: 4,
+            openings: vec![
+                fen::START_FEN.to_string(),
+                "4k3/8/8/8/8/8/4P3/4K3 w - - 0 1".to_string(),
+            ],
+            concurrency: 2,
+            adjudication: Adjudication {
+                max_plies: Some(16),
+                ..Adjudication::default()
+            },
+        };
+        let report = run_match(&config).unwrap();
+        assert_eq!(report.games.len(), 4);
+        let (wins, draws, losses) = report.wins_draws_losses();
+        assert_eq!(wins + draws + losses, 4);
+        assert!(report.games[0].first_white && !report.games[1].first_white);
+        assert_eq!(report.games[2].game.start().to_fen(), config.openings[1]);
+        assert_eq!(report.games[1].game.white, "other");
+        assert_eq!(report.games.iter().filter(|g| g.pair == 1).count(), 2);
+
+        let pgn = report.to_pgn();
+        assert_eq!(pgn.matches("[Event \"one vs other\"]").count(), 4);
+        assert!(pgn.contains("[Round \"4\"]"));
+        assert!(report.summary().starts_with("one vs other: "));
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlayerSpec {
+    pub name: String,
+    pub limits: SearchLimits,
+    /* an external UCI engine to launch instead of searching ourselves */
+    pub command: Option<String>,
+    /* a Polyglot book our engine plays from, and how it picks a move */
+    pub book: Option<String>,
+    pub book_mode: BookMode,
+    /* evaluation weights for our engine, as the tuner writes them */
+    pub weights: Option<String>,
+    /* a directory of endgame tables our engine plays perfectly from */
+    pub tablebase: Option<String>,
+    /* tree search instead of alpha-beta, for our engine */
+    pub mcts: Option<MctsConfig>,
+}
+
+impl PlayerSpec {
+    pub fn engine(name: &str, limits: SearchLimits) -> PlayerSpec {
+        PlayerSpec {
+            name: name.to_string(),
+            limits,
+            command: None,
+            book: None,
+            book_mode: BookMode::Best,
+            weights: None,
+            tablebase: None,
+            mcts: None,
+        }
+    }
+
+    /* Comma separated key=value:
+     *   uci=COMMAND runs an external engine instead of ours
+     *   name, depth, nodes and movetime in ms
+     *   book=FILE plays from a Polyglot book
+     *   book-mode=best|weighted|uniform picks the book move
+     *   weights=FILE evaluates with tuned weights
+     *   tablebase=DIR plays endgames from tables
+     *   mcts=ITERATIONS searches by tree search, which takes the
+     *     options of MctsConfig::set as well */
+    pub fn parse(text: &str) -> Result<PlayerSpec, String> {
+        let mut spec = PlayerSpec::engine("schackmotor", SearchLimits::depth(3));
+        let mut name = None;
+        for part in text.split(',').filter(|p| !p.is_empty()) {
+            let (key, value) = part
+                .split_once('=')
+                .ok_or_else(|| format!("expected key=value, got '{}'", part))?;
+            let number = || {
+                value
+                    .parse::<u64>()
+                    .map_err(|_| format!("{} needs a number, got '{}'", key, value))
+            };
+            match key {
+                "uci" => spec.command = Some(value.to_string()),
+                "book" => spec.book = Some(value.to_string()),
+                "book-mode" => {
+                    spec.book_mode = BookMode::parse(value)
+                        .ok_or_else(|| format!("unknown book mode '{}'", value))?
+                }
+                "weights" => spec.weights = Some(value.to_string()),
+                "tablebase" => spec.tablebase = Some(value.to_string()),
+                "name" => name = Some(value.to_string()),
+                "depth" => spec.limits.depth = number()? as usize,
+                "nodes" => spec.limits.nodes = Some(number()?),
+                "movetime" => spec.limits.time = Some(Duration::from_millis(number()?)),
+                "mcts" => spec
+                    .mcts
+                    .get_or_insert_with(MctsConfig::default)
+                    .set("iterations", value)?,
+                "selection" | "leaf" | "exploration" | "plies" | "seed" => spec
+                    .mcts
+                    .get_or_insert_with(MctsConfig::default)
+                    .set(key, value)?,
+                _ => return Err(format!("unknown player option '{}'", key)),
+            }
+        }
+        if let Some(config) = spec.mcts.as_mut() {
+            config.time = spec.limits.time;
+        }
+        spec.name = name.or_else(|| spec.command.clone()).unwrap_or(spec.name);
+        Ok(spec)
+    }
+
+    pub fn spawn(&self) -> io::Result<Box<dyn Player>> {
+        Ok(match &self.command {
+            Some(command) => Box::new(UciPlayer::launch(&self.name, command, self.limits)?),
+            None => Box::new(EnginePlayer {
+                name: self.name.clone(),
+                limits: self.limits,
+                mcts: self.mcts,
+                weights: match &self.weights {
+                    Some(path) => Weights::open(path).map_err(io::Error::other)?,
+                    None => Weights::default(),
+                },
+                tablebase: match &self.tablebase {
+                    Some(dir) => {
+                        Some(Tablebase::open(dir).map_err(|e| io::Error::other(e.to_string()))?)
+                    }
+                    None => None,
+                },
+                book: match &self.book {
+                    Some(path) => {
+                        Some(Book::open(path).map_err(|e| io::Error::other(e.to_string()))?)
+                    }
+                    None => None,
+                },
+                book_mode: self.book_mode,
+                /* the same book lines every match, for a given concurrency */
+                rng: Rng::new(0),
+            }),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Choice {
+    pub uci: String,
+    /* centipawns from the mover's point of view, when the player says */
+    pub score: Option<i32>,
+}
+
+pub trait Player: Send {
+    fn name(&self) -> &str;
+    fn new_game(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+    fn choose(&mut self, game: &Game) -> io::Result<Choice>;
+}
+
+pub struct EnginePlayer {
+    name: String,
+    limits: SearchLimits,
+    mcts: Option<MctsConfig>,
+    weights: Weights,
+    tablebase: Option<Tablebase>,
+    book: Option<Book>,
+    book_mode: BookMode,
+    rng: Rng,
+}
+
+impl Player for EnginePlayer {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn choose(&mut self, game: &Game) -> io::Result<Choice> {
+        let (mode, rng) = (self.book_mode, &mut self.rng);
+        let from_book = self
+            .book
+            .as_ref()
+            .and_then(|b| b.choose(game.notation(), mode, rng));
+        if let Some(m) = from_book {
+            return Ok(Choice {
+                uci: m.legal.uci,
+                score: None,
+            });
+        }
+        let from_tablebase = self
+            .tablebase
+            .as_ref()
+            .and_then(|t| t.best_move(game.notation()));
+        if let Some((m, probe)) = from_tablebase {
+            return Ok(Choice {
+                uci: m.uci,
+                score: Some(probe.score()),
+            });
+        }
+        if let Some(config) = &self.mcts {
+            let result = mcts::search(game.notation(), config, &self.weights);
+            return match &result.best_move {
+                Some(m) => Ok(Choice {
+                    uci: m.uci.clone(),
+                    score: Some(result.score()),
+                }),
+                None => Err(io::Error::other("no legal move")),
+            };
+        }
+        let result = engine::search_with(game.notation(), &self.limits, &self.weights);
+        match result.best_move {
+            Some(m) => Ok(Choice {
+                uci: m.uci,
+                score: Some(result.score),
+            }),
+            None => Err(io::Error::other("no legal move")),
+        }
+    }
+}
+
+/* An engine in another process, spoken to over its stdin and stdout */
+pub struct UciPlayer {
+    name: String,
+    limits: SearchLimits,
+    child: Child,
+    input: ChildStdin,
+    output: BufReader<ChildStdout>,
+}
+
+/* The score of an info line, mates counted like our own search does */
+fn uci_score(line: &str) -> Option<i32> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let at = words.iter().position(|w| *w == "score")?;
+    let value: i32 = words.get(at + 2)?.parse().ok()?;
+    match *words.get(at + 1)? {
+        "cp" => Some(value),
+        "mate" if value > 0 => Some(MATE_SCORE - (2 * value - 1)),
+        "mate" => Some(-MATE_SCORE - 2 * value),
+        _ => None,
+    }
+}
+
+impl UciPlayer {
+    pub fn launch(name: &str, command: &str, limits: SearchLimits) -> io::Result<UciPlayer> {
+        let mut words = command.split_whitespace();
+        let program = words
+            .next()
+            .ok_or_else(|| io::Error::other("empty engine command"))?;
+        let mut child = Command::new(program)
+            .args(words)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+        let input = child.stdin.take().unwrap();
+        let output = BufReader::new(child.stdout.take().unwrap());
+        let mut player = UciPlayer {
+            name: name.to_string(),
+            limits,
+            child,
+            input,
+            output,
+        };
+        player.send("uci")?;
+        player.read_until("uciok")?;
+        Ok(player)
+    }
+
+    fn send(&mut self, line: &str) -> io::Result<()> {
+        writeln!(self.input, "{}", line)?;
+        self.input.flush()
+    }
+
+    /* Reads lines until one starts with the word, returning them all */
+    fn read_until(&mut self, word: &str) -> io::Result<Vec<String>> {
+        let mut lines = vec![];
+        loop {
+            let mut line = String::new();
+            if self.output.read_line(&mut line)? == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    format!("{} quit while we waited for {}", self.name, word),
+                ));
+            }
+            let line = line.trim().to_string();
+            let done = line.split_whitespace().next() == Some(word);
+            lines.push(line);
+            if done {
+                return Ok(lines);
+            }
+        }
+    }
+}
+
+impl Player for UciPlayer {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn new_game(&mut self) -> io::Result<()> {
+        self.send("ucinewgame")?;
+        self.send("isready")?;
+        self.read_until("readyok").map(|_| ())
+    }
+
+    fn choose(&mut self, game: &Game) -> io::Result<Choice> {
+        let moves: Vec<&str> = game.moves().iter().map(|m| m.uci.as_str()).collect();
+        let position = if moves.is_empty() {
+            format!("position fen {}", game.start().to_fen())
+        } else {
+            format!(
+                "position fen {} moves {}",
+                game.start().to_fen(),
+                moves.join(" ")
+            )
+        };
+        self.send(&position)?;
+
+        /* a time limit alone should not be cut short by the default depth */
+        let mut go = match self.limits.time {
+            Some(time) => format!("go movetime {}", time.as_millis()),
+            None => format!("go depth {}", self.limits.depth),
+        };
+        if let Some(nodes) = self.limits.nodes {
+            go.push_str(&format!(" nodes {}", nodes));
+        }
+        self.send(&go)?;
+
+        let lines = self.read_until("bestmove")?;
+        let uci = lines
+            .last()
+            .and_then(|l| l.split_whitespace().nth(1))
+            .ok_or_else(|| io::Error::other("bestmove without a move"))?
+            .to_string();
+        let score = lines.iter().rev().find_map(|l| uci_score(l));
+        Ok(Choice { uci, score })
+    }
+}
+
+impl Drop for UciPlayer {
+    fn drop(&mut self) {
+        let _ = self.send("quit");
+        let _ = self.child.wait();
+    }
+}
+
+/* When to end a game early. Scores are the players' own, which both have
+ * to agree on for the given number of plies in a row. */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Adjudication {
+    /* a draw once this many plies are played */
+    pub max_plies: Option<usize>,
+    /* a win when the score is beyond this many centipawns for so many plies */
+    pub win_score: Option<(i32, usize)>,
+    /* a draw when the score stays within this after the given ply */
+    pub draw_score: Option<(i32, usize, usize)>,
+}
+
+impl Default for Adjudication {
+    fn default() -> Self {
+        Adjudication {
+            max_plies: Some(400),
+            win_score: Some((1000, 6)),
+            draw_score: None,
+        }
+    }
+}
+
+impl Adjudication {
+    /* scores holds one per ply, from white's point of view */
+    pub(crate) fn verdict(&self, game: &Game, scores: &[Option<i32>]) -> Option<GameResult> {
+        let board = game.notation().board();
+        if !has_mating_material(board, _WHITE_PIECE) && !has_mating_material(board, _BLACK_PIECE) {
+            return Some(GameResult::Draw);
+        }
+        if game.notation().state().halfmove_clock >= 100 {
+            return Some(GameResult::Draw);
+        }
+        if self.max_plies.is_some_and(|n| scores.len() >= n) {
+            return Some(GameResult::Draw);
+        }
+
+        let last = |plies: usize| -> Option<Vec<i32>> {
+            if plies == 0 || scores.len() < plies {
+                return None;
+            }
+            scores[scores.len() - plies..].iter().copied().collect()
+        };
+        if let Some((threshold, plies)) = self.win_score {
+            if let Some(recent) = last(plies) {
+                if recent.iter().all(|s| *s >= threshold) {
+                    return Some(GameResult::WhiteWins);
+                }
+                if recent.iter().all(|s| *s <= -threshold) {
+                    return Some(GameResult::BlackWins);
+                }
+            }
+        }
+        if let Some((threshold, plies, from_ply)) = self.draw_score {
+            if scores.len() >= from_ply {
+                if let Some(recent) = last(plies) {
+                    if recent.iter().all(|s| s.abs() <= threshold) {
+                        return Some(GameResult::Draw);
+                    }
+                }
+            }
+        }
+        None
+    }
+}
+
+/* Plays one game from the opening, the players already set up */
+pub fn play_game(
+    white: &mut dyn Player,
+    black: &mut dyn Player,
+    opening: &str,
+    adjudication: &Adjudication,
+) -> Result<Game, String> {
+    let notation = AlgebraicNotation::from_fen(opening).map_err(|e| e.to_string())?;
+    let mut game = Game::from_notation(notation);
+    game.white = white.name().to_string();
+    game.black = black.name().to_string();
+    white.new_game().map_err(|e| e.to_string())?;
+    black.new_game().map_err(|e| e.to_string())?;
+
+    let mut scores = vec![];
+    while game.result() == GameResult::Ongoing {
+        if let Some(result) = adjudication.verdict(&game, &scores) {
+            game.finish(result, Termination::Adjudication);
+            break;
+        }
+        let turn = game.notation().turn();
+        let player: &mut dyn Player = if turn == _WHITE_PIECE {
+            &mut *white
+        } else {
+            &mut *black
+        };
+        /* an engine that fails or plays an illegal move loses */
+        let played = player
+            .choose(&game)
+            .ok()
+            .and_then(|choice| game.play(&choice.uci).ok().map(|_| choice.score));
+        match played {
+            Some(score) => scores.push(score.map(|s| if turn == _WHITE_PIECE { s } else { -s })),
+            None => game.finish(GameResult::win_for(1 - turn), Termination::Adjudication),
+        }
+    }
+    Ok(game)
+}
+
+#[derive(Debug, Clone)]
+pub struct MatchConfig {
+    pub first: PlayerSpec,
+    pub second: PlayerSpec,
+    pub games: usize,
+    /* FENs, each played twice in a row with the colors swapped */
+    pub openings: Vec<String>,
+    pub concurrency: usize,
+    pub adjudication: Adjudication,
+}
+
+#[derive(Debug, Clone)]
+pub struct MatchGame {
+    pub round: usize,
+    /* the two games of a pair share their opening */
+    pub pair: usize,
+    pub first_white: bool,
+    pub game: Game,
+}
+
+impl MatchGame {
+    /* 1, 1/2 or 0 for the first player, in half points */
+    pub fn half_points(&self) -> Option<u32> {
+        let first_won = if self.first_white {
+            GameResult::WhiteWins
+        } else {
+            GameResult::BlackWins
+        };
+        match self.game.result() {
+            GameResult::Ongoing => None,
+            GameResult::Draw => Some(1),
+            r if r == first_won => Some(2),
+            _ => Some(0),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MatchReport {
+    pub first: String,
+    pub second: String,
+    /* in the order they were scheduled */
+    pub games: Vec<MatchGame>,
+}
+
+pub fn elo_from_score(score: f64) -> f64 {
+    -400.0 * (1.0 / score - 1.0).log10()
+}
+
+/* The Elo difference and half the width of its 95% confidence interval */
+pub fn elo_with_error(wins: usize, draws: usize, losses: usize) -> Option<(f64, f64)> {
+    let n = (wins + draws + losses) as f64;
+    if n == 0.0 {
+        return None;
+    }
+    let score = (wins as f64 + draws as f64 / 2.0) / n;
+    let variance = (wins as f64 * (1.0 - score).powi(2)
+        + draws as f64 * (0.5 - score).powi(2)
+        + losses as f64 * score.powi(2))
+        / n;
+    let margin = 1.96 * (variance / n).sqrt();
+    let bound = |s: f64| elo_from_score(s.clamp(1e-9, 1.0 - 1e-9));
+    Some((
+        bound(score),
+        (bound(score + margin) - bound(score - margin)) / 2.0,
+    ))
+}
+
+impl MatchReport {
+    pub fn wins_draws_losses(&self) -> (usize, usize, usize) {
+        let mut wdl = (0, 0, 0);
+        for g in self.games.iter() {
+            match g.half_points() {
+                Some(2) => wdl.0 += 1,
+                Some(1) => wdl.1 += 1,
+                Some(_) => wdl.2 += 1,
+                None => (),
+            }
+        }
+        wdl
+    }
+
+    /* Of the first player over the second, with its error bars */
+    pub fn elo(&self) -> Option<(f64, f64)> {
+        let (w, d, l) = self.wins_draws_losses();
+        elo_with_error(w, d, l)
+    }
+
+    pub fn summary(&self) -> String {
+        let (w, d, l) = self.wins_draws_losses();
+        let elo = match self.elo() {
+            Some((elo, error)) => format!("Elo {:+.1} +/- {:.1}", elo, error),
+            None => "no games".to_string(),
+        };
+        format!(
+            "{} vs {}: +{} ={} -{} ({:.1}/{}), {}",
+            self.first,
+            self.second,
+            w,
+            d,
+            l,
+            w as f64 + d as f64 / 2.0,
+            w + d + l,
+            elo
+        )
+    }
+
+    pub fn to_pgn(&self) -> String {
+        self.games
+            .iter()
+            .map(|g| g.game.to_pgn())
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+}
+
+pub fn run_match(config: &MatchConfig) -> Result<MatchReport, String> {
+    run_match_with(config, |_| ())
+}
+
+/* Like run_match, calling back with every game as it finishes */
+pub fn run_match_with(
+    config: &MatchConfig,
+    on_game: impl Fn(&MatchGame) + Sync,
+) -> Result<MatchReport, String> {
+    run_match_until(config, on_game, || false)
+}
+
+/* No new games are started once stop returns true; those being played
+ * are finished and reported */
+pub fn run_match_until(
+    config: &MatchConfig,
+    on_game: impl Fn(&MatchGame) + Sync,
+    stop: impl Fn() -> bool + Sync,
+) -> Result<MatchReport, String> {
+    let openings = if config.openings.is_empty() {
+        vec![fen::START_FEN.to_string()]
+    } else {
+        config.openings.clone()
+    };
+    let event = format!("{} vs {}", config.first.name, config.second.name);
+    let next = AtomicUsize::new(0);
+    let finished: Mutex<Vec<MatchGame>> = Mutex::new(vec![]);
+    let failure: Mutex<Option<String>> = Mutex::new(None);
+
+    thread::scope(|scope| {
+        for _ in 0..config.concurrency.clamp(1, config.games.max(1)) {
+            scope.spawn(|| {
+                let spawned = config
+                    .first
+                    .spawn()
+                    .and_then(|first| Ok((first, config.second.spawn()?)));
+                let (mut first, mut second) = match spawned {
+                    Ok(players) => players,
+                    Err(e) => {
+                        *failure.lock().unwrap() = Some(e.to_string());
+                        return;
+                    }
+                };
+                loop {
+                    let round = next.fetch_add(1, Ordering::SeqCst);
+                    if round >= config.games || failure.lock().unwrap().is_some() || stop() {
+                        return;
+                    }
+                    let pair = round / 2;
+                    let first_white = round.is_multiple_of(2);
+                    let opening = &openings[pair % openings.len()];
+                    let played = if first_white {
+                        play_game(
+                            first.as_mut(),
+                            second.as_mut(),
+                            opening,
+                            &config.adjudication,
+                        )
+                    } else {
+                        play_game(
+                            second.as_mut(),
+                            first.as_mut(),
+                            opening,
+                            &config.adjudication,
+                        )
+                    };
+                    match played {
+                        Ok(mut game) => {
+                            game.event = event.clone();
+                            game.round = (round + 1).to_string();
+                            let record = MatchGame {
+                                round,
+                                pair,
+                                first_white,
+                                game,
+                            };
+                            on_game(&record);
+                            finished.lock().unwrap().push(record);
+                        }
+                        Err(e) => *failure.lock().unwrap() = Some(e),
+                    }
+                }
+            });
+        }
+    });
+
+    if let Some(e) = failure.into_inner().unwrap() {
+        return Err(e);
+    }
+    let mut games = finished.into_inner().unwrap();
+    games.sort_by_key(|g| g.round);
+    Ok(MatchReport {
+        first: config.first.name.clone(),
+        second: config.second.name.clone(),
+        games,
+    })
+}
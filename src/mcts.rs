@@ -0,0 +1,495 @@
+/*
+ * Monte Carlo tree search, as an alternative to alpha-beta. Every
+ * iteration walks down the tree to a position not seen before, values
+ * it, and counts the value in every position on the way back up. The
+ * moves visited most often at the root are the ones the search believes
+ * in, and their visit counts and win rates say how strongly.
+ *
+ * Moves are chosen on the way down by UCT, the win rate plus a bonus for
+ * moves tried rarely, or by PUCT, where the bonus is shared out by a
+ * prior from the static evaluation of each move. A new position is
+ * valued by a random playout, which is cut off after some plies and
+ * valued by the evaluator there, or by the evaluator right away, with
+ * whatever weights the search is given.
+ *
+ * Values are between 0 and 1, a win counting 1 and a draw one half.
+ */
+
+use crate::datagen::Rng;
+use crate::engine;
+use crate::evaluation::{self, Weights};
+use crate::game::has_mating_material;
+use crate::movegen::LegalMove;
+use crate::tuning::sigmoid;
+use crate::*;
+use std::time::{Duration, Instant};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn position(fen: &str) -> AlgebraicNotation {
+        AlgebraicNotation::from_fen(fen).unwrap()
+    }
+
+    #[test]
+    fn configs() {
+        let config = MctsConfig::parse("iterations=500,selection=puct,leaf=eval").unwrap();
+        assert_eq!(config.iterations, 500);
+        assert_eq!(config.selection, Selection::Puct);
+        assert_eq!(config.leaf, Leaf::Evaluation);
+        let config = MctsConfig::parse("exploration=0.5,plies=20,seed=3").unwrap();
+        assert_eq!(config.selection, Selection::Uct);
+        assert_eq!((config.exploration, config.playout_plies), (0.5, 20));
+        assert_eq!(config.seed, 3);
+        assert!(MctsConfig::parse("leaf=oracle").is_err());
+        assert!(MctsConfig::parse("iterations=many").is_err());
+        assert!(MctsConfig::parse("depth=3").is_err());
+    }
+
+    #[test]
+    fn finds_mate_by_playouts() {
+        let start = position("6k1/5ppp/8/8/8/8/5PPP/R5K1 w - - 0 1");
+        let config = MctsConfig {
+            iterations: 300,
+            playout_plies: 10,
+            ..MctsConfig::default()
+        };
+        let result = search(&start, &config, &Weights::default());
+        assert_eq!(result.best_move.as_ref().unwrap().san, "Ra8#");
+        assert_eq!(result.iterations, 300);
+        let visits: u32 = result.moves.iter().map(|m| m.visits).sum();
+        assert_eq!(visits, 300);
+        assert_eq!(result.moves.len(), start.legal_moves().len());
+        /* most visited first, and a mate is a sure win */
+        assert!(result.moves.windows(2).all(|w| w[0].visits >= w[1].visits));
+        assert_eq!(result.moves[0].win_rate, 1.0);
+        assert!(result.score() > engine::MATE_SCORE / 2);
+
+        /* the same seed searches the same way */
+        let again = search(&start, &config, &Weights::default());
+        let counts = |r: &MctsResult| -> Vec<u32> { r.moves.iter().map(|m| m.visits).collect() };
+        assert_eq!(counts(&result), counts(&again));
+    }
+
+    #[test]
+    fn takes_the_queen_by_evaluation() {
+        let start = position("7k/8/8/q7/8/8/8/R5K1 w - - 0 1");
+        for selection in [Selection::Uct, Selection::Puct] {
+            let config = MctsConfig {
+                iterations: 200,
+                selection,
+                leaf: Leaf::Evaluation,
+                ..MctsConfig::default()
+            };
+            let result = search(&start, &config, &Weights::default());
+            assert_eq!(
+                result.best_move.as_ref().unwrap().san,
+                "Rxa5",
+                "{:?}",
+                selection
+            );
+            assert!(result.moves[0].win_rate > 0.9, "{}", result);
+            assert!(result.to_string().starts_with("Rxa5 "), "{}", result);
+        }
+    }
+
+    #[test]
+    fn no_moves_no_search() {
+        let mated = position("R5k1/5ppp/8/8/8/8/8/6K1 b - - 0 1");
+        let result = search(&mated, &MctsConfig::default(), &Weights::default());
+        assert!(result.best_move.is_none() && result.moves.is_empty());
+        assert_eq!(result.iterations, 0);
+
+        /* a drawn root still gets its moves looked at */
+        let bare = position("8/8/4k3/8/8/4K3/8/8 w - - 0 1");
+        let config = MctsConfig {
+            iterations: 50,
+            ..MctsConfig::default()
+        };
+        let result = search(&bare, &config, &Weights::default());
+        assert!(result.best_move.is_some());
+        assert_eq!(result.moves.len(), bare.legal_moves().len());
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Selection {
+    /* win rate plus c * sqrt(ln N / n) */
+    Uct,
+    /* win rate plus c * prior * sqrt(N) / (1 + n) */
+    Puct,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Leaf {
+    /* random moves to the end or to the ply limit */
+    Playout,
+    /* the static evaluation of the new position */
+    Evaluation,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MctsConfig {
+    pub iterations: u64,
+    pub time: Option<Duration>,
+    pub exploration: f64,
+    pub selection: Selection,
+    pub leaf: Leaf,
+    /* after so many random plies the evaluator values the playout */
+    pub playout_plies: usize,
+    pub seed: u64,
+}
+
+impl Default for MctsConfig {
+    fn default() -> Self {
+        MctsConfig {
+            iterations: 1000,
+            time: None,
+            exploration: 1.4,
+            selection: Selection::Uct,
+            leaf: Leaf::Playout,
+            playout_plies: 40,
+            seed: 0,
+        }
+    }
+}
+
+impl MctsConfig {
+    /* One key=value: iterations, movetime in ms, exploration,
+     * selection=uct|puct, leaf=playout|eval, plies or seed */
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let number = || {
+            value
+                .parse::<u64>()
+                .map_err(|_| format!("{} needs a number, got '{}'", key, value))
+        };
+        match (key, value) {
+            ("iterations", _) => self.iterations = number()?,
+            ("movetime", _) => self.time = Some(Duration::from_millis(number()?)),
+            ("exploration", _) => {
+                self.exploration = value
+                    .parse()
+                    .map_err(|_| format!("exploration needs a number, got '{}'", value))?
+            }
+            ("selection", "uct") => self.selection = Selection::Uct,
+            ("selection", "puct") => self.selection = Selection::Puct,
+            ("leaf", "playout") => self.leaf = Leaf::Playout,
+            ("leaf", "eval") => self.leaf = Leaf::Evaluation,
+            ("plies", _) => self.playout_plies = number()? as usize,
+            ("seed", _) => self.seed = number()?,
+            ("selection", _) | ("leaf", _) => {
+                return Err(format!("unknown {} '{}'", key, value));
+            }
+            _ => return Err(format!("unknown search option '{}'", key)),
+        }
+        Ok(())
+    }
+
+    /* Comma separated key=value, as set takes them */
+    pub fn parse(text: &str) -> Result<MctsConfig, String> {
+        let mut config = MctsConfig::default();
+        for part in text.split(',').filter(|p| !p.is_empty()) {
+            let (key, value) = part
+                .split_once('=')
+                .ok_or_else(|| format!("expected key=value, got '{}'", part))?;
+            config.set(key, value)?;
+        }
+        Ok(config)
+    }
+}
+
+/* What the search found out about one move at the root */
+#[derive(Debug, Clone, PartialEq)]
+pub struct RootStats {
+    pub legal: LegalMove,
+    pub visits: u32,
+    /* for the side playing the move, 0 to 1 */
+    pub win_rate: f64,
+    pub prior: f64,
+}
+
+impl fmt::Display for RootStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} visits {:.1}%",
+            self.legal.san,
+            self.visits,
+            100.0 * self.win_rate
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MctsResult {
+    /* the most visited move */
+    pub best_move: Option<LegalMove>,
+    /* most visited first */
+    pub moves: Vec<RootStats>,
+    pub iterations: u64,
+}
+
+impl MctsResult {
+    /* The best move's win rate in centipawns, the way the tuner's
+     * sigmoid maps them, for the side to move */
+    pub fn score(&self) -> i32 {
+        let rate = self.moves.first().map_or(0.5, |m| m.win_rate);
+        let limit = engine::MATE_SCORE as f64;
+        if rate >= 1.0 {
+            return engine::MATE_SCORE;
+        }
+        if rate <= 0.0 {
+            return -engine::MATE_SCORE;
+        }
+        (-400.0 * (1.0 / rate - 1.0).log10()).clamp(-limit, limit) as i32
+    }
+}
+
+impl fmt::Display for MctsResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let moves: Vec<String> = self.moves.iter().map(|m| m.to_string()).collect();
+        write!(f, "{}", moves.join(", "))
+    }
+}
+
+struct Node {
+    legal: Option<LegalMove>,
+    position: AlgebraicNotation,
+    children: Vec<usize>,
+    expanded: bool,
+    /* the value for the side to move when the game is over here */
+    terminal: Option<f64>,
+    prior: f64,
+    visits: u32,
+    /* summed values for the side that moved into this position */
+    wins: f64,
+}
+
+impl Node {
+    fn new(legal: Option<LegalMove>, position: AlgebraicNotation) -> Node {
+        Node {
+            legal,
+            position,
+            children: vec![],
+            expanded: false,
+            terminal: None,
+            prior: 1.0,
+            visits: 0,
+            wins: 0.0,
+        }
+    }
+
+    fn win_rate(&self) -> f64 {
+        if self.visits == 0 {
+            0.5
+        } else {
+            self.wins / self.visits as f64
+        }
+    }
+}
+
+/* The static evaluation as a value for the side to move */
+fn evaluation_value(position: &AlgebraicNotation, weights: &Weights) -> f64 {
+    sigmoid(evaluation::evaluate(position, weights) as f64, 1.0)
+}
+
+/* Draws that need no search: the fifty move rule and no mating material */
+fn drawn(position: &AlgebraicNotation) -> bool {
+    position.state().halfmove_clock >= 100
+        || ![_WHITE_PIECE, _BLACK_PIECE]
+            .iter()
+            .any(|color| has_mating_material(position.board(), *color))
+}
+
+/* Random legal moves from position, for the side to move there. A
+ * random candidate is tried and dropped when it turns out illegal, so
+ * no more moves are made than are needed. */
+fn playout(position: &AlgebraicNotation, plies: usize, weights: &Weights, rng: &mut Rng) -> f64 {
+    let mut current = position.clone();
+    for ply in 0..plies {
+        if drawn(&current) {
+            return 0.5;
+        }
+        let mut candidates = current.candidate_moves();
+        let mut next = None;
+        while !candidates.is_empty() {
+            let m = candidates.swap_remove(rng.below(candidates.len()));
+            let mut tried = current.clone();
+            if tried.try_move(&m.notation).is_ok() {
+                next = Some(tried);
+                break;
+            }
+        }
+        let value = match next {
+            Some(next) => {
+                current = next;
+                continue;
+            }
+            None if current.in_check() => 0.0,
+            None => 0.5,
+        };
+        /* the value is for whoever is to move at the end */
+        return if ply.is_multiple_of(2) {
+            value
+        } else {
+            1.0 - value
+        };
+    }
+    let value = evaluation_value(&current, weights);
+    if plies.is_multiple_of(2) {
+        value
+    } else {
+        1.0 - value
+    }
+}
+
+struct Tree<'a> {
+    nodes: Vec<Node>,
+    config: &'a MctsConfig,
+    weights: &'a Weights,
+    rng: Rng,
+}
+
+impl Tree<'_> {
+    fn expand(&mut self, index: usize) {
+        let node = &mut self.nodes[index];
+        node.expanded = true;
+        let successors = node.position.successors();
+        if successors.is_empty() {
+            node.terminal = Some(if node.position.in_check() { 0.0 } else { 0.5 });
+            return;
+        }
+        /* the root still needs moves to choose from */
+        if index > 0 && drawn(&node.position) {
+            node.terminal = Some(0.5);
+            return;
+        }
+        let mut children: Vec<Node> = successors
+            .into_iter()
+            .map(|(legal, next)| Node::new(Some(legal), next))
+            .collect();
+        if self.config.selection == Selection::Puct {
+            /* a softmax over the evaluations after each move, in pawns */
+            let scores: Vec<f64> = children
+                .iter()
+                .map(|c| -evaluation::evaluate(&c.position, self.weights) as f64 / 100.0)
+                .collect();
+            let top = scores.iter().cloned().fold(f64::MIN, f64::max);
+            let weights: Vec<f64> = scores.iter().map(|s| (s - top).exp()).collect();
+            let sum: f64 = weights.iter().sum();
+            for (child, weight) in children.iter_mut().zip(weights) {
+                child.prior = weight / sum;
+            }
+        }
+        let first = self.nodes.len();
+        self.nodes[index].children = (first..first + children.len()).collect();
+        self.nodes.extend(children);
+    }
+
+    fn select(&self, index: usize) -> usize {
+        let node = &self.nodes[index];
+        let c = self.config.exploration;
+        let total = node.visits.max(1) as f64;
+        let score = |child: &Node| match self.config.selection {
+            Selection::Uct if child.visits == 0 => f64::INFINITY,
+            Selection::Uct => child.win_rate() + c * (total.ln() / child.visits as f64).sqrt(),
+            Selection::Puct => {
+                child.win_rate() + c * child.prior * total.sqrt() / (1.0 + child.visits as f64)
+            }
+        };
+        let mut best = node.children[0];
+        let mut best_score = f64::MIN;
+        for &child in &node.children {
+            let s = score(&self.nodes[child]);
+            if s > best_score {
+                best = child;
+                best_score = s;
+            }
+        }
+        best
+    }
+
+    /* One walk down and back up */
+    fn iterate(&mut self) {
+        let mut path = vec![0];
+        let mut index = 0;
+        while self.nodes[index].expanded && !self.nodes[index].children.is_empty() {
+            index = self.select(index);
+            path.push(index);
+        }
+        if !self.nodes[index].expanded {
+            self.expand(index);
+        }
+        let node = &self.nodes[index];
+        /* for the side to move at the end of the path */
+        let mut value = match (node.terminal, self.config.leaf) {
+            (Some(value), _) => value,
+            (None, Leaf::Evaluation) => evaluation_value(&node.position, self.weights),
+            (None, Leaf::Playout) => {
+                let position = node.position.clone();
+                playout(
+                    &position,
+                    self.config.playout_plies,
+                    self.weights,
+                    &mut self.rng,
+                )
+            }
+        };
+        for &index in path.iter().rev() {
+            let node = &mut self.nodes[index];
+            node.visits += 1;
+            node.wins += 1.0 - value;
+            value = 1.0 - value;
+        }
+    }
+}
+
+pub fn search(notation: &AlgebraicNotation, config: &MctsConfig, weights: &Weights) -> MctsResult {
+    let mut tree = Tree {
+        nodes: vec![Node::new(None, notation.clone())],
+        config,
+        weights,
+        rng: Rng::new(config.seed),
+    };
+    tree.expand(0);
+    let deadline = config.time.map(|t| Instant::now() + t);
+    let mut iterations = 0;
+    if !tree.nodes[0].children.is_empty() {
+        while iterations < config.iterations && deadline.is_none_or(|d| Instant::now() < d) {
+            tree.iterate();
+            iterations += 1;
+        }
+    }
+
+    /* SAN with mates marked, which the tree's moves lack */
+    let legal = notation.legal_moves();
+    let mut moves: Vec<RootStats> = tree.nodes[0]
+        .children
+        .iter()
+        .map(|&child| {
+            let node = &tree.nodes[child];
+            let played = node.legal.as_ref().unwrap();
+            RootStats {
+                legal: legal
+                    .iter()
+                    .find(|m| m.uci == played.uci)
+                    .unwrap_or(played)
+                    .clone(),
+                visits: node.visits,
+                win_rate: node.win_rate(),
+                prior: node.prior,
+            }
+        })
+        .collect();
+    moves.sort_by(|a, b| {
+        b.visits
+            .cmp(&a.visits)
+            .then(b.win_rate.total_cmp(&a.win_rate))
+    });
+    MctsResult {
+        best_move: moves.first().map(|m| m.legal.clone()),
+        moves,
+        iterations,
+    }
+}
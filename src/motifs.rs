@@ -0,0 +1,391 @@
+/*
+ * Tactical motifs, for annotating positions and moves.
+ *
+ * Everything is found from who attacks and defends which piece, so the
+ * motifs follow the move rules of the pieces, including pieces from a
+ * definition file. A target counts when it is the king, worth more than
+ * the piece attacking it, or not defended at all.
+ */
+
+use crate::attacks::{attackers_of, is_attacked};
+use crate::movegen::{piece_letter, square_name};
+use crate::pins::{discoveries, found, king_of, uncovered};
+use crate::see::value;
+use crate::*;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn motifs_of(fen: &str, color: usize) -> Vec<Motif> {
+        AlgebraicNotation::from_fen(fen).unwrap().motifs(color)
+    }
+
+    fn named<'a>(motifs: &'a [Motif], name: &str) -> Vec<&'a Motif> {
+        motifs.iter().filter(|m| m.name() == name).collect()
+    }
+
+    #[test]
+    fn forks_pins_and_skewers() {
+        let motifs = motifs_of("r3k3/2N5/8/8/8/8/8/4K3 b - - 0 1", _WHITE_PIECE);
+        let forks = named(&motifs, "fork");
+        assert_eq!(forks.len(), 1);
+        assert_eq!(forks[0].to_string(), "Nc7 forks Ke8 and Ra8");
+
+        let motifs = motifs_of("4k3/8/4n3/8/8/8/8/4RK2 w - - 0 1", _WHITE_PIECE);
+        assert_eq!(named(&motifs, "pin")[0].to_string(), "Re1 pins Ne6 to Ke8");
+
+        let motifs = motifs_of("4r3/8/4k3/8/8/8/8/4RK2 b - - 0 1", _WHITE_PIECE);
+        assert_eq!(
+            named(&motifs, "skewer")[0].to_string(),
+            "Re1 skewers Ke6 and Re8"
+        );
+        assert!(motifs_of("4r3/8/4k3/8/8/8/8/4RK2 b - - 0 1", _BLACK_PIECE).is_empty());
+    }
+
+    #[test]
+    fn defence_motifs() {
+        let motifs = motifs_of("r2r2k1/8/8/3n4/8/4N3/8/R5K1 w - - 0 1", _WHITE_PIECE);
+        let overloaded = named(&motifs, "overloaded defender");
+        assert_eq!(overloaded.len(), 1);
+        assert_eq!(
+            overloaded[0].to_string(),
+            "Rd8 is the only defender of Ra8 and Nd5"
+        );
+
+        let motifs = motifs_of("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1", _WHITE_PIECE);
+        assert_eq!(
+            named(&motifs, "back rank")[0].to_string(),
+            "Kg8 is shut in on the back rank by f7, g7 and h7"
+        );
+        /* with an escape square there is no weakness */
+        let motifs = motifs_of("6k1/5pp1/7p/8/8/8/8/R5K1 w - - 0 1", _WHITE_PIECE);
+        assert!(named(&motifs, "back rank").is_empty());
+    }
+
+    #[test]
+    fn motifs_of_a_move() {
+        let notat = AlgebraicNotation::from_fen("r3k3/8/8/3N4/8/8/8/4K3 w - - 0 1").unwrap();
+        let motifs = notat.move_motifs("Nc7+").unwrap();
+        assert_eq!(motifs.len(), 1);
+        assert_eq!(motifs[0].name(), "fork");
+        assert!(notat.move_motifs("Nc8").is_err());
+
+        let notat = AlgebraicNotation::from_fen("3qk3/8/8/8/3N4/8/3R4/4K3 w - - 0 1").unwrap();
+        let motifs = notat.motifs(_WHITE_PIECE);
+        assert_eq!(
+            named(&motifs, "discovered attack")[0].to_string(),
+            "Nd4 moving uncovers Rd2 on Qd8"
+        );
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Motif {
+    Fork {
+        attacker: FoundPiece,
+        targets: Vec<FoundPiece>,
+    },
+    /* the piece in front can not move without exposing a more valuable one */
+    Pin {
+        attacker: FoundPiece,
+        pinned: FoundPiece,
+        behind: FoundPiece,
+    },
+    /* the more valuable piece is in front and has to move */
+    Skewer {
+        attacker: FoundPiece,
+        front: FoundPiece,
+        behind: FoundPiece,
+    },
+    DiscoveredAttack {
+        blocker: FoundPiece,
+        attacker: FoundPiece,
+        target: FoundPiece,
+    },
+    /* attacked more often than defended, or a double check */
+    DoubleAttack {
+        target: FoundPiece,
+        attackers: Vec<FoundPiece>,
+    },
+    /* the only defender of more than one attacked piece */
+    Overloaded {
+        defender: FoundPiece,
+        duties: Vec<FoundPiece>,
+    },
+    /* a king that can not leave its back rank past its own pieces */
+    BackRank {
+        king: FoundPiece,
+        blockers: Vec<FoundPiece>,
+    },
+}
+
+impl Motif {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Motif::Fork { .. } => "fork",
+            Motif::Pin { .. } => "pin",
+            Motif::Skewer { .. } => "skewer",
+            Motif::DiscoveredAttack { .. } => "discovered attack",
+            Motif::DoubleAttack { .. } => "double attack",
+            Motif::Overloaded { .. } => "overloaded defender",
+            Motif::BackRank { .. } => "back rank",
+        }
+    }
+}
+
+/* Like SAN: Nc7, or just d5 for a pawn */
+fn piece_name(piece: &FoundPiece) -> String {
+    let letter = if piece.piece == 'P' {
+        String::new()
+    } else {
+        piece.piece.to_string()
+    };
+    format!("{}{}", letter, square_name(piece.position))
+}
+
+/* a, a and b, a, b and c */
+fn list(pieces: &[FoundPiece]) -> String {
+    let names: Vec<String> = pieces.iter().map(piece_name).collect();
+    match names.split_last() {
+        Some((last, [])) => last.clone(),
+        Some((last, rest)) => format!("{} and {}", rest.join(", "), last),
+        None => String::new(),
+    }
+}
+
+impl fmt::Display for Motif {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Motif::Fork { attacker, targets } => {
+                write!(f, "{} forks {}", piece_name(attacker), list(targets))
+            }
+            Motif::Pin {
+                attacker,
+                pinned,
+                behind,
+            } => write!(
+                f,
+                "{} pins {} to {}",
+                piece_name(attacker),
+                piece_name(pinned),
+                piece_name(behind)
+            ),
+            Motif::Skewer {
+                attacker,
+                front,
+                behind,
+            } => write!(
+                f,
+                "{} skewers {} and {}",
+                piece_name(attacker),
+                piece_name(front),
+                piece_name(behind)
+            ),
+            Motif::DiscoveredAttack {
+                blocker,
+                attacker,
+                target,
+            } => write!(
+                f,
+                "{} moving uncovers {} on {}",
+                piece_name(blocker),
+                piece_name(attacker),
+                piece_name(target)
+            ),
+            Motif::DoubleAttack { target, attackers } => write!(
+                f,
+                "{} is attacked by {}",
+                piece_name(target),
+                list(attackers)
+            ),
+            Motif::Overloaded { defender, duties } => write!(
+                f,
+                "{} is the only defender of {}",
+                piece_name(defender),
+                list(duties)
+            ),
+            Motif::BackRank { king, blockers } => write!(
+                f,
+                "{} is shut in on the back rank by {}",
+                piece_name(king),
+                list(blockers)
+            ),
+        }
+    }
+}
+
+fn worth_attacking(target: &FoundPiece, attacker: &FoundPiece, defenders: usize) -> bool {
+    target.piece == 'K' || value(target.piece) > value(attacker.piece) || defenders == 0
+}
+
+fn back_rank(board: &Board, color: usize) -> Option<Motif> {
+    let enemy = 1 - color;
+    let king = found(board, king_of(board, enemy)?)?;
+    let (home_row, next_row) = if enemy == _WHITE_PIECE {
+        (0, 1)
+    } else {
+        (7, 6)
+    };
+    if king.position.0 != home_row {
+        return None;
+    }
+    let heavy = board
+        .table
+        .iter()
+        .flatten()
+        .flatten()
+        .any(|p| p.get_color() == color && "RQ".contains(piece_letter(p.as_ref())));
+    if !heavy {
+        return None;
+    }
+
+    let mut blockers = vec![];
+    let column = king.position.1;
+    for c in column.saturating_sub(1)..=cmp::min(column + 1, 7) {
+        match &board.table[next_row][c] {
+            Some(p) if p.get_color() == enemy => blockers.push(found(board, (next_row, c))?),
+            _ if is_attacked(board, (next_row, c), color) => (),
+            _ => return None,
+        }
+    }
+    if blockers.is_empty() {
+        None
+    } else {
+        Some(Motif::BackRank { king, blockers })
+    }
+}
+
+/* The motifs color has against the other side */
+pub fn motifs(board: &Board, color: usize) -> Vec<Motif> {
+    let enemy = 1 - color;
+    let mut enemies = vec![];
+    for (r, row) in board.table.iter().enumerate() {
+        for (c, piece) in row.iter().enumerate() {
+            match piece {
+                Some(p) if p.get_color() == enemy => enemies.push(found(board, (r, c)).unwrap()),
+                _ => (),
+            }
+        }
+    }
+    /* every enemy piece with its attackers and defenders */
+    let attacked: Vec<(FoundPiece, Vec<FoundPiece>, Vec<FoundPiece>)> = enemies
+        .iter()
+        .map(|t| {
+            (
+                *t,
+                attackers_of(board, t.position, color),
+                attackers_of(board, t.position, enemy),
+            )
+        })
+        .collect();
+    let defenders_of = |square: (usize, usize)| {
+        attacked
+            .iter()
+            .find(|(t, _, _)| t.position == square)
+            .map_or(0, |(_, _, defenders)| defenders.len())
+    };
+    let mut motifs = vec![];
+
+    let mut forks: Vec<(FoundPiece, Vec<FoundPiece>)> = vec![];
+    for (target, attackers, defenders) in attacked.iter() {
+        for attacker in attackers {
+            if !worth_attacking(target, attacker, defenders.len()) {
+                continue;
+            }
+            match forks.iter_mut().find(|(a, _)| a == attacker) {
+                Some((_, targets)) => targets.push(*target),
+                None => forks.push((*attacker, vec![*target])),
+            }
+        }
+    }
+    for (attacker, mut targets) in forks {
+        if targets.len() > 1 {
+            targets.sort_by_key(|t| -value(t.piece));
+            motifs.push(Motif::Fork { attacker, targets });
+        }
+    }
+
+    let squares: Vec<(usize, usize)> = enemies.iter().map(|e| e.position).collect();
+    for (front, attackers, _) in attacked.iter() {
+        for (attacker, behind) in uncovered(board, front.position, color, &squares) {
+            if !attackers.contains(&attacker) {
+                continue;
+            }
+            match value(front.piece).cmp(&value(behind.piece)) {
+                cmp::Ordering::Less => motifs.push(Motif::Pin {
+                    attacker,
+                    pinned: *front,
+                    behind,
+                }),
+                cmp::Ordering::Greater
+                    if worth_attacking(&behind, &attacker, defenders_of(behind.position)) =>
+                {
+                    motifs.push(Motif::Skewer {
+                        attacker,
+                        front: *front,
+                        behind,
+                    })
+                }
+                _ => (),
+            }
+        }
+    }
+
+    for d in discoveries(board, color) {
+        if worth_attacking(&d.target, &d.attacker, defenders_of(d.target.position)) {
+            motifs.push(Motif::DiscoveredAttack {
+                blocker: d.blocker,
+                attacker: d.attacker,
+                target: d.target,
+            });
+        }
+    }
+
+    for (target, attackers, defenders) in attacked.iter() {
+        if attackers.len() > 1 && (target.piece == 'K' || attackers.len() > defenders.len()) {
+            motifs.push(Motif::DoubleAttack {
+                target: *target,
+                attackers: attackers.clone(),
+            });
+        }
+    }
+
+    let mut duties: Vec<(FoundPiece, Vec<FoundPiece>)> = vec![];
+    for (target, attackers, defenders) in attacked.iter() {
+        if attackers.is_empty() || defenders.len() != 1 || target.piece == 'K' {
+            continue;
+        }
+        match duties.iter_mut().find(|(d, _)| *d == defenders[0]) {
+            Some((_, guarded)) => guarded.push(*target),
+            None => duties.push((defenders[0], vec![*target])),
+        }
+    }
+    for (defender, mut duties) in duties {
+        if duties.len() > 1 {
+            duties.sort_by_key(|t| -value(t.piece));
+            motifs.push(Motif::Overloaded { defender, duties });
+        }
+    }
+
+    motifs.extend(back_rank(board, color));
+    motifs
+}
+
+impl AlgebraicNotation {
+    pub fn motifs(&self, color: usize) -> Vec<Motif> {
+        motifs(&self.board, color)
+    }
+
+    /* The motifs a move creates for the side making it */
+    pub fn move_motifs(&self, input: &str) -> Result<Vec<Motif>, MoveError> {
+        let before = self.motifs(self.turn);
+        let mut next = self.clone();
+        next.play_move(input)?;
+        Ok(next
+            .motifs(self.turn)
+            .into_iter()
+            .filter(|m| !before.contains(m))
+            .collect())
+    }
+}
@@ -0,0 +1,531 @@
+/*
+ * Legal moves and standard algebraic notation (SAN), built on top of
+ * theory_valid_move and try_move.
+ */
+
+use crate::*;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn start_position() -> AlgebraicNotation {
+        let mut board = Board::new();
+        board.populate_board();
+        Notation::new(board, _WHITE_PIECE)
+    }
+
+    #[test]
+    fn twenty_moves_from_start() {
+        let notat = start_position();
+        let moves = notat.legal_moves();
+        assert_eq!(moves.len(), 20);
+        assert!(moves.iter().any(|m| m.san == "Nf3" && m.uci == "g1f3"));
+        assert!(moves.iter().any(|m| m.san == "e4" && m.notation == "Pe2e4"));
+    }
+
+    #[test]
+    fn san_input() {
+        let mut notat = start_position();
+        assert_eq!(notat.play_move("e4").unwrap().uci, "e2e4");
+        assert_eq!(notat.play_move("e5").unwrap().uci, "e7e5");
+        assert_eq!(notat.play_move("g1f3").unwrap().san, "Nf3");
+        assert_eq!(notat.play_move("Nc6").unwrap().san, "Nc6");
+        assert_eq!(notat.play_move("Bb5").unwrap().san, "Bb5");
+        assert_eq!(notat.play_move("Ke6"), Err(MoveError::IllegalMove));
+        assert_eq!(notat.play_move("Qd5"), Err(MoveError::IllegalMove));
+        assert_eq!(notat.play_move("Nf6d5"), Err(MoveError::NoSuchPiece));
+        assert_eq!(notat.play_move("Zz9"), Err(MoveError::Unparsable));
+        assert_eq!(notat.turn(), _BLACK_PIECE);
+    }
+
+    #[test]
+    fn pinned_piece_explained() {
+        let mut board = Board::new();
+        board.table[0][4] = Some(Box::new(King {
+            has_moved: 1,
+            color: _WHITE_PIECE,
+        }));
+        board.table[1][4] = Some(Box::new(Bishop {
+            has_moved: 1,
+            color: _WHITE_PIECE,
+        }));
+        board.table[7][4] = Some(Box::new(Rook {
+            has_moved: 1,
+            color: _BLACK_PIECE,
+        }));
+        let mut notat: AlgebraicNotation = Notation::new(board, _WHITE_PIECE);
+        assert_eq!(notat.play_move("Bd3"), Err(MoveError::KingInCheck));
+        assert_eq!(notat.play_move("Nf3"), Err(MoveError::NoSuchPiece));
+    }
+
+    #[test]
+    fn side_to_move_and_analysis_mode() {
+        let mut notat = start_position();
+        /* every move form only finds pieces of the side to move */
+        for m in ["e5", "Pe7e5", "e7e5", "Ng8f6", "Nf6"] {
+            assert!(notat.clone().play_move(m).is_err(), "{}", m);
+            assert!(!notat.clone().do_move(m), "{}", m);
+        }
+        notat.play_move("e4").unwrap();
+        assert_eq!(notat.clone().try_move("Ng8f6"), Ok(()));
+        assert_eq!(notat.clone().try_move("Nb1c3"), Err(MoveError::NoSuchPiece));
+
+        notat.set_analysis_mode(true);
+        assert_eq!(notat.play_move("d4").unwrap().uci, "d2d4");
+        assert_eq!(notat.turn(), _BLACK_PIECE);
+        /* white again, although black is to move */
+        assert_eq!(notat.play_move("Nc3").unwrap().uci, "b1c3");
+        assert_eq!(notat.turn(), _BLACK_PIECE);
+        assert_eq!(notat.play_move("e5").unwrap().uci, "e7e5");
+        assert_eq!(notat.turn(), _WHITE_PIECE);
+    }
+
+    #[test]
+    fn mate_suffix() {
+        let mut notat = start_position();
+        for m in ["f3", "e5", "g4"] {
+            notat.play_move(m).unwrap();
+        }
+        let mate = notat.play_move("Qh4").unwrap();
+        assert_eq!(mate.san, "Qh4#");
+        assert!(notat.legal_moves().is_empty());
+        assert!(notat.in_check());
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LegalMove {
+    /* long form accepted by do_move, e.g. Ng1f3, Pe7xd8Q or 0-0 */
+    pub notation: String,
+    pub san: String,
+    pub uci: String,
+    pub piece: char,
+    /* table coordinates, (row, column) from a1 */
+    pub from: (usize, usize),
+    pub to: (usize, usize),
+    pub capture: bool,
+    pub promotion: Option<char>,
+}
+
+struct SanQuery {
+    castle: Option<bool>,
+    piece: char,
+    to: (usize, usize),
+    from_file: Option<usize>,
+    from_rank: Option<usize>,
+    promotion: Option<char>,
+}
+
+pub fn square_name(position: (usize, usize)) -> String {
+    format!("{}{}", (b'a' + position.1 as u8) as char, position.0 + 1)
+}
+
+pub fn parse_square(square: &str) -> Option<(usize, usize)> {
+    let c: Vec<char> = square.chars().collect();
+    if c.len() != 2 || !('a'..='h').contains(&c[0]) || !('1'..='8').contains(&c[1]) {
+        return None;
+    }
+    Some((c[1] as usize - '1' as usize, c[0] as usize - 'a' as usize))
+}
+
+pub fn piece_letter(piece: &dyn PieceTrait) -> char {
+    piece.get_identity().chars().next().unwrap_or('?')
+}
+
+impl AlgebraicNotation {
+    pub fn turn(&self) -> usize {
+        self.turn
+    }
+
+    pub fn board(&self) -> &Board {
+        &self.board
+    }
+
+    pub fn in_check(&self) -> bool {
+        self.check_king_threat(&self.board).is_err()
+    }
+
+    /* Moves that theory_valid_move accepts, before looking at the own king */
+    pub(crate) fn candidate_moves(&self) -> Vec<LegalMove> {
+        let mut candidates = vec![];
+        let table = &self.board.table;
+
+        for (r, row) in table.iter().enumerate() {
+            for (f, square) in row.iter().enumerate() {
+                let piece = match square {
+                    Some(p) if p.get_color() == self.turn => p,
+                    _ => continue,
+                };
+                let letter = piece_letter(piece.as_ref());
+
+                for (tr, target_row) in table.iter().enumerate() {
+                    for (tf, target) in target_row.iter().enumerate() {
+                        if (tr, tf) == (r, f) {
+                            continue;
+                        }
+                        if let Some(t) = target {
+                            if t.get_color() == self.turn {
+                                continue;
+                            }
+                        }
+                        let capture = target.is_some() || letter == 'P' && tf != f;
+                        let test_move = piece.theory_valid_move(
+                            &self.board,
+                            capture,
+                            (r + 1, f + 1),
+                            (tr + 1, tf + 1),
+                        );
+                        if test_move.ok() == Some(false) {
+                            continue;
+                        }
+
+                        let promotions = if letter == 'P' && (tr == 0 || tr == 7) {
+                            vec![Some('Q'), Some('R'), Some('B'), Some('N')]
+                        } else {
+                            vec![None]
+                        };
+                        for promotion in promotions {
+                            candidates.push(LegalMove {
+                                notation: format!(
+                                    "{}{}{}{}{}",
+                                    letter,
+                                    square_name((r, f)),
+                                    if capture { "x" } else { "" },
+                                    square_name((tr, tf)),
+                                    promotion.map(String::from).unwrap_or_default()
+                                ),
+                                san: String::new(),
+                                uci: format!(
+                                    "{}{}{}",
+                                    square_name((r, f)),
+                                    square_name((tr, tf)),
+                                    promotion
+                                        .map(|p| p.to_ascii_lowercase().to_string())
+                                        .unwrap_or_default()
+                                ),
+                                piece: letter,
+                                from: (r, f),
+                                to: (tr, tf),
+                                capture,
+                                promotion,
+                            });
+                        }
+                    }
+                }
+            }
+        }
+
+        let home_row = if self.turn == _WHITE_PIECE { 0 } else { 7 };
+        let king_home = &table[home_row][4];
+        if king_home.is_some() && piece_letter(king_home.as_ref().unwrap().as_ref()) == 'K' {
+            for (notation, san, to) in [("0-0", "O-O", 6), ("0-0-0", "O-O-O", 2)] {
+                candidates.push(LegalMove {
+                    notation: notation.to_string(),
+                    san: san.to_string(),
+                    uci: format!(
+                        "{}{}",
+                        square_name((home_row, 4)),
+                        square_name((home_row, to))
+                    ),
+                    piece: 'K',
+                    from: (home_row, 4),
+                    to: (home_row, to),
+                    capture: false,
+                    promotion: None,
+                });
+            }
+        }
+
+        candidates
+    }
+
+    /* Out of check, the pins of the side to move; in check None, as every
+     * move then has to be tried */
+    fn quiet_pins(&self) -> Option<Vec<pins::Pin>> {
+        if self.in_check() {
+            None
+        } else {
+            Some(pins::pins(&self.board, self.turn))
+        }
+    }
+
+    /* Whether a candidate move is legal as it stands: out of check, a move
+     * by anything but the king that keeps a pinned piece on its ray. En
+     * passant takes two pieces off a line at once, so it is tried too. */
+    fn plainly_legal(&self, pins: &Option<Vec<pins::Pin>>, m: &LegalMove) -> bool {
+        let pins = match pins {
+            Some(pins) => pins,
+            None => return false,
+        };
+        let en_passant =
+            m.piece == 'P' && m.from.1 != m.to.1 && self.board.table[m.to.0][m.to.1].is_none();
+        m.piece != 'K'
+            && !en_passant
+            && pins
+                .iter()
+                .filter(|p| p.pinned.position == m.from)
+                .all(|p| p.ray.contains(&m.to))
+    }
+
+    /* All legal moves together with the position they lead to. The SAN is
+     * complete except that mates are only marked as checks. Plainly legal
+     * moves are played without the test for the own king; only king
+     * moves, en passant and moves out of check are tried. */
+    pub(crate) fn successors(&self) -> Vec<(LegalMove, AlgebraicNotation)> {
+        let pins = self.quiet_pins();
+        let mut legal = vec![];
+        for candidate in self.candidate_moves() {
+            let mut next = self.clone();
+            let played = if self.plainly_legal(&pins, &candidate) {
+                next.play_legal(&candidate.notation)
+            } else {
+                next.try_move(&candidate.notation)
+            };
+            if played.is_ok() {
+                legal.push((candidate, next));
+            }
+        }
+
+        let moves: Vec<LegalMove> = legal.iter().map(|(m, _)| m.clone()).collect();
+        for (m, next) in legal.iter_mut() {
+            if m.san.is_empty() {
+                m.san = base_san(m, &moves);
+            }
+            if next.in_check() {
+                m.san.push('+');
+            }
+        }
+        legal
+    }
+
+    /* Whether the side to move has any legal move, without making one
+     * when a plainly legal move is there */
+    pub fn has_legal_move(&self) -> bool {
+        let candidates = self.candidate_moves();
+        let pins = self.quiet_pins();
+        if candidates.iter().any(|m| self.plainly_legal(&pins, m)) {
+            return true;
+        }
+        candidates
+            .iter()
+            .any(|m| self.clone().try_move(&m.notation).is_ok())
+    }
+
+    pub fn legal_moves(&self) -> Vec<LegalMove> {
+        self.successors()
+            .into_iter()
+            .map(|(mut m, next)| {
+                if m.san.ends_with('+') && !next.has_legal_move() {
+                    m.san.pop();
+                    m.san.push('#');
+                }
+                m
+            })
+            .collect()
+    }
+
+    /* Accepts SAN (Nf3, exd5, e8=Q, O-O), UCI (g1f3) and the long form of do_move */
+    pub fn resolve_move(&self, input: &str) -> Result<LegalMove, MoveError> {
+        let result = self.resolve_for_turn(input);
+        if result.is_err() && self.analysis_mode() {
+            let mut other = self.clone();
+            other.turn = 1 - self.turn;
+            if let Ok(m) = other.resolve_for_turn(input) {
+                return Ok(m);
+            }
+        }
+        result
+    }
+
+    fn resolve_for_turn(&self, input: &str) -> Result<LegalMove, MoveError> {
+        let query = self.parse_san(input)?;
+        let mut matches: Vec<LegalMove> = self
+            .legal_moves()
+            .into_iter()
+            .filter(|m| query.matches(m) && m.promotion == query.promotion)
+            .collect();
+
+        match matches.len() {
+            1 => return Ok(matches.remove(0)),
+            0 => (),
+            _ => {
+                return Err(MoveError::Ambiguous(
+                    matches.into_iter().map(|m| m.san).collect(),
+                ))
+            }
+        }
+
+        /* Nothing legal matched; find out why to be able to explain it */
+        if query.castle.is_some() {
+            let mut next = self.clone();
+            return Err(next
+                .try_move(if query.castle == Some(true) {
+                    "0-0"
+                } else {
+                    "0-0-0"
+                })
+                .err()
+                .unwrap_or(MoveError::CastlingNotAllowed));
+        }
+
+        let candidates: Vec<LegalMove> = self
+            .candidate_moves()
+            .into_iter()
+            .filter(|m| query.matches(m))
+            .collect();
+        if candidates.iter().any(|m| m.promotion != query.promotion) {
+            return Err(MoveError::InvalidPromotion);
+        }
+        for candidate in candidates {
+            let mut next = self.clone();
+            next.try_move(&candidate.notation)?;
+        }
+
+        if let Some(target) = &self.board.table[query.to.0][query.to.1] {
+            if target.get_color() == self.turn {
+                return Err(MoveError::OwnPieceOnTarget);
+            }
+        }
+        let own_pieces = self.find_piece(&self.board, query.piece, 0, 0, self.turn);
+        if own_pieces.is_empty() {
+            return Err(MoveError::NoSuchPiece);
+        }
+        if let (Some(rank), Some(file)) = (query.from_rank, query.from_file) {
+            if !own_pieces.iter().any(|p| p.position == (rank, file)) {
+                return Err(MoveError::NoSuchPiece);
+            }
+        }
+        Err(MoveError::IllegalMove)
+    }
+
+    pub fn play_move(&mut self, input: &str) -> Result<LegalMove, MoveError> {
+        let legal = self.resolve_move(input)?;
+        self.try_move(&legal.notation)?;
+        Ok(legal)
+    }
+
+    fn parse_san(&self, input: &str) -> Result<SanQuery, MoveError> {
+        let san = input.trim().trim_end_matches(|c| "+#!?".contains(c));
+        let mut query = SanQuery {
+            castle: None,
+            piece: 'P',
+            to: (0, 0),
+            from_file: None,
+            from_rank: None,
+            promotion: None,
+        };
+
+        match san {
+            "O-O" | "0-0" => {
+                query.castle = Some(true);
+                return Ok(query);
+            }
+            "O-O-O" | "0-0-0" => {
+                query.castle = Some(false);
+                return Ok(query);
+            }
+            _ => (),
+        }
+
+        let mut chars: Vec<char> = san.chars().filter(|c| *c != '=').collect();
+        if chars.len() < 2 {
+            return Err(MoveError::Unparsable);
+        }
+
+        let last = chars[chars.len() - 1];
+        if last.is_alphabetic() && chars[chars.len() - 2].is_ascii_digit() {
+            let promotion = last.to_ascii_uppercase();
+            if !"QRBN".contains(promotion) {
+                return Err(MoveError::InvalidPromotion);
+            }
+            query.promotion = Some(promotion);
+            chars.pop();
+        }
+
+        if chars[0].is_uppercase() {
+            if !self.board.short_pieces.contains(&chars[0]) {
+                return Err(MoveError::Unparsable);
+            }
+            query.piece = chars.remove(0);
+        }
+
+        if chars.len() < 2 {
+            return Err(MoveError::Unparsable);
+        }
+        let target: String = chars.split_off(chars.len() - 2).into_iter().collect();
+        query.to = parse_square(&target).ok_or(MoveError::Unparsable)?;
+
+        let explicit_piece = query.piece != 'P' || san.starts_with('P');
+        for c in chars {
+            match c {
+                'a'..='h' => query.from_file = Some(c as usize - 'a' as usize),
+                '1'..='8' => query.from_rank = Some(c as usize - '1' as usize),
+                'x' | '-' | ':' => (),
+                _ => return Err(MoveError::Unparsable),
+            }
+        }
+
+        /* UCI style g1f3 names no piece, take whatever stands on the start square */
+        if let (false, Some(rank), Some(file)) = (explicit_piece, query.from_rank, query.from_file)
+        {
+            if let Some(p) = &self.board.table[rank][file] {
+                query.piece = piece_letter(p.as_ref());
+            }
+        }
+        Ok(query)
+    }
+}
+
+impl SanQuery {
+    fn matches(&self, m: &LegalMove) -> bool {
+        if let Some(kingside) = self.castle {
+            return m.notation == if kingside { "0-0" } else { "0-0-0" };
+        }
+        !m.notation.starts_with('0')
+            && m.piece == self.piece
+            && m.to == self.to
+            && self.from_file.is_none_or(|f| m.from.1 == f)
+            && self.from_rank.is_none_or(|r| m.from.0 == r)
+    }
+}
+
+fn base_san(m: &LegalMove, others: &[LegalMove]) -> String {
+    let mut san = String::new();
+    let target = square_name(m.to);
+
+    if m.piece == 'P' {
+        if m.capture {
+            san.push((b'a' + m.from.1 as u8) as char);
+            san.push('x');
+        }
+        san.push_str(&target);
+        if let Some(p) = m.promotion {
+            san.push('=');
+            san.push(p);
+        }
+        return san;
+    }
+
+    san.push(m.piece);
+    let rivals: Vec<&(usize, usize)> = others
+        .iter()
+        .filter(|o| o.piece == m.piece && o.to == m.to && o.from != m.from)
+        .map(|o| &o.from)
+        .collect();
+    if !rivals.is_empty() {
+        let from = square_name(m.from);
+        if rivals.iter().all(|r| r.1 != m.from.1) {
+            san.push_str(&from[..1]);
+        } else if rivals.iter().all(|r| r.0 != m.from.0) {
+            san.push_str(&from[1..]);
+        } else {
+            san.push_str(&from);
+        }
+    }
+    if m.capture {
+        san.push('x');
+    }
+    san.push_str(&target);
+    san
+}
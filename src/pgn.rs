@@ -0,0 +1,286 @@
+/*
+ * Reading PGN collections. Tags are kept as they are; of the movetext
+ * only the moves of the main line and the result are kept, while
+ * comments, variations, move numbers, NAGs and !? annotations are
+ * skipped. Games are replayed with the rules engine only when asked to.
+ */
+
+use crate::game::GameResult;
+use crate::movegen::LegalMove;
+use crate::*;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COLLECTION: &str = r#"[Event "Club championship"]
+[White "Anna"]
+[Black "Bo"]
+[WhiteElo "2105"]
+[Result "1-0"]
+
+1. e4 {best by test} e5 2. Nf3 (2. f4 exf4 {gambit} (2... d5)) Nc6 $1
+3. Bb5 a6?! ; the Morphy defence
+4. Ba4 Nf6 5. 0-0 1-0
+
+[Event "Endgame"]
+[SetUp "1"]
+[FEN "4k3/8/8/8/8/8/4P3/4K3 w - - 0 1"]
+
+1. e4 Kd7 2. Kd2 1/2-1/2
+"#;
+
+    #[test]
+    fn reads_a_collection() {
+        let games = parse_pgn(COLLECTION).unwrap();
+        assert_eq!(games.len(), 2);
+        assert_eq!(games[0].tag("White"), Some("Anna"));
+        assert_eq!(games[0].rating(_WHITE_PIECE), Some(2105));
+        assert_eq!(games[0].rating(_BLACK_PIECE), None);
+        assert_eq!(
+            games[0].moves,
+            vec!["e4", "e5", "Nf3", "Nc6", "Bb5", "a6", "Ba4", "Nf6", "0-0"]
+        );
+        assert_eq!(games[0].result, GameResult::WhiteWins);
+        assert_eq!(games[1].result, GameResult::Draw);
+
+        let (start, moves) = games[1].replay().unwrap();
+        assert_eq!(start.to_fen(), "4k3/8/8/8/8/8/4P3/4K3 w - - 0 1");
+        assert_eq!(moves.len(), 3);
+        assert_eq!(games[0].replay().unwrap().1[8].uci, "e1g1");
+    }
+
+    #[test]
+    fn reports_bad_games() {
+        let games = parse_pgn("1. e4 e5 2. Ke3 *\n").unwrap();
+        assert_eq!(games[0].result, GameResult::Ongoing);
+        let error = games[0].replay().unwrap_err();
+        assert_eq!(error.ply, Some(2));
+        assert!(error.to_string().contains("Ke3"));
+        assert!(parse_pgn("[Event \"open").is_err());
+    }
+
+    #[test]
+    fn move_numbers_glued_to_moves() {
+        let games = parse_pgn("1.e4 e5 2.Nf3 Nc6 3.Bc4 Bc5 4.d3 Nf6 5.0-0 5...0-0 *\n").unwrap();
+        assert_eq!(games[0].moves[8..], ["0-0", "0-0"]);
+        let (_, moves) = games[0].replay().unwrap();
+        assert_eq!(moves[8].uci, "e1g1");
+        assert_eq!(moves[9].uci, "e8g8");
+        assert_eq!(clean_move("12."), None);
+        assert_eq!(clean_move("7...0-0-0+"), Some("0-0-0"));
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PgnError {
+    /* counted from 0 in the collection */
+    pub game: usize,
+    /* the move that could not be played, counted from 0 */
+    pub ply: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for PgnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.ply {
+            Some(ply) => write!(
+                f,
+                "game {}, ply {}: {}",
+                self.game + 1,
+                ply + 1,
+                self.message
+            ),
+            None => write!(f, "game {}: {}", self.game + 1, self.message),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PgnGame {
+    /* where in the collection, counted from 0 */
+    pub index: usize,
+    pub tags: Vec<(String, String)>,
+    /* the main line in SAN, annotations taken off */
+    pub moves: Vec<String>,
+    pub result: GameResult,
+}
+
+impl PgnGame {
+    fn new(index: usize) -> PgnGame {
+        PgnGame {
+            index,
+            tags: vec![],
+            moves: vec![],
+            result: GameResult::Ongoing,
+        }
+    }
+
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|(tag, _)| tag == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /* WhiteElo or BlackElo, when there and a number */
+    pub fn rating(&self, color: usize) -> Option<u32> {
+        let tag = if color == _WHITE_PIECE {
+            "WhiteElo"
+        } else {
+            "BlackElo"
+        };
+        self.tag(tag)?.trim().parse().ok()
+    }
+
+    /* The start position, from the FEN tag if there is one */
+    pub fn start(&self) -> Result<AlgebraicNotation, PgnError> {
+        let fen = self.tag("FEN").unwrap_or(fen::START_FEN);
+        AlgebraicNotation::from_fen(fen).map_err(|e| PgnError {
+            game: self.index,
+            ply: None,
+            message: e.to_string(),
+        })
+    }
+
+    /* The start position and the moves played from it */
+    pub fn replay(&self) -> Result<(AlgebraicNotation, Vec<LegalMove>), PgnError> {
+        let start = self.start()?;
+        let mut position = start.clone();
+        let mut played = vec![];
+        for (ply, san) in self.moves.iter().enumerate() {
+            let m = position.play_move(san).map_err(|e| PgnError {
+                game: self.index,
+                ply: Some(ply),
+                message: format!("{}: {}", san, e),
+            })?;
+            played.push(m);
+        }
+        Ok((start, played))
+    }
+}
+
+fn is_result(token: &str) -> Option<GameResult> {
+    match token {
+        "1-0" | "0-1" | "1/2-1/2" | "*" => GameResult::from_pgn(token),
+        _ => None,
+    }
+}
+
+/* A move token without its number and annotations, if anything is left */
+fn clean_move(token: &str) -> Option<&str> {
+    let token = token.trim_end_matches(['!', '?', '+', '#']);
+    /* a number counts as a move number when dots or nothing follow it;
+     * castling written with zeros, 0-0, keeps its zeros */
+    let after_number = token.trim_start_matches(|c: char| c.is_ascii_digit());
+    let token = if after_number.is_empty() || after_number.starts_with('.') {
+        after_number.trim_start_matches('.')
+    } else {
+        token
+    };
+    if token.is_empty() {
+        None
+    } else {
+        Some(token)
+    }
+}
+
+pub fn parse_pgn(text: &str) -> Result<Vec<PgnGame>, PgnError> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut games = vec![];
+    let mut game = PgnGame::new(0);
+    /* whether movetext was seen, so that the next tag starts a new game */
+    let mut in_moves = false;
+    let mut variations = 0;
+    let mut i = 0;
+    let at_line_start = |i: usize| i == 0 || chars[i - 1] == '\n';
+
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '%' && at_line_start(i) || c == ';' {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+        } else if c == '{' {
+            while i < chars.len() && chars[i] != '}' {
+                i += 1;
+            }
+            i += 1;
+        } else if c == '(' {
+            variations += 1;
+            i += 1;
+        } else if c == ')' {
+            variations -= 1;
+            i += 1;
+        } else if c == '[' && variations == 0 {
+            if in_moves {
+                let index = games.len() + 1;
+                games.push(std::mem::replace(&mut game, PgnGame::new(index)));
+                in_moves = false;
+            }
+            let start = i;
+            let mut quoted = false;
+            while i < chars.len() && (quoted || chars[i] != ']') {
+                if chars[i] == '"' {
+                    quoted = !quoted;
+                }
+                i += 1;
+            }
+            if i == chars.len() {
+                return Err(PgnError {
+                    game: game.index,
+                    ply: None,
+                    message: "a tag is not closed".to_string(),
+                });
+            }
+            let inside: String = chars[start + 1..i].iter().collect();
+            i += 1;
+            let (name, value) = inside
+                .trim()
+                .split_once(char::is_whitespace)
+                .ok_or_else(|| PgnError {
+                    game: game.index,
+                    ply: None,
+                    message: format!("bad tag [{}]", inside),
+                })?;
+            game.tags.push((
+                name.to_string(),
+                value.trim().trim_matches('"').replace("\\\"", "\""),
+            ));
+        } else {
+            let start = i;
+            while i < chars.len() && !chars[i].is_whitespace() && !"{}()[];".contains(chars[i]) {
+                i += 1;
+            }
+            if i == start {
+                /* a stray bracket */
+                i += 1;
+                continue;
+            }
+            let token: String = chars[start..i].iter().collect();
+            if variations > 0 || token.starts_with('$') {
+                continue;
+            }
+            in_moves = true;
+            if let Some(result) = is_result(&token) {
+                game.result = result;
+                let index = games.len() + 1;
+                games.push(std::mem::replace(&mut game, PgnGame::new(index)));
+                in_moves = false;
+            } else if let Some(san) = clean_move(&token) {
+                game.moves.push(san.to_string());
+            }
+        }
+    }
+    if in_moves || !game.tags.is_empty() {
+        if game.result == GameResult::Ongoing {
+            if let Some(result) = game.tag("Result").and_then(GameResult::from_pgn) {
+                game.result = result;
+            }
+        }
+        games.push(game);
+    }
+    Ok(games)
+}
@@ -0,0 +1,333 @@
+/*
+ * Checks, pins and discovered attacks.
+ *
+ * All three are lines that open when a piece leaves its square. A
+ * discovered attack is waiting when taking an own piece off lets another
+ * own piece attack something; finding those by removal keeps it working
+ * for any piece. Pins are asked for by has_legal_move in every searched
+ * node, so they are found by walking the eight rays out from the king on
+ * the board as it is: the first own piece on a ray is pinned when the
+ * next piece is an enemy rook or queen on a file or rank, or an enemy
+ * bishop or queen on a diagonal.
+ */
+
+use crate::attacks::attackers_of;
+use crate::*;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn square(name: &str) -> (usize, usize) {
+        movegen::parse_square(name).unwrap()
+    }
+
+    #[test]
+    fn checks_and_pins() {
+        let notat = AlgebraicNotation::from_fen("4k3/4r3/8/b7/8/2N5/4B3/4K3 w - - 0 1").unwrap();
+        assert!(notat.checkers().is_empty());
+        let pins = notat.pins(_WHITE_PIECE);
+        assert_eq!(pins.len(), 2);
+        let bishop = pins.iter().find(|p| p.pinned.piece == 'B').unwrap();
+        assert_eq!(bishop.pinner.position, square("e7"));
+        /* the bishop can only move along the file, up to and including e7 */
+        let squares: Vec<(usize, usize)> = ["e2", "e3", "e4", "e5", "e6", "e7"]
+            .iter()
+            .map(|s| square(s))
+            .collect();
+        assert_eq!(bishop.ray, squares);
+        let knight = pins.iter().find(|p| p.pinned.piece == 'N').unwrap();
+        assert_eq!(
+            knight.ray,
+            vec![square("d2"), square("c3"), square("b4"), square("a5")]
+        );
+        assert!(notat.pins(_BLACK_PIECE).is_empty());
+
+        /* double check */
+        let notat = AlgebraicNotation::from_fen("4k3/8/3N4/8/8/8/8/4R1K1 b - - 0 1").unwrap();
+        let mut checkers: Vec<char> = notat.checkers().iter().map(|p| p.piece).collect();
+        checkers.sort();
+        assert_eq!(checkers, vec!['N', 'R']);
+    }
+
+    #[test]
+    fn discovered_attacks() {
+        let notat = AlgebraicNotation::from_fen("3qk3/8/8/8/3N4/8/3R4/4K3 w - - 0 1").unwrap();
+        let discoveries = notat.discovered_attacks(_WHITE_PIECE);
+        assert_eq!(discoveries.len(), 1);
+        assert_eq!(discoveries[0].blocker.piece, 'N');
+        assert_eq!(discoveries[0].attacker.piece, 'R');
+        assert_eq!(discoveries[0].target.position, square("d8"));
+        assert!(notat.discovered_attacks(_BLACK_PIECE).is_empty());
+    }
+
+    #[test]
+    fn legal_move_shortcut() {
+        for fen in [
+            fen::START_FEN,
+            /* mate, stalemate, and a pinned piece with its only moves on the ray */
+            "7k/6Q1/6K1/8/8/8/8/8 b - - 0 1",
+            "7k/8/6QK/8/8/8/8/8 b - - 0 1",
+            "k7/1r6/8/8/8/8/6Q1/K7 b - - 0 1",
+            "k7/8/8/8/8/8/r7/K7 w - - 0 1",
+        ] {
+            let notat = AlgebraicNotation::from_fen(fen).unwrap();
+            assert_eq!(
+                notat.has_legal_move(),
+                !notat.successors().is_empty(),
+                "{}",
+                fen
+            );
+        }
+    }
+
+    /* A perft to depth 2 from a few positions full of lines, checking the
+     * rays against the pins found by taking each piece off, and the moves
+     * generated with them against trying every candidate, on the way; the
+     * node counts are the known ones, so the tree itself stays the same */
+    #[test]
+    fn pins_match_removal() {
+        fn by_removal(board: &Board, color: usize) -> Vec<((usize, usize), (usize, usize))> {
+            let king = match king_of(board, color) {
+                Some(king) => king,
+                None => return vec![],
+            };
+            let mut pins = vec![];
+            for (r, row) in board.table.iter().enumerate() {
+                for (c, piece) in row.iter().enumerate() {
+                    match piece {
+                        Some(p) if p.get_color() == color && (r, c) != king => (),
+                        _ => continue,
+                    }
+                    for (pinner, _) in uncovered(board, (r, c), 1 - color, &[king]) {
+                        pins.push(((r, c), pinner.position));
+                    }
+                }
+            }
+            pins.sort();
+            pins
+        }
+
+        fn walk(notat: &AlgebraicNotation, depth: usize) -> usize {
+            if depth == 0 {
+                return 1;
+            }
+            for color in [_WHITE_PIECE, _BLACK_PIECE] {
+                let mut pins: Vec<_> = pins(&notat.board, color)
+                    .iter()
+                    .map(|p| (p.pinned.position, p.pinner.position))
+                    .collect();
+                pins.sort();
+                assert_eq!(pins, by_removal(&notat.board, color), "{}", notat.to_fen());
+            }
+            let successors = notat.successors();
+            assert_eq!(notat.has_legal_move(), !successors.is_empty());
+            /* the moves played without the test for the own king are the
+             * ones that pass it */
+            let played: Vec<&str> = successors.iter().map(|(m, _)| m.uci.as_str()).collect();
+            let tried: Vec<String> = notat
+                .candidate_moves()
+                .into_iter()
+                .filter(|m| notat.clone().try_move(&m.notation).is_ok())
+                .map(|m| m.uci)
+                .collect();
+            assert_eq!(played, tried, "{}", notat.to_fen());
+            successors
+                .iter()
+                .map(|(_, next)| walk(next, depth - 1))
+                .sum()
+        }
+
+        let nodes: Vec<usize> = [
+            "4k3/4r3/8/b7/8/2N5/4B3/4K3 w - - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+        ]
+        .iter()
+        .map(|fen| walk(&AlgebraicNotation::from_fen(fen).unwrap(), 2))
+        .collect();
+        assert_eq!(nodes, vec![84, 2039, 191]);
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Pin {
+    pub pinned: FoundPiece,
+    pub pinner: FoundPiece,
+    /* the line from the king up to and including the pinner, which the
+     * pinned piece stands on and may not leave, or only the pinner when it
+     * does not attack along a line */
+    pub ray: Vec<(usize, usize)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Discovery {
+    /* the piece that has to move away */
+    pub blocker: FoundPiece,
+    pub attacker: FoundPiece,
+    pub target: FoundPiece,
+}
+
+pub(crate) fn found(board: &Board, position: (usize, usize)) -> Option<FoundPiece> {
+    board.table[position.0][position.1]
+        .as_ref()
+        .map(|p| FoundPiece {
+            piece: movegen::piece_letter(p.as_ref()),
+            position,
+        })
+}
+
+pub(crate) fn king_of(board: &Board, color: usize) -> Option<(usize, usize)> {
+    let mut square = None;
+    for (r, row) in board.table.iter().enumerate() {
+        for (c, piece) in row.iter().enumerate() {
+            if let Some(p) = piece {
+                if p.get_color() == color && movegen::piece_letter(p.as_ref()) == 'K' {
+                    square = Some((r, c));
+                }
+            }
+        }
+    }
+    square
+}
+
+/* Pairs of an attacker of color and a piece it would attack once the
+ * blocker is gone, leaving out the attacks there already are */
+pub(crate) fn uncovered(
+    board: &Board,
+    blocker: (usize, usize),
+    color: usize,
+    targets: &[(usize, usize)],
+) -> Vec<(FoundPiece, FoundPiece)> {
+    let mut without = board.clone();
+    without.table[blocker.0][blocker.1] = None;
+
+    let mut pairs = vec![];
+    for target in targets.iter().copied().filter(|t| *t != blocker) {
+        let before = attackers_of(board, target, color);
+        for attacker in attackers_of(&without, target, color) {
+            if !before.iter().any(|b| b.position == attacker.position) {
+                pairs.push((attacker, found(board, target).unwrap()));
+            }
+        }
+    }
+    pairs
+}
+
+/* The squares from next to the king up to the pinner, when on one line */
+fn ray(king: (usize, usize), pinner: (usize, usize)) -> Vec<(usize, usize)> {
+    let (dr, dc) = (
+        pinner.0 as i32 - king.0 as i32,
+        pinner.1 as i32 - king.1 as i32,
+    );
+    if dr != 0 && dc != 0 && dr.abs() != dc.abs() {
+        return vec![pinner];
+    }
+    let steps = dr.abs().max(dc.abs());
+    (1..=steps)
+        .map(|i| {
+            (
+                (king.0 as i32 + dr.signum() * i) as usize,
+                (king.1 as i32 + dc.signum() * i) as usize,
+            )
+        })
+        .collect()
+}
+
+/* The pieces giving check to color, two of them in a double check */
+pub fn checkers(board: &Board, color: usize) -> Vec<FoundPiece> {
+    match king_of(board, color) {
+        Some(king) => attackers_of(board, king, 1 - color),
+        None => vec![],
+    }
+}
+
+const DIRECTIONS: [(i32, i32); 8] = [
+    (1, 0),
+    (-1, 0),
+    (0, 1),
+    (0, -1),
+    (1, 1),
+    (1, -1),
+    (-1, 1),
+    (-1, -1),
+];
+
+/* The pieces of color that may not leave the line to their king */
+pub fn pins(board: &Board, color: usize) -> Vec<Pin> {
+    let king = match king_of(board, color) {
+        Some(king) => king,
+        None => return vec![],
+    };
+    let mut pins = vec![];
+    for &(dr, dc) in DIRECTIONS.iter() {
+        let slider = if dr == 0 || dc == 0 { 'R' } else { 'B' };
+        let mut own = None;
+        let (mut r, mut c) = (king.0 as i32 + dr, king.1 as i32 + dc);
+        while (0..8).contains(&r) && (0..8).contains(&c) {
+            let square = (r as usize, c as usize);
+            if let Some(p) = &board.table[square.0][square.1] {
+                let piece = found(board, square).unwrap();
+                match own {
+                    None if p.get_color() == color => own = Some(piece),
+                    Some(pinned) if piece.piece == slider || piece.piece == 'Q' => {
+                        if p.get_color() != color {
+                            pins.push(Pin {
+                                pinned,
+                                pinner: piece,
+                                ray: ray(king, square),
+                            });
+                        }
+                        break;
+                    }
+                    _ => break,
+                }
+            }
+            r += dr;
+            c += dc;
+        }
+    }
+    pins
+}
+
+/* Attacks of color on enemy pieces that are waiting for an own piece to move */
+pub fn discoveries(board: &Board, color: usize) -> Vec<Discovery> {
+    let mut own = vec![];
+    let mut enemies = vec![];
+    for (r, row) in board.table.iter().enumerate() {
+        for (c, piece) in row.iter().enumerate() {
+            match piece {
+                Some(p) if p.get_color() == color => own.push((r, c)),
+                Some(_) => enemies.push((r, c)),
+                None => (),
+            }
+        }
+    }
+
+    let mut discoveries = vec![];
+    for blocker in own {
+        for (attacker, target) in uncovered(board, blocker, color, &enemies) {
+            discoveries.push(Discovery {
+                blocker: found(board, blocker).unwrap(),
+                attacker,
+                target,
+            });
+        }
+    }
+    discoveries
+}
+
+impl AlgebraicNotation {
+    /* The pieces checking the side to move */
+    pub fn checkers(&self) -> Vec<FoundPiece> {
+        checkers(&self.board, self.turn)
+    }
+
+    pub fn pins(&self, color: usize) -> Vec<Pin> {
+        pins(&self.board, color)
+    }
+
+    pub fn discovered_attacks(&self, color: usize) -> Vec<Discovery> {
+        discoveries(&self.board, color)
+    }
+}
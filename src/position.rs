@@ -0,0 +1,237 @@
+/*
+ * The parts of a position that can not be seen on the board: who may
+ * still castle, the en passant square and the two move counters, the
+ * same things FEN lists after the piece placement.
+ */
+
+use crate::movegen::piece_letter;
+use crate::*;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn start_position() -> AlgebraicNotation {
+        let mut board = Board::new();
+        board.populate_board();
+        Notation::new(board, _WHITE_PIECE)
+    }
+
+    #[test]
+    fn updated_on_every_move() {
+        let mut notat = start_position();
+        assert_eq!(notat.state().castling, CastlingRights::all());
+        notat.play_move("e4").unwrap();
+        assert_eq!(notat.state().en_passant, Some((2, 4)));
+        notat.play_move("Nf6").unwrap();
+        assert_eq!(notat.state().en_passant, None);
+        assert_eq!(notat.state().halfmove_clock, 1);
+        assert_eq!(notat.state().fullmove_number, 2);
+
+        notat.play_move("Ke2").unwrap();
+        assert!(!notat.state().castling.allows(_WHITE_PIECE, true));
+        assert!(!notat.state().castling.allows(_WHITE_PIECE, false));
+        assert_eq!(notat.state().halfmove_clock, 2);
+
+        /* a knight taking the rook in its corner takes the right with it */
+        let mut notat =
+            AlgebraicNotation::from_fen("r3k2r/8/8/8/8/6n1/8/R3K2R b KQkq - 0 1").unwrap();
+        notat.play_move("Nxh1").unwrap();
+        assert_eq!(notat.state().castling.to_fen(), "Qkq");
+        assert_eq!(notat.state().halfmove_clock, 0);
+    }
+
+    #[test]
+    fn en_passant_only_on_the_next_ply() {
+        let mut notat = start_position();
+        for m in ["e4", "a6", "e5", "d5"] {
+            notat.play_move(m).unwrap();
+        }
+        let mut now = notat.clone();
+        assert_eq!(now.play_move("exd6").unwrap().uci, "e5d6");
+        assert!(now.board().table[4][3].is_none());
+
+        notat.play_move("h3").unwrap();
+        notat.play_move("h6").unwrap();
+        assert!(notat.play_move("exd6").is_err());
+
+        /* two single steps are not a double step */
+        let mut notat = start_position();
+        for m in ["e4", "d6", "e5", "h6", "h3", "d5"] {
+            notat.play_move(m).unwrap();
+        }
+        assert!(notat.play_move("exd6").is_err());
+
+        /* both directions */
+        let mut notat = AlgebraicNotation::from_fen("4k3/8/8/8/2pPp3/8/8/4K3 b - d3 0 1").unwrap();
+        assert!(notat.clone().play_move("cxd3").is_ok());
+        assert!(notat.play_move("exd3").is_ok());
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CastlingRights {
+    pub white_kingside: bool,
+    pub white_queenside: bool,
+    pub black_kingside: bool,
+    pub black_queenside: bool,
+}
+
+impl CastlingRights {
+    pub fn none() -> CastlingRights {
+        CastlingRights {
+            white_kingside: false,
+            white_queenside: false,
+            black_kingside: false,
+            black_queenside: false,
+        }
+    }
+
+    pub fn all() -> CastlingRights {
+        CastlingRights {
+            white_kingside: true,
+            white_queenside: true,
+            black_kingside: true,
+            black_queenside: true,
+        }
+    }
+
+    fn right(&mut self, color: usize, kingside: bool) -> &mut bool {
+        match (color == _WHITE_PIECE, kingside) {
+            (true, true) => &mut self.white_kingside,
+            (true, false) => &mut self.white_queenside,
+            (false, true) => &mut self.black_kingside,
+            (false, false) => &mut self.black_queenside,
+        }
+    }
+
+    pub fn allows(&self, color: usize, kingside: bool) -> bool {
+        match (color == _WHITE_PIECE, kingside) {
+            (true, true) => self.white_kingside,
+            (true, false) => self.white_queenside,
+            (false, true) => self.black_kingside,
+            (false, false) => self.black_queenside,
+        }
+    }
+
+    pub fn set(&mut self, color: usize, kingside: bool, allowed: bool) {
+        *self.right(color, kingside) = allowed;
+    }
+
+    /* KQkq, or - when nobody may castle */
+    pub fn to_fen(&self) -> String {
+        let rights: String = [
+            (self.white_kingside, 'K'),
+            (self.white_queenside, 'Q'),
+            (self.black_kingside, 'k'),
+            (self.black_queenside, 'q'),
+        ]
+        .iter()
+        .filter(|(allowed, _)| *allowed)
+        .map(|(_, letter)| *letter)
+        .collect();
+        if rights.is_empty() {
+            "-".to_string()
+        } else {
+            rights
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PositionState {
+    pub castling: CastlingRights,
+    /* the square a pawn skipped on the last ply, as (row, column) */
+    pub en_passant: Option<(usize, usize)>,
+    /* plies since the last capture or pawn move */
+    pub halfmove_clock: u32,
+    pub fullmove_number: u32,
+}
+
+impl Default for PositionState {
+    fn default() -> Self {
+        PositionState {
+            castling: CastlingRights::none(),
+            en_passant: None,
+            halfmove_clock: 0,
+            fullmove_number: 1,
+        }
+    }
+}
+
+/* The corners rooks castle from, and which right each belongs to */
+const ROOK_CORNERS: [((usize, usize), usize, bool); 4] = [
+    ((0, 7), _WHITE_PIECE, true),
+    ((0, 0), _WHITE_PIECE, false),
+    ((7, 7), _BLACK_PIECE, true),
+    ((7, 0), _BLACK_PIECE, false),
+];
+
+impl PositionState {
+    /* Every right whose king and rook are unmoved on their home squares */
+    pub fn from_placement(board: &Board) -> PositionState {
+        let stands = |(row, column): (usize, usize), letter: char, color: usize| match &board.table
+            [row][column]
+        {
+            Some(p) => {
+                piece_letter(p.as_ref()) == letter
+                    && p.get_color() == color
+                    && p.clone().movement(0) == 0
+            }
+            None => false,
+        };
+
+        let mut state = PositionState::default();
+        for (corner, color, kingside) in ROOK_CORNERS.iter() {
+            let home_row = corner.0;
+            state.castling.set(
+                *color,
+                *kingside,
+                stands((home_row, 4), 'K', *color) && stands(*corner, 'R', *color),
+            );
+        }
+        state
+    }
+
+    /* After color moved the piece letter from one square to another */
+    pub(crate) fn update(
+        &mut self,
+        color: usize,
+        letter: char,
+        from: (usize, usize),
+        to: (usize, usize),
+        captured: bool,
+    ) {
+        self.en_passant = if letter == 'P' && from.0.abs_diff(to.0) == 2 {
+            Some(((from.0 + to.0) / 2, from.1))
+        } else {
+            None
+        };
+
+        if letter == 'P' || captured {
+            self.halfmove_clock = 0;
+        } else {
+            self.halfmove_clock += 1;
+        }
+        if color == _BLACK_PIECE {
+            self.fullmove_number += 1;
+        }
+
+        if letter == 'K' {
+            self.castling.set(color, true, false);
+            self.castling.set(color, false, false);
+        }
+        /* a rook leaving its corner, or being taken there */
+        for (corner, owner, kingside) in ROOK_CORNERS.iter() {
+            if from == *corner || to == *corner {
+                self.castling.set(*owner, *kingside, false);
+            }
+        }
+    }
+}
+
+impl AlgebraicNotation {
+    pub fn state(&self) -> &PositionState {
+        &self.board.state
+    }
+}
@@ -0,0 +1,599 @@
+/*
+ * Solving chess problems. Unlike the engine, which looks for a good
+ * move, a solver has to prove its answer: a directmate in N holds when
+ * some first move, the key, mates within N moves against every defence,
+ * and the solution is the whole tree of defences and mating replies.
+ * A selfmate turns this around: the side to move forces the other side
+ * to mate it, the defence doing all it can not to.
+ *
+ * Every key is reported. More than one means the problem is cooked, and
+ * a mate in fewer moves than stated is reported as well. A defence met
+ * by more than one continuation is a dual.
+ *
+ * In a helpmate both sides work together, the side to move to be mated
+ * by the other on its Nth move, exactly. Every line that does it is a
+ * solution, and where two solutions part after the first move, there
+ * is a dual.
+ *
+ * Positions are remembered with the moves left, so transpositions are
+ * solved once.
+ */
+
+use crate::book::polyglot_key;
+use crate::movegen::LegalMove;
+use crate::*;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn position(fen: &str) -> AlgebraicNotation {
+        AlgebraicNotation::from_fen(fen).unwrap()
+    }
+
+    /* Whether every line of the tree ends in mate in time */
+    fn ends_in_mate(start: &AlgebraicNotation, tree: &MateTree, moves: u32) -> bool {
+        let mut next = start.clone();
+        next.try_move(&tree.attack.notation).unwrap();
+        if tree.mates() {
+            return next.in_check() && !next.has_legal_move();
+        }
+        moves > 1
+            && tree.defences.len() == next.legal_moves().len()
+            && tree.defences.iter().all(|d| {
+                let mut after = next.clone();
+                after.try_move(&d.reply.notation).unwrap();
+                !d.continuations.is_empty()
+                    && d.continuations
+                        .iter()
+                        .all(|c| ends_in_mate(&after, c, moves - 1))
+            })
+    }
+
+    #[test]
+    fn stipulations() {
+        assert_eq!(Stipulation::parse("#2"), Ok(Stipulation::Directmate(2)));
+        assert_eq!(Stipulation::parse("h#3"), Ok(Stipulation::Helpmate(3)));
+        assert_eq!(Stipulation::parse("S#1"), Ok(Stipulation::Selfmate(1)));
+        assert_eq!(Stipulation::Directmate(3).to_string(), "#3");
+        assert_eq!(Stipulation::Helpmate(2).to_string(), "h#2");
+        assert!(Stipulation::parse("#0").is_err());
+        assert!(Stipulation::parse("=2").is_err());
+        assert!(Stipulation::parse("h#").is_err());
+    }
+
+    #[test]
+    fn mate_in_two() {
+        /* the rooks mate on the back rank, from the seventh rank either way */
+        let start = position("6k1/8/8/8/8/8/R7/1R4K1 w - - 0 1");
+        let solution = solve_directmate(&start, 2);
+        let keys: Vec<&str> = solution
+            .keys
+            .iter()
+            .map(|k| k.attack.san.as_str())
+            .collect();
+        assert!(keys.contains(&"Ra7") && keys.contains(&"Rb7"), "{:?}", keys);
+        assert!(solution.cooked());
+        assert_eq!(solution.shortest, None);
+        for key in &solution.keys {
+            assert!(ends_in_mate(&start, key, 2));
+        }
+        let text = solution.to_string();
+        assert!(text.contains("1. Ra7!"), "{}", text);
+        assert!(text.contains("1... Kf8 2. Rb8#"), "{}", text);
+
+        /* and no mate in one */
+        let refuted = solve_directmate(&start, 1);
+        assert!(refuted.keys.is_empty());
+        assert!(refuted.to_string().contains("no solution"));
+        let longer = solve_directmate(&start, 3);
+        assert_eq!(longer.shortest, Some(2));
+    }
+
+    #[test]
+    fn sound_and_short() {
+        /* only the queen mates at once */
+        let start = position("k7/8/1K6/8/8/8/8/6Q1 w - - 0 1");
+        let solution = solve_directmate(&start, 1);
+        let keys: Vec<&str> = solution
+            .keys
+            .iter()
+            .map(|k| k.attack.san.as_str())
+            .collect();
+        assert_eq!(keys, vec!["Qg8#"]);
+        assert!(!solution.cooked());
+        assert!(solution.keys[0].mates());
+
+        /* a stalemate is no mate: after Qb6 black is out of moves */
+        let start = position("k7/2K5/8/8/8/8/8/1Q6 w - - 0 1");
+        let solution = solve_directmate(&start, 2);
+        assert!(solution.keys.iter().all(|k| k.attack.san != "Qb6"));
+        assert_eq!(solution.shortest, Some(1));
+    }
+
+    #[test]
+    fn selfmate() {
+        /* with g4 and h4 covered, black's last move is g2 mate */
+        let start = position("8/8/8/R7/8/6pk/8/6BK w - - 0 1");
+        let solution = solve_selfmate(&start, 1);
+        assert_eq!(solution.keys.len(), 1);
+        let key = &solution.keys[0];
+        assert_eq!(key.attack.san, "Ra4");
+        assert_eq!(key.defences.len(), 1);
+        assert_eq!(key.defences[0].reply.san, "g2#");
+        assert_eq!(key.duals(), 0);
+        assert_eq!(solution.to_string(), "s#1: one key\n1. Ra4!\n    1... g2#");
+
+        /* the same move is no directmate, and a selfmate needs a key */
+        assert!(solve_directmate(&start, 1).keys.is_empty());
+        let start = position("8/8/8/8/R7/6pk/8/6BK b - - 0 1");
+        assert!(solve_selfmate(&start, 1).keys.is_empty());
+    }
+
+    #[test]
+    fn helpmate() {
+        let start = position("7k/8/6K1/8/8/8/8/R7 b - - 0 1");
+        let solution = solve_helpmate(&start, 1);
+        assert_eq!(solution.to_string(), "h#1: one solution\n1.Kg8 Ra8#");
+        assert!(solution.duals().is_empty());
+
+        /* white can wait in many ways before the rook mates */
+        let solution = solve_helpmate(&start, 2);
+        assert!(solution.solutions.len() > 1);
+        for line in &solution.solutions {
+            let mut after = start.clone();
+            for (i, m) in line.iter().enumerate() {
+                assert!(!mated(&after), "{:?}", line);
+                after.try_move(&m.notation).unwrap();
+                assert_eq!(mated(&after), i == 3, "{:?}", line);
+            }
+        }
+        let sans: Vec<Vec<&str>> = solution
+            .solutions
+            .iter()
+            .map(|line| line.iter().map(|m| m.san.as_str()).collect())
+            .collect();
+        assert!(sans.contains(&vec!["Kg8", "Kh6", "Kh8", "Ra8#"]));
+        let duals = solution.duals();
+        assert_eq!(duals.len(), 1);
+        assert_eq!(duals[0].line[0].san, "Kg8");
+        assert_eq!(duals[0].alternatives.len(), solution.solutions.len());
+        assert!(solution.to_string().contains("dual after 1.Kg8: "));
+
+        /* black choosing between a4 and Kg8 after 1.a5 Rc1 is no dual */
+        let start = position("7k/p7/6K1/8/8/8/8/1R6 b - - 0 1");
+        let mut solution = solve_helpmate(&start, 2);
+        solution
+            .solutions
+            .retain(|line| line[0].san == "a5" && line[1].san == "Rc1");
+        assert_eq!(solution.solutions.len(), 2);
+        assert!(solution.duals().is_empty());
+
+        /* black cannot be mated by a lone king */
+        let start = position("7k/8/6K1/8/8/8/8/8 b - - 0 1");
+        assert!(solve_helpmate(&start, 2).solutions.is_empty());
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stipulation {
+    /* white, or whoever moves, mates in so many moves against any defence */
+    Directmate(u32),
+    /* the side to move is mated on the other side's Nth move, both helping */
+    Helpmate(u32),
+    /* the side to move forces the other side to mate it */
+    Selfmate(u32),
+}
+
+impl Stipulation {
+    pub fn parse(text: &str) -> Result<Stipulation, String> {
+        let text = text.trim();
+        let moves = |digits: &str| match digits.parse::<u32>() {
+            Ok(n) if n > 0 => Ok(n),
+            _ => Err(format!("bad number of moves in '{}'", text)),
+        };
+        let lower = text.to_ascii_lowercase();
+        if let Some(digits) = lower.strip_prefix("h#") {
+            Ok(Stipulation::Helpmate(moves(digits)?))
+        } else if let Some(digits) = lower.strip_prefix("s#") {
+            Ok(Stipulation::Selfmate(moves(digits)?))
+        } else if let Some(digits) = lower.strip_prefix('#') {
+            Ok(Stipulation::Directmate(moves(digits)?))
+        } else {
+            Err(format!("'{}' is no stipulation, like #2, h#2 or s#2", text))
+        }
+    }
+
+    pub fn moves(&self) -> u32 {
+        match self {
+            Stipulation::Directmate(n) | Stipulation::Helpmate(n) | Stipulation::Selfmate(n) => *n,
+        }
+    }
+}
+
+impl fmt::Display for Stipulation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stipulation::Directmate(n) => write!(f, "#{}", n),
+            Stipulation::Helpmate(n) => write!(f, "h#{}", n),
+            Stipulation::Selfmate(n) => write!(f, "s#{}", n),
+        }
+    }
+}
+
+/* A move of the attacking side with every defence against it. In a
+ * directmate a move without defences mates, in a selfmate a defence
+ * without continuations does */
+#[derive(Debug, Clone, PartialEq)]
+pub struct MateTree {
+    pub attack: LegalMove,
+    pub defences: Vec<Defence>,
+}
+
+/* A defence and every move that still reaches the goal in time after it */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Defence {
+    pub reply: LegalMove,
+    pub continuations: Vec<MateTree>,
+}
+
+impl MateTree {
+    pub fn mates(&self) -> bool {
+        self.defences.is_empty()
+    }
+
+    /* Defences met by more than one continuation, all through the tree */
+    pub fn duals(&self) -> usize {
+        self.defences
+            .iter()
+            .map(|d| {
+                let here = usize::from(d.continuations.len() > 1);
+                here + d.continuations.iter().map(|c| c.duals()).sum::<usize>()
+            })
+            .sum()
+    }
+
+    fn write(&self, f: &mut fmt::Formatter<'_>, number: u32, indent: usize) -> fmt::Result {
+        for defence in &self.defences {
+            write!(
+                f,
+                "\n{:indent$}{}... {}",
+                "",
+                number,
+                defence.reply.san,
+                indent = indent
+            )?;
+            /* mates on the same line, longer continuations below */
+            for continuation in defence.continuations.iter().filter(|c| c.mates()) {
+                write!(f, " {}. {}", number + 1, continuation.attack.san)?;
+            }
+            for continuation in defence.continuations.iter().filter(|c| !c.mates()) {
+                write!(
+                    f,
+                    "\n{:indent$}{}. {}",
+                    "",
+                    number + 1,
+                    continuation.attack.san,
+                    indent = indent + 4
+                )?;
+                continuation.write(f, number + 1, indent + 8)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/* The solution of a directmate or a selfmate */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Solution {
+    pub stipulation: Stipulation,
+    pub keys: Vec<MateTree>,
+    /* the fewest moves that mate, when fewer than stated */
+    pub shortest: Option<u32>,
+}
+
+/* The name the solution had when only directmates were solved */
+pub type Directmate = Solution;
+
+impl Solution {
+    /* More than one key */
+    pub fn cooked(&self) -> bool {
+        self.keys.len() > 1
+    }
+}
+
+impl fmt::Display for Solution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.keys.is_empty() {
+            return write!(f, "{}: no solution", self.stipulation);
+        }
+        write!(f, "{}: ", self.stipulation)?;
+        match self.keys.len() {
+            1 => write!(f, "one key")?,
+            n => write!(f, "{} keys, cooked", n)?,
+        }
+        if let Some(shortest) = self.shortest {
+            write!(f, ", mate in {} already", shortest)?;
+        }
+        match self.keys.iter().map(|k| k.duals()).sum::<usize>() {
+            0 => (),
+            1 => write!(f, ", 1 dual")?,
+            n => write!(f, ", {} duals", n)?,
+        }
+        for key in &self.keys {
+            write!(f, "\n1. {}!", key.attack.san)?;
+            key.write(f, 1, 4)?;
+        }
+        Ok(())
+    }
+}
+
+fn mated(position: &AlgebraicNotation) -> bool {
+    position.in_check() && !position.has_legal_move()
+}
+
+/* Legal moves with the positions they lead to and mates marked in SAN */
+fn moves_from(position: &AlgebraicNotation) -> Vec<(LegalMove, AlgebraicNotation)> {
+    let mut successors = position.successors();
+    for (m, next) in successors.iter_mut() {
+        if m.san.ends_with('+') && !next.has_legal_move() {
+            m.san.pop();
+            m.san.push('#');
+        }
+    }
+    successors
+}
+
+#[derive(Debug, Default)]
+struct Solver {
+    /* a selfmate rather than a directmate */
+    selfmate: bool,
+    /* whether the side to move gets there, by position and moves left */
+    known: HashMap<(u64, u32), bool>,
+}
+
+impl Solver {
+    /* Whether the side to move reaches its goal within so many moves,
+     * whatever the defence */
+    fn forces(&mut self, position: &AlgebraicNotation, moves: u32) -> bool {
+        if moves == 0 {
+            return false;
+        }
+        let key = (polyglot_key(position), moves);
+        if let Some(known) = self.known.get(&key) {
+            return *known;
+        }
+        let forced = position
+            .successors()
+            .iter()
+            .any(|(_, next)| self.attack_holds(next, moves));
+        self.known.insert(key, forced);
+        forced
+    }
+
+    /* After a move of the attacking side with so many moves to do it in,
+     * counting that one: mate already, or there after every defence */
+    fn attack_holds(&mut self, next: &AlgebraicNotation, moves: u32) -> bool {
+        if self.selfmate {
+            /* a defence that mates gives in, one that stalemates escapes */
+            let defences = next.successors();
+            return !defences.is_empty()
+                && defences
+                    .iter()
+                    .all(|(_, after)| mated(after) || self.forces(after, moves - 1));
+        }
+        if moves == 1 {
+            return next.in_check() && !next.has_legal_move();
+        }
+        let defences = next.successors();
+        if defences.is_empty() {
+            return next.in_check();
+        }
+        defences
+            .iter()
+            .all(|(_, after)| self.forces(after, moves - 1))
+    }
+
+    fn tree(&mut self, attack: LegalMove, next: &AlgebraicNotation, moves: u32) -> MateTree {
+        if mated(next) {
+            return MateTree {
+                attack,
+                defences: vec![],
+            };
+        }
+        let mut defences = vec![];
+        for (reply, after) in moves_from(next) {
+            let continuations = if self.selfmate && mated(&after) {
+                vec![]
+            } else {
+                self.continuations(&after, moves - 1)
+            };
+            defences.push(Defence {
+                reply,
+                continuations,
+            });
+        }
+        MateTree { attack, defences }
+    }
+
+    fn continuations(&mut self, position: &AlgebraicNotation, moves: u32) -> Vec<MateTree> {
+        let mut trees = vec![];
+        for (attack, next) in moves_from(position) {
+            if self.attack_holds(&next, moves) {
+                trees.push(self.tree(attack, &next, moves));
+            }
+        }
+        trees
+    }
+
+    fn solve(&mut self, start: &AlgebraicNotation, stipulation: Stipulation) -> Solution {
+        let moves = stipulation.moves();
+        let keys = self.continuations(start, moves);
+        let shortest = if keys.is_empty() {
+            None
+        } else {
+            (1..moves).find(|n| self.forces(start, *n))
+        };
+        Solution {
+            stipulation,
+            keys,
+            shortest,
+        }
+    }
+
+    /* Whether the side to move can be mated in exactly so many moves */
+    fn helps(&mut self, position: &AlgebraicNotation, moves: u32) -> bool {
+        let key = (polyglot_key(position), moves);
+        if let Some(known) = self.known.get(&key) {
+            return *known;
+        }
+        let mut possible = false;
+        for (_, next) in position.successors() {
+            possible = next.successors().iter().any(|(_, after)| {
+                if moves == 1 {
+                    mated(after)
+                } else {
+                    self.helps(after, moves - 1)
+                }
+            });
+            if possible {
+                break;
+            }
+        }
+        self.known.insert(key, possible);
+        possible
+    }
+
+    fn help_lines(
+        &mut self,
+        position: &AlgebraicNotation,
+        moves: u32,
+        line: &mut Vec<LegalMove>,
+        lines: &mut Vec<Vec<LegalMove>>,
+    ) {
+        for (help, next) in moves_from(position) {
+            for (answer, after) in moves_from(&next) {
+                let found = if moves == 1 {
+                    mated(&after)
+                } else {
+                    self.helps(&after, moves - 1)
+                };
+                if !found {
+                    continue;
+                }
+                line.push(help.clone());
+                line.push(answer);
+                if moves == 1 {
+                    lines.push(line.clone());
+                } else {
+                    self.help_lines(&after, moves - 1, line, lines);
+                }
+                line.truncate(line.len() - 2);
+            }
+        }
+    }
+}
+
+/* Every key that mates within so many moves, with its solution tree */
+pub fn solve_directmate(start: &AlgebraicNotation, moves: u32) -> Solution {
+    Solver::default().solve(start, Stipulation::Directmate(moves))
+}
+
+/* Every key that forces the other side to mate within so many moves */
+pub fn solve_selfmate(start: &AlgebraicNotation, moves: u32) -> Solution {
+    let mut solver = Solver {
+        selfmate: true,
+        ..Solver::default()
+    };
+    solver.solve(start, Stipulation::Selfmate(moves))
+}
+
+/* Where two helpmate solutions part after the moves they share */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dual {
+    pub line: Vec<LegalMove>,
+    pub alternatives: Vec<LegalMove>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Helpmate {
+    pub moves: u32,
+    /* the moves of both sides, starting with the side to be mated */
+    pub solutions: Vec<Vec<LegalMove>>,
+}
+
+impl Helpmate {
+    /* Solutions that part on a move of the mating side after the moves
+     * they share; the helping side choosing among moves is no dual */
+    pub fn duals(&self) -> Vec<Dual> {
+        let mut duals: Vec<Dual> = vec![];
+        for solution in &self.solutions {
+            for split in (1..solution.len()).step_by(2) {
+                let line = &solution[..split];
+                let next = &solution[split];
+                let same = |a: &[LegalMove], b: &[LegalMove]| {
+                    a.len() == b.len() && a.iter().zip(b).all(|(x, y)| x.uci == y.uci)
+                };
+                match duals.iter_mut().find(|d| same(&d.line, line)) {
+                    Some(dual) => {
+                        if dual.alternatives.iter().all(|m| m.uci != next.uci) {
+                            dual.alternatives.push(next.clone());
+                        }
+                    }
+                    None => duals.push(Dual {
+                        line: line.to_vec(),
+                        alternatives: vec![next.clone()],
+                    }),
+                }
+            }
+        }
+        duals.retain(|d| d.alternatives.len() > 1);
+        duals
+    }
+}
+
+/* Moves in pairs, the helping side's first: 1.Kf8 Rb8# */
+fn write_line(f: &mut fmt::Formatter<'_>, line: &[LegalMove]) -> fmt::Result {
+    for (i, m) in line.iter().enumerate() {
+        if i % 2 == 0 {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{}.{}", i / 2 + 1, m.san)?;
+        } else {
+            write!(f, " {}", m.san)?;
+        }
+    }
+    Ok(())
+}
+
+impl fmt::Display for Helpmate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "h#{}: ", self.moves)?;
+        match self.solutions.len() {
+            0 => return write!(f, "no solution"),
+            1 => write!(f, "one solution")?,
+            n => write!(f, "{} solutions", n)?,
+        }
+        for solution in &self.solutions {
+            writeln!(f)?;
+            write_line(f, solution)?;
+        }
+        for dual in self.duals() {
+            write!(f, "\ndual after ")?;
+            write_line(f, &dual.line)?;
+            let alternatives: Vec<&str> =
+                dual.alternatives.iter().map(|m| m.san.as_str()).collect();
+            write!(f, ": {}", alternatives.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/* Every line in which the side to move is mated on the other side's
+ * move number so many, and not before */
+pub fn solve_helpmate(start: &AlgebraicNotation, moves: u32) -> Helpmate {
+    let mut solutions = vec![];
+    Solver::default().help_lines(start, moves, &mut vec![], &mut solutions);
+    Helpmate { moves, solutions }
+}
@@ -0,0 +1,354 @@
+/*
+ * Line-delimited JSON protocol for driving the engine from other programs.
+ *
+ * Every request is one JSON object on one line, with a "cmd" and an
+ * optional "id" that is echoed back:
+ *
+ *     {"id": 1, "cmd": "move", "move": "e4"}
+ *
+ * and every request gets exactly one response line, either
+ *
+ *     {"id": 1, "ok": true, ...}
+ *
+ * or, when something went wrong,
+ *
+ *     {"id": 1, "ok": false, "error": {"code": "illegal_move", "message": "..."}}
+ *
+ * Commands: new (optional "fen"), move ("move" in SAN, UCI or long form),
+ * legal_moves, undo, status, fen and search (optional "depth").
+ */
+
+use crate::engine;
+use crate::game::{Game, GameResult};
+use crate::movegen::{square_name, LegalMove};
+use crate::serialization::color_name;
+use crate::*;
+use json::JsonValue;
+use std::io::{self, BufRead, Write};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exchange(session: &mut Session, line: &str) -> JsonValue {
+        json::parse(&session.handle_line(line)).unwrap()
+    }
+
+    #[test]
+    fn play_and_query() {
+        let mut session = Session::new();
+        let response = exchange(&mut session, r#"{"id": 7, "cmd": "move", "move": "e4"}"#);
+        assert_eq!(response["id"], 7);
+        assert_eq!(response["ok"], true);
+        assert_eq!(response["move"]["san"], "e4");
+        assert_eq!(response["turn"], "black");
+
+        let response = exchange(&mut session, r#"{"cmd": "legal_moves"}"#);
+        assert_eq!(response["moves"].len(), 20);
+        assert!(response["id"].is_null());
+
+        let response = exchange(&mut session, r#"{"cmd": "undo"}"#);
+        assert_eq!(response["move"]["uci"], "e2e4");
+        let response = exchange(&mut session, r#"{"cmd": "fen"}"#);
+        assert_eq!(response["fen"], crate::fen::START_FEN);
+
+        let response = exchange(
+            &mut session,
+            r#"{"cmd": "new", "fen": "6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1"}"#,
+        );
+        assert_eq!(response["ok"], true);
+        let response = exchange(&mut session, r#"{"cmd": "search", "depth": 2}"#);
+        assert_eq!(response["best_move"]["uci"], "a1a8");
+
+        let response = exchange(&mut session, r#"{"cmd": "move", "move": "Ra8"}"#);
+        assert_eq!(response["result"], "1-0");
+        let response = exchange(&mut session, r#"{"cmd": "status"}"#);
+        assert_eq!(response["termination"], "checkmate");
+    }
+
+    #[test]
+    fn structured_errors() {
+        let mut session = Session::new();
+        let response = exchange(&mut session, "e4");
+        assert_eq!(response["ok"], false);
+        assert_eq!(response["error"]["code"], "parse_error");
+
+        let response = exchange(&mut session, r#"{"id": "a", "cmd": "fly"}"#);
+        assert_eq!(response["id"], "a");
+        assert_eq!(response["error"]["code"], "unknown_command");
+
+        let response = exchange(&mut session, r#"{"cmd": "move"}"#);
+        assert_eq!(response["error"]["code"], "missing_field");
+
+        let response = exchange(&mut session, r#"{"cmd": "move", "move": "Ke2"}"#);
+        assert_eq!(response["error"]["code"], "illegal_move");
+        assert_eq!(response["error"]["reason"], "own_piece_on_target");
+
+        let response = exchange(&mut session, r#"{"cmd": "undo"}"#);
+        assert_eq!(response["error"]["code"], "nothing_to_undo");
+
+        let mut output = vec![];
+        let input = "{\"cmd\": \"fen\"}\n\n{\"cmd\": \"move\", \"move\": \"e4\"}\n";
+        session.run(input.as_bytes(), &mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap().lines().count(), 2);
+    }
+}
+
+/* Deeper searches take too long to be useful behind a request */
+pub const MAX_SEARCH_DEPTH: usize = 6;
+pub const DEFAULT_SEARCH_DEPTH: usize = 3;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProtocolError {
+    pub code: &'static str,
+    pub message: String,
+    /* for illegal moves, the reason as a code like "king_in_check" */
+    pub reason: Option<&'static str>,
+}
+
+impl ProtocolError {
+    pub fn new(code: &'static str, message: String) -> ProtocolError {
+        ProtocolError {
+            code,
+            message,
+            reason: None,
+        }
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        let mut error = json::object! {
+            code: self.code,
+            message: self.message.as_str(),
+        };
+        if let Some(reason) = self.reason {
+            error["reason"] = reason.into();
+        }
+        error
+    }
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.code, self.message)
+    }
+}
+
+impl From<MoveError> for ProtocolError {
+    fn from(error: MoveError) -> ProtocolError {
+        let code = match error {
+            MoveError::GameOver => "game_over",
+            _ => "illegal_move",
+        };
+        ProtocolError {
+            code,
+            message: error.to_string(),
+            reason: Some(move_error_code(&error)),
+        }
+    }
+}
+
+pub fn move_error_code(error: &MoveError) -> &'static str {
+    match error {
+        MoveError::Unparsable => "unparsable",
+        MoveError::NoSuchPiece => "no_such_piece",
+        MoveError::IllegalMove => "illegal_move",
+        MoveError::CastlingNotAllowed => "castling_not_allowed",
+        MoveError::KingInCheck => "king_in_check",
+        MoveError::OwnPieceOnTarget => "own_piece_on_target",
+        MoveError::NothingToCapture => "nothing_to_capture",
+        MoveError::CaptureNotMarked => "capture_not_marked",
+        MoveError::InvalidPromotion => "invalid_promotion",
+        MoveError::Ambiguous(_) => "ambiguous",
+        MoveError::GameOver => "game_over",
+    }
+}
+
+pub fn move_to_json(m: &LegalMove) -> JsonValue {
+    json::object! {
+        san: m.san.as_str(),
+        uci: m.uci.as_str(),
+        piece: m.piece.to_string(),
+        from: square_name(m.from),
+        to: square_name(m.to),
+        capture: m.capture,
+        promotion: m.promotion.map(|p| p.to_string()),
+    }
+}
+
+pub fn required_str<'a>(request: &'a JsonValue, field: &str) -> Result<&'a str, ProtocolError> {
+    let value = &request[field];
+    if value.is_null() {
+        return Err(ProtocolError::new(
+            "missing_field",
+            format!("'{}' is required", field),
+        ));
+    }
+    value
+        .as_str()
+        .ok_or_else(|| ProtocolError::new("invalid_field", format!("'{}' must be a string", field)))
+}
+
+/* The commands that act on a single game, shared with the server */
+pub fn game_command(
+    game: &mut Game,
+    command: &str,
+    request: &JsonValue,
+) -> Result<JsonValue, ProtocolError> {
+    match command {
+        "new" => {
+            *game = match request["fen"].as_str() {
+                Some(fen) => Game::from_notation(
+                    AlgebraicNotation::from_fen(fen)
+                        .map_err(|e| ProtocolError::new("invalid_field", e.to_string()))?,
+                ),
+                None if request["fen"].is_null() => Game::new(),
+                None => {
+                    return Err(ProtocolError::new(
+                        "invalid_field",
+                        "'fen' must be a string".to_string(),
+                    ))
+                }
+            };
+            Ok(game.to_json())
+        }
+        "move" => {
+            let input = required_str(request, "move")?;
+            let played = game.play(input)?;
+            let mut response = game.to_json();
+            response["move"] = move_to_json(&played);
+            Ok(response)
+        }
+        "legal_moves" => {
+            let mut moves = JsonValue::new_array();
+            if game.result() == GameResult::Ongoing {
+                for m in game.notation().legal_moves() {
+                    moves.push(move_to_json(&m)).unwrap();
+                }
+            }
+            Ok(json::object! {
+                turn: color_name(game.notation().turn()),
+                moves: moves,
+            })
+        }
+        "undo" => match game.undo() {
+            Some(m) => {
+                let mut response = game.to_json();
+                response["move"] = move_to_json(&m);
+                Ok(response)
+            }
+            None => Err(ProtocolError::new(
+                "nothing_to_undo",
+                "no moves have been played".to_string(),
+            )),
+        },
+        "status" => Ok(game.to_json()),
+        "fen" => Ok(json::object! { fen: game.fen() }),
+        "search" => {
+            let depth = match &request["depth"] {
+                JsonValue::Null => DEFAULT_SEARCH_DEPTH,
+                value => match value.as_usize() {
+                    Some(d) if (1..=MAX_SEARCH_DEPTH).contains(&d) => d,
+                    _ => {
+                        return Err(ProtocolError::new(
+                            "invalid_field",
+                            format!("'depth' must be between 1 and {}", MAX_SEARCH_DEPTH),
+                        ))
+                    }
+                },
+            };
+            let result = engine::search(game.notation(), depth);
+            Ok(json::object! {
+                best_move: result.best_move.as_ref().map(move_to_json),
+                score: result.score,
+                nodes: result.nodes,
+                depth: depth,
+            })
+        }
+        other => Err(ProtocolError::new(
+            "unknown_command",
+            format!("unknown command '{}'", other),
+        )),
+    }
+}
+
+/* Wraps a result into a response line, echoing the request id */
+pub fn respond(id: &JsonValue, result: Result<JsonValue, ProtocolError>) -> JsonValue {
+    let mut response = match result {
+        Ok(mut fields) => {
+            fields["ok"] = true.into();
+            fields
+        }
+        Err(e) => json::object! {
+            ok: false,
+            error: e.to_json(),
+        },
+    };
+    if !id.is_null() {
+        response["id"] = id.clone();
+    }
+    response
+}
+
+/* Parses a request line into the request object and its command */
+pub fn parse_request(line: &str) -> Result<(JsonValue, String), (JsonValue, ProtocolError)> {
+    let request = json::parse(line).map_err(|e| {
+        (
+            JsonValue::Null,
+            ProtocolError::new("parse_error", e.to_string()),
+        )
+    })?;
+    if !request.is_object() {
+        return Err((
+            JsonValue::Null,
+            ProtocolError::new("parse_error", "a request must be an object".to_string()),
+        ));
+    }
+    let command = required_str(&request, "cmd")
+        .map_err(|e| (request["id"].clone(), e))?
+        .to_string();
+    Ok((request, command))
+}
+
+pub struct Session {
+    game: Game,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Session {
+    pub fn new() -> Session {
+        Session { game: Game::new() }
+    }
+
+    pub fn game(&self) -> &Game {
+        &self.game
+    }
+
+    pub fn handle_line(&mut self, line: &str) -> String {
+        let response = match parse_request(line) {
+            Ok((request, command)) => respond(
+                &request["id"],
+                game_command(&mut self.game, &command, &request),
+            ),
+            Err((id, e)) => respond(&id, Err(e)),
+        };
+        response.dump()
+    }
+
+    /* Answers requests until the input ends; blank lines are ignored */
+    pub fn run<R: BufRead, W: Write>(&mut self, input: R, mut output: W) -> io::Result<()> {
+        for line in input.lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            writeln!(output, "{}", self.handle_line(&line))?;
+            output.flush()?;
+        }
+        Ok(())
+    }
+}
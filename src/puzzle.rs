@@ -0,0 +1,295 @@
+/*
+ * Puzzles: a position and the moves that solve it, alternating between
+ * the solver and the replies, which are played automatically.
+ *
+ * In a mate in N any move that still mates in time is accepted. Once the
+ * solver leaves the given line that way, the replies come from the engine
+ * and every later move is checked by searching for the mate.
+ */
+
+use crate::engine::{self, MATE_SCORE};
+use crate::epd::EpdRecord;
+use crate::fen::FenError;
+use crate::movegen::{square_name, LegalMove};
+use crate::*;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scripted_line() {
+        let puzzle =
+            Puzzle::new("r3k3/8/8/3N4/8/8/8/4K3 w - - 0 1", &["Nc7+", "Kd7", "Nxa8"]).unwrap();
+        assert_eq!(puzzle.mate_in, None);
+        let mut session = PuzzleSession::new(&puzzle);
+
+        assert_eq!(session.try_move("Nf6+"), Ok(Attempt::Wrong));
+        assert_eq!(session.hint(), Some("move the piece on d5".to_string()));
+        assert_eq!(session.hint(), Some("Nc7+".to_string()));
+        match session.try_move("Nc7") {
+            Ok(Attempt::Correct { reply }) => assert_eq!(reply.unwrap().san, "Kd7"),
+            other => panic!("{:?}", other),
+        }
+        assert!(session.try_move("Ke9").is_err());
+        assert_eq!(session.try_move("Nxa8"), Ok(Attempt::Solved));
+        assert!(session.solved());
+        assert_eq!((session.attempts(), session.hints()), (1, 2));
+        assert_eq!(session.try_move("Ke2"), Err(MoveError::GameOver));
+
+        assert!(Puzzle::new("r3k3/8/8/3N4/8/8/8/4K3 w - - 0 1", &["Nc7+", "Kd7"]).is_err());
+        assert!(Puzzle::new("r3k3/8/8/3N4/8/8/8/4K3 w - - 0 1", &["Nc8"]).is_err());
+    }
+
+    #[test]
+    fn alternative_mates() {
+        let puzzle = Puzzle::new("6k1/5ppp/8/8/8/8/8/RR4K1 w - - 0 1", &["Ra8#"]).unwrap();
+        assert_eq!(puzzle.mate_in, Some(1));
+        let mut session = PuzzleSession::new(&puzzle);
+        assert_eq!(session.try_move("Rb7"), Ok(Attempt::Wrong));
+        assert_eq!(session.try_move("Rb8"), Ok(Attempt::Solved));
+
+        /* mate in two, where the first move may also be Rb7 */
+        let record =
+            EpdRecord::parse("6k1/8/8/8/8/8/R7/1R4K1 w - - dm 2; pv Ra7 Kf8 Rb8#;").unwrap();
+        let puzzle = Puzzle::from_epd(&record).unwrap();
+        assert_eq!(puzzle.mate_in, Some(2));
+        let mut session = PuzzleSession::new(&puzzle);
+        match session.try_move("Rb7") {
+            Ok(Attempt::Correct { reply }) => assert!(reply.is_some()),
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(session.hint(), Some("move the piece on a2".to_string()));
+        assert_eq!(session.try_move("Ra8"), Ok(Attempt::Solved));
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PuzzleError {
+    Fen(FenError),
+    IllegalMove {
+        index: usize,
+        input: String,
+        error: MoveError,
+    },
+    /* the solution has to end with a move of the solver */
+    Incomplete,
+}
+
+impl fmt::Display for PuzzleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PuzzleError::Fen(e) => write!(f, "{}", e),
+            PuzzleError::IllegalMove {
+                index,
+                input,
+                error,
+            } => write!(f, "solution move {} ({}): {}", index + 1, input, error),
+            PuzzleError::Incomplete => write!(f, "the solution ends with a reply"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Puzzle {
+    pub start: AlgebraicNotation,
+    /* the solver's moves and the replies, starting with the solver */
+    pub solution: Vec<LegalMove>,
+    pub mate_in: Option<u32>,
+}
+
+impl Puzzle {
+    /* The mate length is found from the solution when it ends in mate */
+    pub fn new(fen: &str, moves: &[&str]) -> Result<Puzzle, PuzzleError> {
+        let start = AlgebraicNotation::from_fen(fen).map_err(PuzzleError::Fen)?;
+        if moves.len().is_multiple_of(2) {
+            return Err(PuzzleError::Incomplete);
+        }
+        let mut position = start.clone();
+        let mut solution = vec![];
+        for (index, input) in moves.iter().enumerate() {
+            let played = position
+                .play_move(input)
+                .map_err(|error| PuzzleError::IllegalMove {
+                    index,
+                    input: input.to_string(),
+                    error,
+                })?;
+            solution.push(played);
+        }
+        let mated = position.in_check() && !position.has_legal_move();
+        Ok(Puzzle {
+            start,
+            mate_in: if mated {
+                Some(moves.len() as u32 / 2 + 1)
+            } else {
+                None
+            },
+            solution,
+        })
+    }
+
+    /* The solution from pv, or a single best move from bm, and dm */
+    pub fn from_epd(record: &EpdRecord) -> Result<Puzzle, PuzzleError> {
+        let moves: Vec<&str> = match record.operation("pv") {
+            Some(pv) => pv.iter().map(|m| m.as_str()).collect(),
+            None => record.best_moves().into_iter().take(1).collect(),
+        };
+        let mut puzzle = Puzzle::new(&record.fen(), &moves)?;
+        puzzle.mate_in = puzzle.mate_in.or_else(|| record.mate_in());
+        Ok(puzzle)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Attempt {
+    /* right, and the reply that was played for the other side */
+    Correct { reply: Option<LegalMove> },
+    Solved,
+    Wrong,
+}
+
+#[derive(Debug, Clone)]
+pub struct PuzzleSession {
+    puzzle: Puzzle,
+    position: AlgebraicNotation,
+    /* moves played so far, by both sides */
+    ply: usize,
+    /* set once the solver found another mate than the given one */
+    off_line: bool,
+    solved: bool,
+    attempts: u32,
+    hints: u32,
+    /* hints given for the current move */
+    hint_level: u32,
+}
+
+impl PuzzleSession {
+    pub fn new(puzzle: &Puzzle) -> PuzzleSession {
+        PuzzleSession {
+            puzzle: puzzle.clone(),
+            position: puzzle.start.clone(),
+            ply: 0,
+            off_line: false,
+            solved: false,
+            attempts: 0,
+            hints: 0,
+            hint_level: 0,
+        }
+    }
+
+    pub fn position(&self) -> &AlgebraicNotation {
+        &self.position
+    }
+
+    pub fn solved(&self) -> bool {
+        self.solved
+    }
+
+    /* Wrong moves tried, illegal ones not counted */
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    pub fn hints(&self) -> u32 {
+        self.hints
+    }
+
+    /* Solver moves still to make, including the next one */
+    fn moves_left(&self) -> u32 {
+        match self.puzzle.mate_in {
+            Some(n) => n - self.ply as u32 / 2,
+            None => (self.puzzle.solution.len() - self.ply) as u32 / 2 + 1,
+        }
+    }
+
+    /* Whether the side that just moved mates within moves more of its own */
+    fn mates_in(after: &AlgebraicNotation, moves: u32) -> bool {
+        if !after.has_legal_move() {
+            return after.in_check();
+        }
+        if moves == 0 {
+            return false;
+        }
+        /* mated after the reply and moves more of its own */
+        let plies = 2 * moves as i32;
+        engine::search(after, plies as usize + 1).score <= -(MATE_SCORE - plies)
+    }
+
+    fn expected(&self) -> Option<&LegalMove> {
+        if self.off_line {
+            None
+        } else {
+            self.puzzle.solution.get(self.ply)
+        }
+    }
+
+    pub fn try_move(&mut self, input: &str) -> Result<Attempt, MoveError> {
+        if self.solved {
+            return Err(MoveError::GameOver);
+        }
+        let legal = self.position.resolve_move(input)?;
+        let mut after = self.position.clone();
+        after.try_move(&legal.notation)?;
+
+        let left = self.moves_left();
+        let right = match self.expected() {
+            Some(m) if m.uci == legal.uci => true,
+            _ => match self.puzzle.mate_in {
+                Some(_) => PuzzleSession::mates_in(&after, left - 1),
+                None => false,
+            },
+        };
+        if !right {
+            self.attempts += 1;
+            return Ok(Attempt::Wrong);
+        }
+
+        if self.expected().is_some_and(|m| m.uci != legal.uci) {
+            self.off_line = true;
+        }
+        self.position = after;
+        self.ply += 1;
+        self.hint_level = 0;
+        if left == 1 || !self.position.has_legal_move() {
+            self.solved = true;
+            return Ok(Attempt::Solved);
+        }
+
+        let reply = match self.expected() {
+            Some(m) => m.clone(),
+            /* the best defence the engine finds */
+            None => {
+                let depth = 2 * (left as usize - 1) + 1;
+                match engine::search(&self.position, depth).best_move {
+                    Some(m) => m,
+                    None => {
+                        self.solved = true;
+                        return Ok(Attempt::Solved);
+                    }
+                }
+            }
+        };
+        self.position.try_move(&reply.notation)?;
+        self.ply += 1;
+        Ok(Attempt::Correct { reply: Some(reply) })
+    }
+
+    /* First where to look, then the move itself */
+    pub fn hint(&mut self) -> Option<String> {
+        if self.solved {
+            return None;
+        }
+        let next = match self.expected() {
+            Some(m) => m.clone(),
+            None => engine::search(&self.position, 2 * self.moves_left() as usize).best_move?,
+        };
+        self.hints += 1;
+        self.hint_level += 1;
+        if self.hint_level == 1 {
+            Some(format!("move the piece on {}", square_name(next.from)))
+        } else {
+            Some(next.san)
+        }
+    }
+}
@@ -0,0 +1,167 @@
+/*
+ * Static exchange evaluation: what a series of captures on one square
+ * wins or loses when both sides recapture with their least valuable
+ * piece and may stop whenever going on would lose material. Attackers are
+ * looked up again after every capture, so a rook behind a rook or a queen
+ * behind a bishop joins in once the piece in front of it has taken.
+ */
+
+use crate::attacks::attackers_on;
+use crate::engine::piece_value;
+use crate::movegen::{piece_letter, LegalMove};
+use crate::*;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn square(name: &str) -> (usize, usize) {
+        movegen::parse_square(name).unwrap()
+    }
+
+    #[test]
+    fn exchanges() {
+        /* an undefended pawn */
+        let notat = AlgebraicNotation::from_fen("4k3/8/8/3p4/4P3/8/8/4K3 w - - 0 1").unwrap();
+        assert_eq!(see_square(&notat.board, square("d5"), _WHITE_PIECE), 100);
+        let hanging = notat.hanging_pieces(_BLACK_PIECE);
+        assert_eq!(hanging.len(), 1);
+        assert_eq!(hanging[0].0.position, square("d5"));
+
+        /* defended by a rook, and won with the second rook behind the first */
+        let notat = AlgebraicNotation::from_fen("3rk3/8/8/3p4/8/8/8/3RK3 w - - 0 1").unwrap();
+        assert_eq!(see_square(&notat.board, square("d5"), _WHITE_PIECE), -400);
+        assert!(notat.hanging_pieces(_BLACK_PIECE).is_empty());
+        let notat = AlgebraicNotation::from_fen("3rk3/8/8/3p4/8/8/3R4/3RK3 w - - 0 1").unwrap();
+        assert_eq!(see_square(&notat.board, square("d5"), _WHITE_PIECE), 100);
+
+        /* the pawn takes first, and the queen only if it pays */
+        let mut notat =
+            AlgebraicNotation::from_fen("4k3/8/2p5/3p4/4P3/8/8/3QK3 w - - 0 1").unwrap();
+        assert_eq!(see_square(&notat.board, square("d5"), _WHITE_PIECE), 100);
+        let queen_takes = notat.resolve_move("Qxd5").unwrap();
+        /* Qxd5 cxd5 exd5 */
+        assert_eq!(notat.see(&queen_takes), -700);
+        notat.play_move("exd5").unwrap();
+        /* cxd5 wins the pawn back, but the queen takes again */
+        assert_eq!(see_capture(&notat.board, square("c6"), square("d5")), 0);
+    }
+}
+
+/* High enough that taking the king ends every exchange */
+const KING_VALUE: i32 = 20_000;
+
+pub(crate) fn value(letter: char) -> i32 {
+    if letter == 'K' {
+        KING_VALUE
+    } else {
+        piece_value(letter)
+    }
+}
+
+fn letter_on(board: &Board, square: (usize, usize)) -> Option<char> {
+    board.table[square.0][square.1]
+        .as_ref()
+        .map(|p| piece_letter(p.as_ref()))
+}
+
+/* The exchange on square after the piece on from made the first capture,
+ * from the point of view of that piece's side */
+fn swap(board: &Board, from: (usize, usize), square: (usize, usize)) -> i32 {
+    let mut scratch = board.clone();
+    let mut color = match &board.table[from.0][from.1] {
+        Some(p) => p.get_color(),
+        None => return 0,
+    };
+    /* a pawn taking en passant finds the square empty */
+    let mut gains = vec![letter_on(board, square).map_or(piece_value('P'), value)];
+    let mut from = from;
+
+    loop {
+        let capturer = scratch.table[from.0][from.1].take();
+        let captured_next = capturer
+            .as_ref()
+            .map_or(0, |p| value(piece_letter(p.as_ref())));
+        scratch.table[square.0][square.1] = capturer;
+        color = 1 - color;
+
+        let next = attackers_on(&mut scratch, square, color)
+            .into_iter()
+            .min_by_key(|p| value(p.piece));
+        match next {
+            Some(attacker) => {
+                gains.push(captured_next - gains[gains.len() - 1]);
+                from = attacker.position;
+            }
+            None => break,
+        }
+        /* after a king is taken nothing else matters */
+        if captured_next == KING_VALUE {
+            break;
+        }
+    }
+
+    /* either side may decline to recapture */
+    while gains.len() > 1 {
+        let last = gains.pop().unwrap();
+        let previous = gains.len() - 1;
+        gains[previous] = -cmp::max(-gains[previous], last);
+    }
+    gains[0]
+}
+
+/* What capturing on to with the piece on from wins, with best recaptures */
+pub fn see_capture(board: &Board, from: (usize, usize), to: (usize, usize)) -> i32 {
+    swap(board, from, to)
+}
+
+/* The exchange when color starts taking on square with its least valuable
+ * attacker, or 0 when it can not take there */
+pub fn see_square(board: &Board, square: (usize, usize), color: usize) -> i32 {
+    match &board.table[square.0][square.1] {
+        Some(p) if p.get_color() != color => (),
+        _ => return 0,
+    }
+    let first = attackers_on(&mut board.clone(), square, color)
+        .into_iter()
+        .min_by_key(|p| value(p.piece));
+    match first {
+        Some(attacker) => swap(board, attacker.position, square),
+        None => 0,
+    }
+}
+
+impl AlgebraicNotation {
+    /* The material a capture wins or loses; 0 for quiet moves */
+    pub fn see(&self, m: &LegalMove) -> i32 {
+        if m.capture {
+            see_capture(&self.board, m.from, m.to)
+        } else {
+            0
+        }
+    }
+
+    /* Pieces of color the opponent wins material by taking, with how much */
+    pub fn hanging_pieces(&self, color: usize) -> Vec<(FoundPiece, i32)> {
+        let mut hanging = vec![];
+        for (r, row) in self.board.table.iter().enumerate() {
+            for (c, piece) in row.iter().enumerate() {
+                let piece = match piece {
+                    Some(p) if p.get_color() == color && piece_letter(p.as_ref()) != 'K' => p,
+                    _ => continue,
+                };
+                let loss = see_square(&self.board, (r, c), 1 - color);
+                if loss > 0 {
+                    hanging.push((
+                        FoundPiece {
+                            piece: piece_letter(piece.as_ref()),
+                            position: (r, c),
+                        },
+                        loss,
+                    ));
+                }
+            }
+        }
+        hanging
+    }
+}
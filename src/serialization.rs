@@ -0,0 +1,407 @@
+/*
+ * JSON export and import of whole games.
+ *
+ * Besides the current position (as FEN and as a board diagram, rank 8
+ * first) a game carries its start position and the moves played, and
+ * import replays those moves so that undo keeps working afterwards. A
+ * clock comes back with the time each side had left, running again on
+ * the real time for the side to move.
+ */
+
+use crate::clock::{Clock, MonotonicTime, TimeControl};
+use crate::fen::{fen_char, START_FEN};
+use crate::game::{Game, GameResult, Termination};
+use crate::movegen::square_name;
+use crate::*;
+use json::JsonValue;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_round_trip() {
+        let mut game = Game::new();
+        game.white = "Gustaf".to_string();
+        game.event = "Club championship".to_string();
+        game.round = "3".to_string();
+        for m in ["e4", "c5", "e5", "d5"] {
+            game.play(m).unwrap();
+        }
+        game.resign(_WHITE_PIECE);
+
+        let exported = game.to_json();
+        assert_eq!(exported["turn"], "white");
+        assert_eq!(exported["en_passant"], "d6");
+        assert_eq!(exported["castling"]["white_kingside"], true);
+        assert_eq!(exported["board"][3], "..ppP...");
+        assert_eq!(exported["moves"][3]["san"], "d5");
+        assert_eq!(exported["moves"][3]["uci"], "d7d5");
+        assert_eq!(exported["result"], "0-1");
+        assert_eq!(exported["termination"], "resignation");
+        assert_eq!(exported["event"], "Club championship");
+        assert_eq!(exported["round"], "3");
+
+        let mut imported = Game::from_json(&exported.dump()).unwrap();
+        assert_eq!(imported.fen(), game.fen());
+        assert_eq!(imported.white, "Gustaf");
+        assert_eq!(imported.event, "Club championship");
+        assert_eq!(imported.round, "3");
+        assert_eq!(imported.result(), GameResult::BlackWins);
+        assert_eq!(imported.termination(), Some(Termination::Resignation));
+        assert_eq!(imported.to_pgn(), game.to_pgn());
+        assert_eq!(imported.undo().unwrap().san, "d5");
+    }
+
+    #[test]
+    fn flagged_game_with_a_clock() {
+        use crate::clock::ManualTime;
+
+        let mut game = Game::new();
+        let time = Arc::new(ManualTime::default());
+        let control = TimeControl::parse("2/60+1:30").unwrap();
+        game.set_clock(Clock::new(control.clone(), time.clone()).unwrap());
+        for m in ["e4", "e5", "Nf3"] {
+            time.advance(Duration::from_secs(5));
+            game.play(m).unwrap();
+        }
+        time.advance(Duration::from_secs(70));
+        assert!(game.check_flag());
+
+        let exported = game.to_json();
+        assert_eq!(exported["termination"], "time_forfeit");
+        let imported = Game::from_json(&exported.dump()).unwrap();
+        assert_eq!(imported.result(), GameResult::WhiteWins);
+        assert_eq!(imported.termination(), Some(Termination::TimeForfeit));
+        let clock = imported.clock().unwrap();
+        assert_eq!(clock.control(), &control);
+        assert_eq!(clock.running(), None);
+        /* white is into the second stage: 60 - 10 + 2 + 30 */
+        assert_eq!(clock.remaining(_WHITE_PIECE), Duration::from_secs(82));
+        assert_eq!(clock.remaining(_BLACK_PIECE), Duration::ZERO);
+        assert_eq!(imported.to_json()["clock"], exported["clock"]);
+
+        /* a game that goes on gets its clock running again */
+        let mut game = Game::new();
+        game.set_clock(Clock::new(control, time.clone()).unwrap());
+        game.play("d4").unwrap();
+        let imported = Game::from_json(&game.to_json_string()).unwrap();
+        assert_eq!(imported.clock().unwrap().running(), Some(_BLACK_PIECE));
+
+        let broken = r#"{"clock": {"control": "5x", "white_ms": 1, "black_ms": 1}}"#;
+        assert!(matches!(
+            Game::from_json(broken),
+            Err(SerializationError::Invalid("clock", _))
+        ));
+    }
+
+    #[test]
+    fn json_errors() {
+        assert!(matches!(
+            Game::from_json("{"),
+            Err(SerializationError::Parse(_))
+        ));
+        assert_eq!(
+            Game::from_json(r#"{"moves": [{"san": "e4"}, {"san": "e4"}]}"#).err(),
+            Some(SerializationError::IllegalMove {
+                index: 1,
+                input: "e4".to_string(),
+                error: MoveError::IllegalMove
+            })
+        );
+        assert!(matches!(
+            Game::from_json(r#"{"moves": ["e4"], "fen": "8/8/8/8/8/8/8/8 w - - 0 1"}"#),
+            Err(SerializationError::Invalid("fen", _))
+        ));
+        assert!(matches!(
+            Game::from_json(r#"{"result": "1-0"}"#),
+            Err(SerializationError::Invalid("termination", _))
+        ));
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SerializationError {
+    Parse(String),
+    Invalid(&'static str, String),
+    IllegalMove {
+        index: usize,
+        input: String,
+        error: MoveError,
+    },
+}
+
+impl fmt::Display for SerializationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SerializationError::Parse(e) => write!(f, "malformed JSON: {}", e),
+            SerializationError::Invalid(field, e) => write!(f, "invalid {}: {}", field, e),
+            SerializationError::IllegalMove {
+                index,
+                input,
+                error,
+            } => write!(f, "move {} ({}): {}", index + 1, input, error),
+        }
+    }
+}
+
+pub fn color_name(color: usize) -> &'static str {
+    if color == _WHITE_PIECE {
+        "white"
+    } else {
+        "black"
+    }
+}
+
+pub fn color_from_name(name: &str) -> Option<usize> {
+    match name {
+        "white" => Some(_WHITE_PIECE),
+        "black" => Some(_BLACK_PIECE),
+        _ => None,
+    }
+}
+
+pub fn board_to_json(board: &Board) -> JsonValue {
+    let mut ranks = JsonValue::new_array();
+    for row in board.table.iter().rev() {
+        let rank: String = row
+            .iter()
+            .map(|square| match square {
+                Some(p) => fen_char(p.as_ref()),
+                None => '.',
+            })
+            .collect();
+        ranks.push(rank).unwrap();
+    }
+    ranks
+}
+
+/* The current position on its own, as also used by the command protocol */
+pub fn position_to_json(notation: &AlgebraicNotation) -> JsonValue {
+    let state = notation.state();
+
+    let mut position = JsonValue::new_object();
+    position["fen"] = notation.to_fen().into();
+    position["board"] = board_to_json(notation.board());
+    position["turn"] = color_name(notation.turn()).into();
+    position["castling"] = json::object! {
+        white_kingside: state.castling.white_kingside,
+        white_queenside: state.castling.white_queenside,
+        black_kingside: state.castling.black_kingside,
+        black_queenside: state.castling.black_queenside,
+    };
+    position["en_passant"] = state.en_passant.map(square_name).into();
+    position["halfmove_clock"] = state.halfmove_clock.into();
+    position["fullmove_number"] = state.fullmove_number.into();
+    position["check"] = notation.in_check().into();
+    position
+}
+
+impl Game {
+    pub fn to_json(&self) -> JsonValue {
+        let mut game = position_to_json(self.notation());
+        game["start_fen"] = self.start().to_fen().into();
+
+        let mut moves = JsonValue::new_array();
+        for m in self.moves() {
+            moves
+                .push(json::object! {
+                    san: m.san.as_str(),
+                    uci: m.uci.as_str(),
+                })
+                .unwrap();
+        }
+        game["moves"] = moves;
+
+        game["result"] = self.result().pgn().into();
+        game["termination"] = match self.termination() {
+            Some(t) => t.name().into(),
+            None => JsonValue::Null,
+        };
+        game["draw_offer"] = match self.draw_offer() {
+            Some(c) => color_name(c).into(),
+            None => JsonValue::Null,
+        };
+        game["clock"] = match self.clock() {
+            Some(clock) => json::object! {
+                control: clock.control().to_string(),
+                white_ms: clock.remaining(_WHITE_PIECE).as_millis() as u64,
+                black_ms: clock.remaining(_BLACK_PIECE).as_millis() as u64,
+                running: clock.running().map(color_name),
+            },
+            None => JsonValue::Null,
+        };
+        game["white"] = self.white.as_str().into();
+        game["black"] = self.black.as_str().into();
+        game["event"] = self.event.as_str().into();
+        game["round"] = self.round.as_str().into();
+        game
+    }
+
+    pub fn to_json_string(&self) -> String {
+        self.to_json().dump()
+    }
+
+    pub fn from_json(text: &str) -> Result<Game, SerializationError> {
+        let value = json::parse(text).map_err(|e| SerializationError::Parse(e.to_string()))?;
+        Game::from_json_value(&value)
+    }
+
+    /* Replays the moves from start_fen; fields that follow from the moves are only checked */
+    pub fn from_json_value(value: &JsonValue) -> Result<Game, SerializationError> {
+        if !value.is_object() {
+            return Err(SerializationError::Parse("expected an object".to_string()));
+        }
+
+        let start_fen = optional_str(value, "start_fen")?.unwrap_or(START_FEN);
+        let start = AlgebraicNotation::from_fen(start_fen)
+            .map_err(|e| SerializationError::Invalid("start_fen", e.to_string()))?;
+        let mut game = Game::from_notation(start);
+
+        if let Some(name) = optional_str(value, "white")? {
+            game.white = name.to_string();
+        }
+        if let Some(name) = optional_str(value, "black")? {
+            game.black = name.to_string();
+        }
+        if let Some(event) = optional_str(value, "event")? {
+            game.event = event.to_string();
+        }
+        if let Some(round) = optional_str(value, "round")? {
+            game.round = round.to_string();
+        }
+
+        let moves = &value["moves"];
+        if !moves.is_null() && !moves.is_array() {
+            return Err(SerializationError::Invalid(
+                "moves",
+                "expected an array".to_string(),
+            ));
+        }
+        for (index, m) in moves.members().enumerate() {
+            /* either {"uci": ..., "san": ...} or a plain string */
+            let input = m["uci"]
+                .as_str()
+                .or_else(|| m["san"].as_str())
+                .or_else(|| m.as_str())
+                .ok_or_else(|| {
+                    SerializationError::Invalid(
+                        "moves",
+                        format!("move {} has no uci or san", index + 1),
+                    )
+                })?;
+            game.play(input)
+                .map_err(|error| SerializationError::IllegalMove {
+                    index,
+                    input: input.to_string(),
+                    error,
+                })?;
+        }
+
+        if let Some(fen) = optional_str(value, "fen")? {
+            if fen != game.fen() {
+                return Err(SerializationError::Invalid(
+                    "fen",
+                    format!("the moves lead to {}", game.fen()),
+                ));
+            }
+        }
+
+        if let Some(token) = optional_str(value, "result")? {
+            let result = GameResult::from_pgn(token).ok_or_else(|| {
+                SerializationError::Invalid("result", format!("unknown result '{}'", token))
+            })?;
+            let termination = match optional_str(value, "termination")? {
+                Some(name) => Some(Termination::from_name(name).ok_or_else(|| {
+                    SerializationError::Invalid(
+                        "termination",
+                        format!("unknown termination '{}'", name),
+                    )
+                })?),
+                None => None,
+            };
+
+            if game.result() != GameResult::Ongoing {
+                if result != game.result() {
+                    return Err(SerializationError::Invalid(
+                        "result",
+                        format!("the moves end the game {}", game.result().pgn()),
+                    ));
+                }
+            } else if result != GameResult::Ongoing {
+                match termination {
+                    Some(
+                        t @ (Termination::Resignation
+                        | Termination::Agreement
+                        | Termination::TimeForfeit
+                        | Termination::Adjudication),
+                    ) => game.finish(result, t),
+                    _ => {
+                        return Err(SerializationError::Invalid(
+                            "termination",
+                            format!(
+                                "{} can not end this game",
+                                termination.map_or("nothing", |t| t.name())
+                            ),
+                        ))
+                    }
+                }
+            }
+        }
+
+        if let Some(name) = optional_str(value, "draw_offer")? {
+            let color = color_from_name(name).ok_or_else(|| {
+                SerializationError::Invalid("draw_offer", format!("unknown color '{}'", name))
+            })?;
+            if game.result() == GameResult::Ongoing {
+                game.set_draw_offer(Some(color));
+            }
+        }
+
+        if !value["clock"].is_null() {
+            let clock = clock_from_json(&value["clock"], &game)?;
+            game.set_clock(clock);
+        }
+
+        Ok(game)
+    }
+}
+
+/* The clock as to_json writes it, for the game its moves were replayed in */
+fn clock_from_json(value: &JsonValue, game: &Game) -> Result<Clock, SerializationError> {
+    let invalid = |why: String| SerializationError::Invalid("clock", why);
+    let control = value["control"]
+        .as_str()
+        .ok_or_else(|| invalid("'control' is required".to_string()))?;
+    let control = TimeControl::parse(control).map_err(|e| invalid(e.to_string()))?;
+    let mut remaining = [Duration::ZERO; 2];
+    for (color, field) in [(_WHITE_PIECE, "white_ms"), (_BLACK_PIECE, "black_ms")] {
+        let ms = value[field]
+            .as_u64()
+            .ok_or_else(|| invalid(format!("'{}' must be milliseconds", field)))?;
+        remaining[color] = Duration::from_millis(ms);
+    }
+    /* every other move, starting with the side to move at the start */
+    let first = game.start().turn();
+    let plies = game.moves().len() as u32;
+    let mut made = [0; 2];
+    made[first] = plies.div_ceil(2);
+    made[1 - first] = plies / 2;
+    Clock::resume(control, Arc::new(MonotonicTime::default()), made, remaining)
+        .map_err(|e| invalid(e.to_string()))
+}
+
+fn optional_str<'a>(
+    value: &'a JsonValue,
+    field: &'static str,
+) -> Result<Option<&'a str>, SerializationError> {
+    let v = &value[field];
+    if v.is_null() {
+        return Ok(None);
+    }
+    v.as_str()
+        .map(Some)
+        .ok_or_else(|| SerializationError::Invalid(field, "expected a string".to_string()))
+}
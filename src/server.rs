@@ -0,0 +1,523 @@
+/*
+ * Local server hosting many games at once over TCP.
+ *
+ * Clients speak the line-delimited JSON protocol, with every game
+ * addressed by the id returned from "create". Commands:
+ *
+ *     create   start a game, optionally from "fen" and taking a "color"
+ *     join     take a free seat in "game", optionally a given "color"
+ *     watch    follow "game" as a spectator
+ *     list     the games on the server
+ *     move     play "move" in "game", only for the player to move
+ *     resign   resign "game"
+ *     legal_moves, status, fen, search   as in the single game protocol
+ *
+ * Everybody seated at or watching a game gets an event line when
+ * something happens in it, e.g.
+ *
+ *     {"event": "move", "game": 1, "move": {...}, "fen": "...", "result": "*"}
+ *
+ * Searches and move lists work on a copy of the game, so the lobby is
+ * not locked while the engine thinks and other games go on meanwhile.
+ */
+
+use crate::game::{Game, GameResult};
+use crate::protocol::{self, game_command, move_to_json, respond, ProtocolError};
+use crate::serialization::{color_from_name, color_name};
+use crate::*;
+use json::JsonValue;
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::Receiver;
+
+    fn client(lobby: &mut Lobby) -> (ClientId, Receiver<String>) {
+        let (sender, receiver) = mpsc::channel();
+        (lobby.connect(sender), receiver)
+    }
+
+    fn request(lobby: &mut Lobby, client: ClientId, line: &str) -> JsonValue {
+        json::parse(&lobby.handle_line(client, line)).unwrap()
+    }
+
+    #[test]
+    fn two_players_and_a_spectator() {
+        let mut lobby = Lobby::new();
+        let (alice, _) = client(&mut lobby);
+        let (bob, bob_events) = client(&mut lobby);
+        let (carol, carol_events) = client(&mut lobby);
+
+        let created = request(&mut lobby, alice, r#"{"cmd": "create", "color": "white"}"#);
+        let id = created["game"].as_u64().unwrap();
+        let line = format!(r#"{{"cmd": "join", "game": {}}}"#, id);
+        assert_eq!(request(&mut lobby, bob, &line)["color"], "black");
+        let line = format!(r#"{{"cmd": "watch", "game": {}}}"#, id);
+        assert_eq!(request(&mut lobby, carol, &line)["ok"], true);
+
+        let line = format!(r#"{{"cmd": "move", "game": {}, "move": "e5"}}"#, id);
+        let response = request(&mut lobby, bob, &line);
+        assert_eq!(response["error"]["code"], "not_your_turn");
+
+        let line = format!(r#"{{"cmd": "move", "game": {}, "move": "e4"}}"#, id);
+        assert_eq!(request(&mut lobby, alice, &line)["ok"], true);
+        let event = json::parse(&carol_events.try_recv().unwrap()).unwrap();
+        assert_eq!(event["event"], "move");
+        assert_eq!(event["move"]["san"], "e4");
+        /* bob was told about carol's arrival first */
+        let events: Vec<JsonValue> = bob_events
+            .try_iter()
+            .map(|e| json::parse(&e).unwrap())
+            .collect();
+        assert_eq!(events.last().unwrap()["move"]["uci"], "e2e4");
+
+        let line = format!(r#"{{"cmd": "resign", "game": {}}}"#, id);
+        assert_eq!(
+            request(&mut lobby, carol, &line)["error"]["code"],
+            "not_a_player"
+        );
+        assert_eq!(request(&mut lobby, bob, &line)["result"], "1-0");
+        let event = json::parse(&carol_events.try_recv().unwrap()).unwrap();
+        assert_eq!(event["event"], "result");
+        assert_eq!(event["termination"], "resignation");
+    }
+
+    #[test]
+    fn games_are_independent() {
+        let mut lobby = Lobby::new();
+        let (alice, _) = client(&mut lobby);
+        let first = request(&mut lobby, alice, r#"{"cmd": "create", "color": "white"}"#);
+        let second = request(&mut lobby, alice, r#"{"cmd": "create", "color": "black"}"#);
+        assert_ne!(first["game"], second["game"]);
+
+        let line = format!(
+            r#"{{"cmd": "move", "game": {}, "move": "d4"}}"#,
+            first["game"]
+        );
+        assert_eq!(request(&mut lobby, alice, &line)["ok"], true);
+        let line = format!(r#"{{"cmd": "fen", "game": {}}}"#, second["game"]);
+        assert_eq!(
+            request(&mut lobby, alice, &line)["fen"],
+            crate::fen::START_FEN
+        );
+
+        let listed = request(&mut lobby, alice, r#"{"cmd": "list"}"#);
+        assert_eq!(listed["games"].len(), 2);
+        let line = r#"{"cmd": "status", "game": 99}"#;
+        assert_eq!(
+            request(&mut lobby, alice, line)["error"]["code"],
+            "no_such_game"
+        );
+
+        /* leaving frees the seats */
+        lobby.disconnect(alice);
+        let (bob, _) = client(&mut lobby);
+        let line = format!(
+            r#"{{"cmd": "join", "game": {}, "color": "white"}}"#,
+            first["game"]
+        );
+        assert_eq!(request(&mut lobby, bob, &line)["ok"], true);
+    }
+
+    #[test]
+    fn searching_outside_the_lock() {
+        let lobby = Mutex::new(Lobby::new());
+        let (sender, _events) = mpsc::channel();
+        let alice = lock(&lobby).connect(sender);
+        let created = json::parse(&handle_shared(&lobby, alice, r#"{"cmd": "create"}"#)).unwrap();
+        let line = format!(
+            r#"{{"id": 7, "cmd": "search", "game": {}, "depth": 1}}"#,
+            created["game"]
+        );
+        let response = json::parse(&handle_shared(&lobby, alice, &line)).unwrap();
+        assert_eq!(response["id"], 7);
+        assert_eq!(response["game"], created["game"]);
+        assert!(response["best_move"]["uci"].is_string());
+        let line = r#"{"cmd": "legal_moves", "game": 5}"#;
+        let response = json::parse(&handle_shared(&lobby, alice, line)).unwrap();
+        assert_eq!(response["error"]["code"], "no_such_game");
+
+        /* a handler that panicked does not take the others with it */
+        let lobby = Arc::new(lobby);
+        let poisoner = Arc::clone(&lobby);
+        let _ = thread::spawn(move || {
+            let _guard = poisoner.lock().unwrap();
+            panic!("handler failed");
+        })
+        .join();
+        assert!(lobby.is_poisoned());
+        let response = json::parse(&handle_shared(&lobby, alice, r#"{"cmd": "list"}"#)).unwrap();
+        assert_eq!(response["games"].len(), 1);
+    }
+
+    #[test]
+    fn over_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || serve(listener));
+
+        let stream = TcpStream::connect(address).unwrap();
+        let mut writer = stream.try_clone().unwrap();
+        let mut reader = BufReader::new(stream);
+        writeln!(writer, r#"{{"id": 1, "cmd": "create"}}"#).unwrap();
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        let response = json::parse(&line).unwrap();
+        assert_eq!(response["id"], 1);
+        assert_eq!(response["game"], 1);
+    }
+}
+
+pub type ClientId = u64;
+
+struct Room {
+    game: Game,
+    seats: [Option<ClientId>; 2],
+    spectators: Vec<ClientId>,
+}
+
+impl Room {
+    fn seat_of(&self, client: ClientId) -> Option<usize> {
+        self.seats.iter().position(|s| *s == Some(client))
+    }
+
+    fn audience(&self) -> Vec<ClientId> {
+        self.seats
+            .iter()
+            .flatten()
+            .chain(self.spectators.iter())
+            .copied()
+            .collect()
+    }
+
+    fn summary(&self, id: u64) -> JsonValue {
+        json::object! {
+            game: id,
+            white_seated: self.seats[_WHITE_PIECE].is_some(),
+            black_seated: self.seats[_BLACK_PIECE].is_some(),
+            spectators: self.spectators.len(),
+            moves: self.game.moves().len(),
+            result: self.game.result().pgn(),
+        }
+    }
+}
+
+/* All games and connected clients; the sockets themselves live in serve */
+pub struct Lobby {
+    rooms: HashMap<u64, Room>,
+    clients: HashMap<ClientId, Sender<String>>,
+    next_game: u64,
+    next_client: ClientId,
+}
+
+impl Default for Lobby {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn error(code: &'static str, message: String) -> ProtocolError {
+    ProtocolError::new(code, message)
+}
+
+impl Lobby {
+    pub fn new() -> Lobby {
+        Lobby {
+            rooms: HashMap::new(),
+            clients: HashMap::new(),
+            next_game: 1,
+            next_client: 1,
+        }
+    }
+
+    /* Events for the client are sent to the given channel, one line each */
+    pub fn connect(&mut self, events: Sender<String>) -> ClientId {
+        let client = self.next_client;
+        self.next_client += 1;
+        self.clients.insert(client, events);
+        client
+    }
+
+    pub fn disconnect(&mut self, client: ClientId) {
+        self.clients.remove(&client);
+        let mut left = vec![];
+        for (id, room) in self.rooms.iter_mut() {
+            room.spectators.retain(|c| *c != client);
+            if let Some(color) = room.seat_of(client) {
+                room.seats[color] = None;
+                left.push((*id, color));
+            }
+        }
+        for (id, color) in left {
+            self.broadcast(
+                id,
+                client,
+                json::object! { event: "leave", game: id, color: color_name(color) },
+            );
+        }
+    }
+
+    /* Sends an event to everybody at the game except the client causing it */
+    fn broadcast(&self, id: u64, except: ClientId, event: JsonValue) {
+        let line = event.dump();
+        if let Some(room) = self.rooms.get(&id) {
+            for client in room.audience() {
+                if client == except {
+                    continue;
+                }
+                if let Some(sender) = self.clients.get(&client) {
+                    /* a client that went away is cleaned up by its own thread */
+                    let _ = sender.send(line.clone());
+                }
+            }
+        }
+    }
+
+    pub fn handle_line(&mut self, client: ClientId, line: &str) -> String {
+        let response = match protocol::parse_request(line) {
+            Ok((request, command)) => {
+                respond(&request["id"], self.command(client, &command, &request))
+            }
+            Err((id, e)) => respond(&id, Err(e)),
+        };
+        response.dump()
+    }
+
+    fn room_id(&self, request: &JsonValue) -> Result<u64, ProtocolError> {
+        let value = &request["game"];
+        if value.is_null() {
+            return Err(error("missing_field", "'game' is required".to_string()));
+        }
+        match value.as_u64() {
+            Some(id) if self.rooms.contains_key(&id) => Ok(id),
+            Some(id) => Err(error("no_such_game", format!("there is no game {}", id))),
+            None => Err(error(
+                "invalid_field",
+                "'game' must be a game id".to_string(),
+            )),
+        }
+    }
+
+    fn requested_color(request: &JsonValue) -> Result<Option<usize>, ProtocolError> {
+        match &request["color"] {
+            JsonValue::Null => Ok(None),
+            value => value
+                .as_str()
+                .and_then(color_from_name)
+                .map(Some)
+                .ok_or_else(|| {
+                    error(
+                        "invalid_field",
+                        "'color' must be white or black".to_string(),
+                    )
+                }),
+        }
+    }
+
+    fn command(
+        &mut self,
+        client: ClientId,
+        command: &str,
+        request: &JsonValue,
+    ) -> Result<JsonValue, ProtocolError> {
+        match command {
+            "create" => {
+                let color = Lobby::requested_color(request)?;
+                let mut game = Game::new();
+                if !request["fen"].is_null() {
+                    game_command(&mut game, "new", request)?;
+                }
+                let id = self.next_game;
+                self.next_game += 1;
+                let mut room = Room {
+                    game,
+                    seats: [None, None],
+                    spectators: vec![],
+                };
+                if let Some(c) = color {
+                    room.seats[c] = Some(client);
+                }
+                let mut response = room.game.to_json();
+                response["game"] = id.into();
+                response["color"] = color.map(color_name).into();
+                self.rooms.insert(id, room);
+                Ok(response)
+            }
+            "join" => {
+                let id = self.room_id(request)?;
+                let wanted = Lobby::requested_color(request)?;
+                let room = self.rooms.get_mut(&id).unwrap();
+                if room.seat_of(client).is_some() {
+                    return Err(error(
+                        "already_seated",
+                        format!("you already play in game {}", id),
+                    ));
+                }
+                let free = [_WHITE_PIECE, _BLACK_PIECE]
+                    .iter()
+                    .copied()
+                    .find(|c| room.seats[*c].is_none() && wanted.is_none_or(|w| w == *c))
+                    .ok_or_else(|| error("seat_taken", format!("no free seat in game {}", id)))?;
+                room.seats[free] = Some(client);
+                room.spectators.retain(|c| *c != client);
+
+                let mut response = room.game.to_json();
+                response["game"] = id.into();
+                response["color"] = color_name(free).into();
+                self.broadcast(
+                    id,
+                    client,
+                    json::object! { event: "join", game: id, color: color_name(free) },
+                );
+                Ok(response)
+            }
+            "watch" => {
+                let id = self.room_id(request)?;
+                self.broadcast(id, client, json::object! { event: "watch", game: id });
+                let room = self.rooms.get_mut(&id).unwrap();
+                if room.seat_of(client).is_none() && !room.spectators.contains(&client) {
+                    room.spectators.push(client);
+                }
+                let mut response = room.game.to_json();
+                response["game"] = id.into();
+                Ok(response)
+            }
+            "list" => {
+                let mut ids: Vec<&u64> = self.rooms.keys().collect();
+                ids.sort();
+                let mut games = JsonValue::new_array();
+                for id in ids {
+                    games.push(self.rooms[id].summary(*id)).unwrap();
+                }
+                Ok(json::object! { games: games })
+            }
+            "move" | "resign" => {
+                let id = self.room_id(request)?;
+                let room = self.rooms.get_mut(&id).unwrap();
+                let color = room.seat_of(client).ok_or_else(|| {
+                    error("not_a_player", format!("you do not play in game {}", id))
+                })?;
+
+                let mut event;
+                if command == "move" {
+                    if room.game.notation().turn() != color {
+                        return Err(error(
+                            "not_your_turn",
+                            format!("it is {}'s turn", color_name(1 - color)),
+                        ));
+                    }
+                    let input = protocol::required_str(request, "move")?;
+                    let played = room.game.play(input)?;
+                    event = json::object! { event: "move", move: move_to_json(&played) };
+                } else {
+                    if room.game.result() != GameResult::Ongoing {
+                        return Err(error("game_over", "the game is over".to_string()));
+                    }
+                    room.game.resign(color);
+                    event = json::object! { event: "result" };
+                }
+
+                let response = room.game.to_json();
+                event["game"] = id.into();
+                event["fen"] = room.game.fen().into();
+                event["turn"] = response["turn"].clone();
+                event["result"] = response["result"].clone();
+                event["termination"] = response["termination"].clone();
+                self.broadcast(id, client, event);
+                Ok(response)
+            }
+            "legal_moves" | "status" | "fen" | "search" => {
+                let id = self.room_id(request)?;
+                let room = self.rooms.get_mut(&id).unwrap();
+                let mut response = game_command(&mut room.game, command, request)?;
+                response["game"] = id.into();
+                Ok(response)
+            }
+            other => Err(error(
+                "unknown_command",
+                format!("unknown command '{}'", other),
+            )),
+        }
+    }
+}
+
+/* The lobby, also after a handler panicked while holding it; every
+ * command leaves the lobby consistent before it can fail */
+fn lock(lobby: &Mutex<Lobby>) -> MutexGuard<'_, Lobby> {
+    lobby.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/* Like Lobby::handle_line, but with the lobby locked only as long as
+ * needed: searches and move lists run on a copy of the game */
+fn handle_shared(lobby: &Mutex<Lobby>, client: ClientId, line: &str) -> String {
+    let (request, command) = match protocol::parse_request(line) {
+        Ok(parsed) => parsed,
+        Err((id, e)) => return respond(&id, Err(e)).dump(),
+    };
+    if command != "legal_moves" && command != "search" {
+        return respond(
+            &request["id"],
+            lock(lobby).command(client, &command, &request),
+        )
+        .dump();
+    }
+    let copy = {
+        let lobby = lock(lobby);
+        lobby
+            .room_id(&request)
+            .map(|id| (id, lobby.rooms[&id].game.clone()))
+    };
+    let result = copy.and_then(|(id, mut game)| {
+        let mut response = game_command(&mut game, &command, &request)?;
+        response["game"] = id.into();
+        Ok(response)
+    });
+    respond(&request["id"], result).dump()
+}
+
+/* Accepts clients until the listener fails, one thread per connection */
+pub fn serve(listener: TcpListener) -> io::Result<()> {
+    let lobby = Arc::new(Mutex::new(Lobby::new()));
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let lobby = Arc::clone(&lobby);
+        thread::spawn(move || handle_client(lobby, stream));
+    }
+    Ok(())
+}
+
+fn handle_client(lobby: Arc<Mutex<Lobby>>, stream: TcpStream) -> io::Result<()> {
+    /* responses and events share one writer so lines never interleave */
+    let (sender, receiver) = mpsc::channel::<String>();
+    let mut writer = stream.try_clone()?;
+    thread::spawn(move || {
+        for line in receiver {
+            if writeln!(writer, "{}", line).is_err() {
+                break;
+            }
+        }
+    });
+
+    let client = lock(&lobby).connect(sender.clone());
+    for line in BufReader::new(stream).lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = handle_shared(&lobby, client, &line);
+        if sender.send(response).is_err() {
+            break;
+        }
+    }
+    lock(&lobby).disconnect(client);
+    Ok(())
+}
@@ -0,0 +1,264 @@
+/*
+ * Sequential probability ratio tests between two players.
+ *
+ * H0 says the first player is elo0 stronger than the second, H1 that it
+ * is elo1 stronger. Games are played in pairs with the same opening and
+ * swapped colors, and each pair scores 0, 1/2, 1, 3/2 or 2 points. The
+ * counts of those five outcomes (the pentanomial) give the mean and the
+ * variance of the pair score, and from them the log likelihood ratio
+ *
+ *     LLR = N (s1 - s0) (2 mean - s0 - s1) / (2 variance)
+ *
+ * with N pairs, s0 and s1 the expected scores at elo0 and elo1, all per
+ * game. The match stops as soon as the LLR leaves the bounds given by
+ * alpha and beta, the error rates for accepting H1 and H0 wrongly.
+ */
+
+use crate::matchplay::{run_match_until, MatchConfig, MatchGame, MatchReport};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matchplay::{Adjudication, PlayerSpec};
+
+    #[test]
+    fn llr_and_bounds() {
+        let sprt = Sprt::parse("0,10").unwrap();
+        assert_eq!((sprt.alpha, sprt.beta), (0.05, 0.05));
+        let (lower, upper) = sprt.bounds();
+        assert!((upper - 2.944).abs() < 0.001 && (lower + 2.944).abs() < 0.001);
+
+        let strong = sprt.llr(&[0, 0, 50, 50, 0]);
+        assert!((strong - 10.85).abs() < 0.01, "{}", strong);
+        assert_eq!(sprt.verdict(&[0, 0, 50, 50, 0]), Some(Hypothesis::H1));
+        assert_eq!(sprt.verdict(&[0, 50, 50, 0, 0]), Some(Hypothesis::H0));
+        assert_eq!(sprt.verdict(&[1, 2, 3, 2, 1]), None);
+        /* identical pairs say nothing about the variance yet */
+        assert_eq!(sprt.llr(&[0, 0, 9, 0, 0]), 0.0);
+
+        assert!(Sprt::parse("10,0").is_err());
+        assert!(Sprt::parse("0,5,0.5,0.05").is_ok());
+        assert!(Sprt::parse("0,5,1.5,0.05").is_err());
+        assert!(Sprt::parse("0,5,0.5,0.5").is_err());
+        assert!(Sprt::parse("0,5,0.7,0.4").is_err());
+    }
+
+    #[test]
+    fn counts_complete_pairs() {
+        let config = MatchConfig {
+            first: PlayerSpec::parse("depth=1,name=a").unwrap(),
+            second: PlayerSpec::parse("depth=1,name=b").unwrap(),
+            games: 5,
+            openings: vec![],
+            concurrency: 2,
+            adjudication: Adjudication {
+                max_plies: Some(6),
+                ..Adjudication::default()
+            },
+        };
+        let result = run_sprt(&config, &Sprt::parse("0,5").unwrap()).unwrap();
+        /* the fifth game has no partner */
+        assert_eq!(result.pentanomial.iter().sum::<usize>(), 2);
+        assert_eq!(result.pentanomial, [0, 0, 2, 0, 0]);
+        assert_eq!(result.verdict, None);
+        assert!(result.to_string().contains("LLR 0.00"));
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Hypothesis {
+    /* the first player is no better than elo0 */
+    H0,
+    /* it is at least elo1 better */
+    H1,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sprt {
+    pub elo0: f64,
+    pub elo1: f64,
+    pub alpha: f64,
+    pub beta: f64,
+}
+
+fn expected_score(elo: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-elo / 400.0))
+}
+
+impl Sprt {
+    pub fn new(elo0: f64, elo1: f64, alpha: f64, beta: f64) -> Result<Sprt, String> {
+        if elo1 <= elo0 {
+            return Err("elo1 has to be above elo0".to_string());
+        }
+        if !(0.0..1.0).contains(&alpha)
+            || !(0.0..1.0).contains(&beta)
+            || alpha == 0.0
+            || beta == 0.0
+        {
+            return Err("alpha and beta have to be between 0 and 1".to_string());
+        }
+        /* otherwise the lower bound ends up above the upper one */
+        if alpha + beta >= 1.0 {
+            return Err("alpha and beta have to add up to less than 1".to_string());
+        }
+        Ok(Sprt {
+            elo0,
+            elo1,
+            alpha,
+            beta,
+        })
+    }
+
+    /* elo0,elo1 with alpha and beta 0.05, or elo0,elo1,alpha,beta */
+    pub fn parse(text: &str) -> Result<Sprt, String> {
+        let numbers = text
+            .split(',')
+            .map(|n| n.trim().parse::<f64>())
+            .collect::<Result<Vec<f64>, _>>()
+            .map_err(|_| format!("expected elo0,elo1[,alpha,beta], got '{}'", text))?;
+        match numbers[..] {
+            [elo0, elo1] => Sprt::new(elo0, elo1, 0.05, 0.05),
+            [elo0, elo1, alpha, beta] => Sprt::new(elo0, elo1, alpha, beta),
+            _ => Err(format!("expected elo0,elo1[,alpha,beta], got '{}'", text)),
+        }
+    }
+
+    /* The LLR accepts H0 below the first and H1 above the second */
+    pub fn bounds(&self) -> (f64, f64) {
+        (
+            (self.beta / (1.0 - self.alpha)).ln(),
+            ((1.0 - self.beta) / self.alpha).ln(),
+        )
+    }
+
+    /* pentanomial counts pairs scoring 0, 1/2, 1, 3/2 and 2 points */
+    pub fn llr(&self, pentanomial: &[usize; 5]) -> f64 {
+        let pairs: usize = pentanomial.iter().sum();
+        if pairs == 0 {
+            return 0.0;
+        }
+        let n = pairs as f64;
+        /* the pair scores per game: 0, 1/4, 1/2, 3/4 and 1 */
+        let score = |i: usize| i as f64 / 4.0;
+        let mean = (0..5)
+            .map(|i| pentanomial[i] as f64 * score(i))
+            .sum::<f64>()
+            / n;
+        let variance = (0..5)
+            .map(|i| pentanomial[i] as f64 * (score(i) - mean).powi(2))
+            .sum::<f64>()
+            / n;
+        if variance <= 0.0 {
+            return 0.0;
+        }
+        let (s0, s1) = (expected_score(self.elo0), expected_score(self.elo1));
+        n * (s1 - s0) * (2.0 * mean - s0 - s1) / (2.0 * variance)
+    }
+
+    pub fn verdict(&self, pentanomial: &[usize; 5]) -> Option<Hypothesis> {
+        let llr = self.llr(pentanomial);
+        let (lower, upper) = self.bounds();
+        if llr >= upper {
+            Some(Hypothesis::H1)
+        } else if llr <= lower {
+            Some(Hypothesis::H0)
+        } else {
+            None
+        }
+    }
+}
+
+/* Pairs where both games are over, by the points the first player got */
+pub fn pentanomial(games: &[MatchGame]) -> [usize; 5] {
+    let mut counts = [0; 5];
+    let mut pairs: Vec<(usize, u32, usize)> = vec![];
+    for g in games {
+        if let Some(half_points) = g.half_points() {
+            match pairs.iter_mut().find(|(pair, _, _)| *pair == g.pair) {
+                Some(entry) => {
+                    entry.1 += half_points;
+                    entry.2 += 1;
+                }
+                None => pairs.push((g.pair, half_points, 1)),
+            }
+        }
+    }
+    for (_, half_points, games) in pairs {
+        if games == 2 {
+            counts[half_points as usize] += 1;
+        }
+    }
+    counts
+}
+
+#[derive(Debug, Clone)]
+pub struct SprtResult {
+    pub sprt: Sprt,
+    pub report: MatchReport,
+    pub pentanomial: [usize; 5],
+    pub llr: f64,
+    /* None when the games ran out first */
+    pub verdict: Option<Hypothesis>,
+}
+
+impl fmt::Display for SprtResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (lower, upper) = self.sprt.bounds();
+        writeln!(f, "{}", self.report.summary())?;
+        writeln!(
+            f,
+            "SPRT elo0 {} elo1 {} alpha {} beta {}: LLR {:.2} ({:.2}, {:.2}), pentanomial {:?}",
+            self.sprt.elo0,
+            self.sprt.elo1,
+            self.sprt.alpha,
+            self.sprt.beta,
+            self.llr,
+            lower,
+            upper,
+            self.pentanomial
+        )?;
+        match self.verdict {
+            Some(Hypothesis::H0) => write!(f, "H0 accepted"),
+            Some(Hypothesis::H1) => write!(f, "H1 accepted"),
+            None => write!(f, "no decision after {} games", self.report.games.len()),
+        }
+    }
+}
+
+/* Plays at most config.games games, stopping once a hypothesis holds */
+pub fn run_sprt(config: &MatchConfig, sprt: &Sprt) -> Result<SprtResult, String> {
+    run_sprt_with(config, sprt, |_| ())
+}
+
+pub fn run_sprt_with(
+    config: &MatchConfig,
+    sprt: &Sprt,
+    on_game: impl Fn(&MatchGame) + Sync,
+) -> Result<SprtResult, String> {
+    let games: Mutex<Vec<MatchGame>> = Mutex::new(vec![]);
+    let decided = AtomicBool::new(false);
+    let report = run_match_until(
+        config,
+        |g| {
+            on_game(g);
+            let mut games = games.lock().unwrap();
+            games.push(g.clone());
+            if sprt.verdict(&pentanomial(&games)).is_some() {
+                decided.store(true, Ordering::SeqCst);
+            }
+        },
+        || decided.load(Ordering::SeqCst),
+    )?;
+
+    let pentanomial = pentanomial(&report.games);
+    Ok(SprtResult {
+        sprt: *sprt,
+        llr: sprt.llr(&pentanomial),
+        verdict: sprt.verdict(&pentanomial),
+        pentanomial,
+        report,
+    })
+}
@@ -0,0 +1,1081 @@
+/*
+ * Endgame tablebases for up to four men, made by retrograde analysis.
+ * A table holds one material configuration, KQK or KRKP for instance,
+ * white's men named first. It knows for every position with that
+ * material and either side to move whether the side to move wins, draws
+ * or loses, and how many plies it takes until mate.
+ *
+ * Generation starts from the mates and works backwards one ply at a
+ * time: a position is won in n plies when some move leads to a loss in
+ * n - 1, and lost in n when every move leads to a win and the longest of
+ * them takes n - 1. Captures and promotions change the material and are
+ * looked up in the smaller tables, which are made first. Castling rights
+ * and en passant are not part of a table.
+ *
+ * A position is found at
+ *
+ *     index = (turn * 64 + square 0) * 64 + square 1 ...
+ *
+ * with the men in table order, squares counted from a1 along the ranks.
+ * Every position takes one byte: 0 for a draw, an odd n for a win in n
+ * plies, an even n + 2 for a loss in n plies and 255 for a position that
+ * cannot come up. A table file is "SMTB", a version byte, the length of
+ * the material name, the name and then those bytes.
+ */
+
+use crate::engine::MATE_SCORE;
+use crate::movegen::{piece_letter, LegalMove};
+use crate::position::CastlingRights;
+use crate::*;
+use std::cmp::Reverse;
+use std::fs;
+use std::path::Path;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn position(fen: &str) -> AlgebraicNotation {
+        AlgebraicNotation::from_fen(fen).unwrap()
+    }
+
+    /* made once, it takes a while without optimisations */
+    fn kpk() -> &'static Tablebase {
+        static TABLES: std::sync::OnceLock<Tablebase> = std::sync::OnceLock::new();
+        TABLES.get_or_init(|| {
+            let mut tablebase = Tablebase::new();
+            let made: Vec<String> = tablebase
+                .generate(&Material::parse("KPK").unwrap())
+                .iter()
+                .map(|m| m.to_string())
+                .collect();
+            assert_eq!(made, vec!["KQK", "KRK", "KPK"]);
+            tablebase
+        })
+    }
+
+    #[test]
+    fn materials() {
+        let material = Material::parse("KRKP").unwrap();
+        assert_eq!(material.to_string(), "KRKP");
+        assert_eq!(material.men(), 4);
+        assert_eq!(
+            Material::parse("kpk").unwrap().canonical().to_string(),
+            "KPK"
+        );
+        assert_eq!(
+            Material::parse("KKP").unwrap().canonical().to_string(),
+            "KPK"
+        );
+        let children: Vec<String> = Material::parse("KPK")
+            .unwrap()
+            .children()
+            .iter()
+            .map(|m| m.to_string())
+            .collect();
+        assert_eq!(children, vec!["KBK", "KK", "KNK", "KQK", "KRK"]);
+        for bad in &["KQ", "QKK", "KQKQK", "KQRKB", "KXK"] {
+            assert!(Material::parse(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn queen_against_king() {
+        let tablebase = kpk();
+        let table = tablebase.get(&Material::parse("KQK").unwrap()).unwrap();
+        /* the longest mates take ten and sixteen moves */
+        assert_eq!(table.longest(), Some(Probe::win(19)));
+        let rook = tablebase.get(&Material::parse("KRK").unwrap()).unwrap();
+        assert_eq!(rook.longest(), Some(Probe::win(31)));
+
+        let mate_in_one = position("k7/8/1K6/8/8/8/8/6Q1 w - - 0 1");
+        assert_eq!(tablebase.probe(&mate_in_one), Some(Probe::win(1)));
+        let (m, probe) = tablebase.best_move(&mate_in_one).unwrap();
+        assert_eq!(probe, Probe::win(1));
+        assert!(m.san.ends_with('#'));
+        assert_eq!(
+            tablebase.probe(&position("k7/1Q6/1K6/8/8/8/8/8 b - - 0 1")),
+            Some(Probe::loss(0))
+        );
+        /* the queen is lost */
+        assert_eq!(
+            tablebase.probe(&position("k7/1Q6/8/8/8/8/8/7K b - - 0 1")),
+            Some(Probe::draw())
+        );
+        assert_eq!(
+            tablebase.probe(&position("k7/1Q6/8/8/8/8/8/7K w - - 0 1")),
+            None
+        );
+        assert_eq!(tablebase.probe(&position(fen::START_FEN)), None);
+
+        let bytes = table.to_bytes();
+        assert_eq!(Table::from_bytes(&bytes).unwrap(), *table);
+        assert!(Table::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(Table::from_bytes(b"SMTB").is_err());
+    }
+
+    #[test]
+    fn en_passant_is_not_in_the_tables() {
+        /* a table calling everything a draw shows whether it is asked */
+        let material = Material::parse("KPKP").unwrap();
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&[VERSION, 4]);
+        bytes.extend_from_slice(b"KPKP");
+        bytes.resize(bytes.len() + material.size(), 0);
+        let mut tablebase = Tablebase::new();
+        tablebase.insert(Table::from_bytes(&bytes).unwrap());
+
+        let mut pushed = position("8/8/8/8/1p6/8/P7/K1k5 w - - 0 1");
+        assert_eq!(tablebase.probe(&pushed), Some(Probe::draw()));
+        pushed.play_move("a4").unwrap();
+        assert!(pushed.state().en_passant.is_some());
+        assert_eq!(tablebase.probe(&pushed), None);
+        assert!(tablebase.best_move(&pushed).is_none());
+
+        /* with no pawn to take, the square changes nothing */
+        let mut pushed = position("8/7p/8/8/8/8/P7/K1k5 w - - 0 1");
+        pushed.play_move("a4").unwrap();
+        assert!(pushed.state().en_passant.is_some());
+        assert_eq!(tablebase.probe(&pushed), Some(Probe::draw()));
+        assert_eq!(Probe::win(3).score(), MATE_SCORE - 3);
+    }
+
+    #[test]
+    fn pawn_endings() {
+        let tablebase = kpk();
+        /* whoever has to move gives up the opposition */
+        let opposition = "8/4k3/8/4K3/4P3/8/8/8";
+        assert_eq!(
+            tablebase.probe(&position(&format!("{} w - - 0 1", opposition))),
+            Some(Probe::draw())
+        );
+        let probe = tablebase
+            .probe(&position(&format!("{} b - - 0 1", opposition)))
+            .unwrap();
+        assert_eq!(probe.wdl, Wdl::Loss);
+        /* the same with the colours swapped, from the KPK table */
+        assert_eq!(
+            tablebase.probe(&position("8/8/8/4p3/4k3/8/4K3/8 w - - 0 1")),
+            Some(probe)
+        );
+        let mut won = position("4k3/8/3K4/4P3/8/8/8/8 w - - 0 1");
+        let (m, best) = tablebase.best_move(&won).unwrap();
+        assert_eq!(best.wdl, Wdl::Win);
+        won.try_move(&m.notation).unwrap();
+        assert_eq!(tablebase.probe(&won), Some(Probe::loss(best.dtm - 1)));
+        assert_eq!(probe.to_string(), format!("mated in {}", probe.moves()));
+    }
+}
+
+pub const MAX_MEN: usize = 4;
+
+/* in table order */
+const KINDS: &str = "KQRBNP";
+const INVALID: u8 = 255;
+const MAGIC: &[u8] = b"SMTB";
+const VERSION: u8 = 1;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TablebaseError {
+    Io(String),
+    /* not a table file, or one cut short */
+    Format(String),
+    Material(String),
+}
+
+impl fmt::Display for TablebaseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TablebaseError::Io(message) => write!(f, "{}", message),
+            TablebaseError::Format(message) => write!(f, "not a tablebase: {}", message),
+            TablebaseError::Material(text) => write!(
+                f,
+                "'{}' is no material of {} men at most, like KQK or KRKP",
+                text, MAX_MEN
+            ),
+        }
+    }
+}
+
+/* worst first */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Wdl {
+    Loss,
+    Draw,
+    Win,
+}
+
+/* The value of a position for the side to move */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Probe {
+    pub wdl: Wdl,
+    /* plies until mate, 0 for draws */
+    pub dtm: u32,
+}
+
+impl Probe {
+    pub fn win(dtm: u32) -> Probe {
+        Probe { wdl: Wdl::Win, dtm }
+    }
+
+    pub fn loss(dtm: u32) -> Probe {
+        Probe {
+            wdl: Wdl::Loss,
+            dtm,
+        }
+    }
+
+    pub fn draw() -> Probe {
+        Probe {
+            wdl: Wdl::Draw,
+            dtm: 0,
+        }
+    }
+
+    /* Moves until mate, counting the mating one */
+    pub fn moves(&self) -> u32 {
+        self.dtm.div_ceil(2)
+    }
+
+    /* As the engine scores a mate found by search */
+    pub fn score(&self) -> i32 {
+        match self.wdl {
+            Wdl::Win => MATE_SCORE - self.dtm as i32,
+            Wdl::Loss => -MATE_SCORE + self.dtm as i32,
+            Wdl::Draw => 0,
+        }
+    }
+
+    /* The value for the side that moved into this position */
+    fn before(self) -> Probe {
+        match self.wdl {
+            Wdl::Win => Probe::loss(self.dtm + 1),
+            Wdl::Loss => Probe::win(self.dtm + 1),
+            Wdl::Draw => self,
+        }
+    }
+
+    /* Quick wins before slow ones, slow losses before quick ones */
+    fn preference(&self) -> (Wdl, i64) {
+        match self.wdl {
+            Wdl::Win => (Wdl::Win, -(self.dtm as i64)),
+            Wdl::Draw => (Wdl::Draw, 0),
+            Wdl::Loss => (Wdl::Loss, self.dtm as i64),
+        }
+    }
+
+    fn to_byte(self) -> u8 {
+        assert!(self.dtm <= 253, "mate too far away to store");
+        match self.wdl {
+            Wdl::Win => self.dtm as u8,
+            Wdl::Loss => self.dtm as u8 + 2,
+            Wdl::Draw => 0,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Option<Probe> {
+        match byte {
+            INVALID => None,
+            0 => Some(Probe::draw()),
+            n if n % 2 == 1 => Some(Probe::win(n as u32)),
+            n => Some(Probe::loss(n as u32 - 2)),
+        }
+    }
+}
+
+impl fmt::Display for Probe {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.wdl {
+            Wdl::Win => write!(f, "mate in {}", self.moves()),
+            Wdl::Loss if self.dtm == 0 => write!(f, "mated"),
+            Wdl::Loss => write!(f, "mated in {}", self.moves()),
+            Wdl::Draw => write!(f, "draw"),
+        }
+    }
+}
+
+fn kind_rank(kind: char) -> usize {
+    KINDS.find(kind).unwrap_or(KINDS.len())
+}
+
+fn kind_value(kind: char) -> u32 {
+    match kind {
+        'Q' => 9,
+        'R' => 5,
+        'B' | 'N' => 3,
+        'P' => 1,
+        _ => 0,
+    }
+}
+
+/* The men of a table, white's first and each side in KINDS order */
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Material {
+    men: Vec<(usize, char)>,
+}
+
+impl Material {
+    fn new(mut men: Vec<(usize, char)>) -> Material {
+        men.sort_by_key(|(color, kind)| (*color, kind_rank(*kind)));
+        Material { men }
+    }
+
+    /* White's men from the first king, black's from the second */
+    pub fn parse(text: &str) -> Result<Material, TablebaseError> {
+        let error = || TablebaseError::Material(text.to_string());
+        let upper = text.trim().to_ascii_uppercase();
+        if !upper.starts_with('K') || !upper.chars().all(|c| KINDS.contains(c)) {
+            return Err(error());
+        }
+        let second = upper[1..].find('K').ok_or_else(error)? + 1;
+        let (white, black) = upper.split_at(second);
+        if white[1..].contains('K') || black[1..].contains('K') || upper.len() > MAX_MEN {
+            return Err(error());
+        }
+        let men = white
+            .chars()
+            .map(|kind| (_WHITE_PIECE, kind))
+            .chain(black.chars().map(|kind| (_BLACK_PIECE, kind)))
+            .collect();
+        Ok(Material::new(men))
+    }
+
+    pub fn men(&self) -> usize {
+        self.men.len()
+    }
+
+    fn strength(&self, color: usize) -> u32 {
+        self.men
+            .iter()
+            .filter(|(c, _)| *c == color)
+            .map(|(_, kind)| kind_value(*kind))
+            .sum()
+    }
+
+    fn mirrored(&self) -> Material {
+        Material::new(self.men.iter().map(|(c, k)| (1 - *c, *k)).collect())
+    }
+
+    /* The stronger side as white, the way tables are named */
+    pub fn canonical(&self) -> Material {
+        let key = |m: &Material| {
+            (
+                m.strength(_WHITE_PIECE) as i64 - m.strength(_BLACK_PIECE) as i64,
+                Reverse(m.to_string()),
+            )
+        };
+        let mirrored = self.mirrored();
+        if key(&mirrored) > key(self) {
+            mirrored
+        } else {
+            self.clone()
+        }
+    }
+
+    /* Nobody can ever be mated: two kings, perhaps with one minor piece */
+    fn insufficient(&self) -> bool {
+        self.men.len() <= 3 && self.men.iter().all(|(_, k)| "KBN".contains(*k))
+    }
+
+    /* What captures and promotions can turn this material into */
+    pub fn children(&self) -> Vec<Material> {
+        let mut children = vec![];
+        let without = |men: &[(usize, char)], i: usize| {
+            let mut men = men.to_vec();
+            men.remove(i);
+            Material::new(men)
+        };
+        for (i, (color, kind)) in self.men.iter().enumerate() {
+            if *kind == 'K' {
+                continue;
+            }
+            children.push(without(&self.men, i));
+            if *kind != 'P' {
+                continue;
+            }
+            for promoted in "QRBN".chars() {
+                let mut men = self.men.clone();
+                men[i].1 = promoted;
+                for (j, (other, taken)) in self.men.iter().enumerate() {
+                    if other != color && *taken != 'K' {
+                        children.push(without(&men, j));
+                    }
+                }
+                children.push(Material::new(men));
+            }
+        }
+        let mut children: Vec<Material> = children.iter().map(|m| m.canonical()).collect();
+        children.sort_by_key(|m| m.to_string());
+        children.dedup();
+        children
+    }
+
+    fn size(&self) -> usize {
+        2 * 64usize.pow(self.men.len() as u32)
+    }
+}
+
+impl fmt::Display for Material {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (_, kind) in &self.men {
+            write!(f, "{}", kind)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Man {
+    color: usize,
+    kind: char,
+    /* 8 * row + column */
+    square: usize,
+}
+
+const NO_MAN: Man = Man {
+    color: _WHITE_PIECE,
+    kind: 'K',
+    square: 64,
+};
+
+const KING_STEPS: [(i32, i32); 8] = [
+    (1, 0),
+    (1, 1),
+    (0, 1),
+    (-1, 1),
+    (-1, 0),
+    (-1, -1),
+    (0, -1),
+    (1, -1),
+];
+const KNIGHT_STEPS: [(i32, i32); 8] = [
+    (2, 1),
+    (1, 2),
+    (-1, 2),
+    (-2, 1),
+    (-2, -1),
+    (-1, -2),
+    (1, -2),
+    (2, -1),
+];
+
+fn offset(square: usize, dr: i32, dc: i32) -> Option<usize> {
+    let row = (square / 8) as i32 + dr;
+    let col = (square % 8) as i32 + dc;
+    if (0..8).contains(&row) && (0..8).contains(&col) {
+        Some((8 * row + col) as usize)
+    } else {
+        None
+    }
+}
+
+fn forward(color: usize) -> i32 {
+    if color == _WHITE_PIECE {
+        1
+    } else {
+        -1
+    }
+}
+
+fn lines(kind: char) -> &'static [(i32, i32)] {
+    match kind {
+        'R' => &[(1, 0), (0, 1), (-1, 0), (0, -1)],
+        'B' => &[(1, 1), (-1, 1), (-1, -1), (1, -1)],
+        _ => &KING_STEPS,
+    }
+}
+
+/* A small position of its own, quicker to move about than a Board */
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Setup {
+    men: [Man; MAX_MEN],
+    count: usize,
+    turn: usize,
+}
+
+impl Setup {
+    fn decode(material: &Material, mut index: usize) -> Setup {
+        let mut men = [NO_MAN; MAX_MEN];
+        for i in (0..material.men.len()).rev() {
+            let (color, kind) = material.men[i];
+            men[i] = Man {
+                color,
+                kind,
+                square: index % 64,
+            };
+            index /= 64;
+        }
+        Setup {
+            men,
+            count: material.men.len(),
+            turn: index,
+        }
+    }
+
+    fn from_notation(notation: &AlgebraicNotation) -> Option<Setup> {
+        if notation.state().castling != CastlingRights::none() {
+            return None;
+        }
+        let mut men = [NO_MAN; MAX_MEN];
+        let mut count = 0;
+        for (row, squares) in notation.board().table.iter().enumerate() {
+            for (col, square) in squares.iter().enumerate() {
+                if let Some(p) = square {
+                    if count == MAX_MEN {
+                        return None;
+                    }
+                    men[count] = Man {
+                        color: p.get_color(),
+                        kind: piece_letter(p.as_ref()),
+                        square: 8 * row + col,
+                    };
+                    count += 1;
+                }
+            }
+        }
+        Some(Setup {
+            men,
+            count,
+            turn: notation.turn(),
+        })
+    }
+
+    fn men(&self) -> &[Man] {
+        &self.men[..self.count]
+    }
+
+    fn material(&self) -> Material {
+        Material::new(self.men().iter().map(|m| (m.color, m.kind)).collect())
+    }
+
+    /* Only meaningful with the men in table order */
+    fn index(&self) -> usize {
+        self.men()
+            .iter()
+            .fold(self.turn, |index, m| 64 * index + m.square)
+    }
+
+    /* The men put in the order of a table with this material */
+    fn arrange(&self, material: &Material) -> Option<Setup> {
+        let mut arranged = *self;
+        let mut used = [false; MAX_MEN];
+        for (slot, (color, kind)) in material.men.iter().enumerate() {
+            let i = (0..self.count).find(|i| {
+                !used[*i] && self.men[*i].color == *color && self.men[*i].kind == *kind
+            })?;
+            used[i] = true;
+            arranged.men[slot] = self.men[i];
+        }
+        Some(arranged)
+    }
+
+    /* Colours swapped and the board turned upside down */
+    fn mirrored(&self) -> Setup {
+        let mut mirrored = *self;
+        for m in mirrored.men[..self.count].iter_mut() {
+            m.color = 1 - m.color;
+            m.square ^= 56;
+        }
+        mirrored.turn = 1 - self.turn;
+        mirrored
+    }
+
+    fn at(&self, square: usize) -> Option<usize> {
+        self.men().iter().position(|m| m.square == square)
+    }
+
+    fn attacks(&self, man: &Man, target: usize) -> bool {
+        let dr = (target / 8) as i32 - (man.square / 8) as i32;
+        let dc = (target % 8) as i32 - (man.square % 8) as i32;
+        match man.kind {
+            'K' => dr.abs().max(dc.abs()) == 1,
+            'N' => dr.abs() * dc.abs() == 2,
+            'P' => dr == forward(man.color) && dc.abs() == 1,
+            kind => {
+                let straight = (dr == 0) != (dc == 0);
+                let diagonal = dr != 0 && dr.abs() == dc.abs();
+                let along = match kind {
+                    'R' => straight,
+                    'B' => diagonal,
+                    _ => straight || diagonal,
+                };
+                along && {
+                    let step = 8 * dr.signum() + dc.signum();
+                    let mut square = man.square as i32 + step;
+                    while square != target as i32 && self.at(square as usize).is_none() {
+                        square += step;
+                    }
+                    square == target as i32
+                }
+            }
+        }
+    }
+
+    fn in_check(&self, color: usize) -> bool {
+        let king = self
+            .men()
+            .iter()
+            .find(|m| m.color == color && m.kind == 'K')
+            .map_or(64, |m| m.square);
+        self.men()
+            .iter()
+            .any(|m| m.color != color && self.attacks(m, king))
+    }
+
+    /* Men on squares of their own, no pawns on the first or last rank and
+     * the side that just moved not in check */
+    fn valid(&self) -> bool {
+        let men = self.men();
+        for (i, m) in men.iter().enumerate() {
+            let row = m.square / 8;
+            if m.kind == 'P' && (row == 0 || row == 7) {
+                return false;
+            }
+            if men[..i].iter().any(|other| other.square == m.square) {
+                return false;
+            }
+        }
+        !self.in_check(1 - self.turn)
+    }
+
+    /* The squares a man other than a pawn reaches, occupied or not */
+    fn targets(&self, man: &Man, mut f: impl FnMut(usize)) {
+        match man.kind {
+            'K' | 'N' => {
+                let steps = if man.kind == 'K' {
+                    &KING_STEPS
+                } else {
+                    &KNIGHT_STEPS
+                };
+                for (dr, dc) in steps {
+                    if let Some(target) = offset(man.square, *dr, *dc) {
+                        f(target);
+                    }
+                }
+            }
+            kind => {
+                for (dr, dc) in lines(kind) {
+                    let mut square = man.square;
+                    while let Some(target) = offset(square, *dr, *dc) {
+                        f(target);
+                        if self.at(target).is_some() {
+                            break;
+                        }
+                        square = target;
+                    }
+                }
+            }
+        }
+    }
+
+    fn play(&self, i: usize, to: usize, promotion: Option<char>, f: &mut impl FnMut(&Setup, bool)) {
+        let captured = self.at(to);
+        if captured.is_some_and(|c| self.men[c].color == self.turn) {
+            return;
+        }
+        let mut child = *self;
+        child.turn = 1 - self.turn;
+        child.men[i].square = to;
+        if let Some(kind) = promotion {
+            child.men[i].kind = kind;
+        }
+        if let Some(c) = captured {
+            child.men.copy_within(c + 1..self.count, c);
+            child.count -= 1;
+        }
+        if !child.in_check(self.turn) {
+            f(&child, captured.is_none() && promotion.is_none());
+        }
+    }
+
+    /* Every legal move, as the position it leads to and whether the
+     * material stays the same, that is neither a capture nor a promotion */
+    fn each_move(&self, mut f: impl FnMut(&Setup, bool)) {
+        for i in 0..self.count {
+            let man = self.men[i];
+            if man.color != self.turn {
+                continue;
+            }
+            if man.kind != 'P' {
+                self.targets(&man, |to| self.play(i, to, None, &mut f));
+                continue;
+            }
+            let last = if man.color == _WHITE_PIECE { 7 } else { 0 };
+            let mut advance = |to: usize| {
+                if to / 8 == last {
+                    for kind in "QRBN".chars() {
+                        self.play(i, to, Some(kind), &mut f);
+                    }
+                } else {
+                    self.play(i, to, None, &mut f);
+                }
+            };
+            let dir = forward(man.color);
+            if let Some(one) = offset(man.square, dir, 0).filter(|s| self.at(*s).is_none()) {
+                advance(one);
+                let start = if man.color == _WHITE_PIECE { 1 } else { 6 };
+                if man.square / 8 == start {
+                    if let Some(two) = offset(one, dir, 0).filter(|s| self.at(*s).is_none()) {
+                        advance(two);
+                    }
+                }
+            }
+            for dc in [-1, 1] {
+                if let Some(to) = offset(man.square, dir, dc) {
+                    if self.at(to).is_some_and(|c| self.men[c].color != self.turn) {
+                        advance(to);
+                    }
+                }
+            }
+        }
+    }
+
+    /* The positions that reach this one with a move that is neither a
+     * capture nor a promotion, whether they are valid or not */
+    fn each_unmove(&self, mut f: impl FnMut(&Setup)) {
+        let mover = 1 - self.turn;
+        for i in 0..self.count {
+            let man = self.men[i];
+            if man.color != mover {
+                continue;
+            }
+            let mut back = |from: usize| {
+                let mut earlier = *self;
+                earlier.turn = mover;
+                earlier.men[i].square = from;
+                f(&earlier);
+            };
+            if man.kind != 'P' {
+                self.targets(&man, |from| {
+                    if self.at(from).is_none() {
+                        back(from);
+                    }
+                });
+                continue;
+            }
+            let dir = -forward(mover);
+            if let Some(one) = offset(man.square, dir, 0).filter(|s| self.at(*s).is_none()) {
+                back(one);
+                let fourth = if mover == _WHITE_PIECE { 3 } else { 4 };
+                if man.square / 8 == fourth {
+                    if let Some(two) = offset(one, dir, 0).filter(|s| self.at(*s).is_none()) {
+                        back(two);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Table {
+    material: Material,
+    values: Vec<u8>,
+}
+
+impl Table {
+    pub fn material(&self) -> &Material {
+        &self.material
+    }
+
+    /* Positions that are won, drawn and lost for the side to move */
+    pub fn counts(&self) -> (usize, usize, usize) {
+        let mut counts = (0, 0, 0);
+        for probe in self.values.iter().filter_map(|v| Probe::from_byte(*v)) {
+            match probe.wdl {
+                Wdl::Win => counts.0 += 1,
+                Wdl::Draw => counts.1 += 1,
+                Wdl::Loss => counts.2 += 1,
+            }
+        }
+        counts
+    }
+
+    /* The longest win in the table */
+    pub fn longest(&self) -> Option<Probe> {
+        self.values
+            .iter()
+            .filter_map(|v| Probe::from_byte(*v))
+            .filter(|p| p.wdl == Wdl::Win)
+            .max_by_key(|p| p.dtm)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let name = self.material.to_string();
+        let mut bytes = Vec::with_capacity(6 + name.len() + self.values.len());
+        bytes.extend_from_slice(MAGIC);
+        bytes.push(VERSION);
+        bytes.push(name.len() as u8);
+        bytes.extend_from_slice(name.as_bytes());
+        bytes.extend_from_slice(&self.values);
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Table, TablebaseError> {
+        let format = |message: &str| TablebaseError::Format(message.to_string());
+        if bytes.len() < 6 || &bytes[..4] != MAGIC {
+            return Err(format("no SMTB header"));
+        }
+        if bytes[4] != VERSION {
+            return Err(format(&format!("version {}", bytes[4])));
+        }
+        let end = 6 + bytes[5] as usize;
+        let name = bytes
+            .get(6..end)
+            .and_then(|name| std::str::from_utf8(name).ok())
+            .ok_or_else(|| format("the material name is cut short"))?;
+        let material = Material::parse(name)?;
+        let values = bytes[end..].to_vec();
+        if values.len() != material.size() {
+            return Err(format(&format!(
+                "{} holds {} positions, not {}",
+                material,
+                material.size(),
+                values.len()
+            )));
+        }
+        Ok(Table { material, values })
+    }
+
+    fn probe_setup(&self, setup: &Setup) -> Option<Probe> {
+        let arranged = setup.arrange(&self.material)?;
+        Probe::from_byte(self.values[arranged.index()])
+    }
+}
+
+fn schedule(layers: &mut Vec<Vec<u32>>, dtm: u32, index: usize) {
+    let dtm = dtm as usize;
+    if layers.len() <= dtm {
+        layers.resize(dtm + 1, vec![]);
+    }
+    layers[dtm].push(index as u32);
+}
+
+/* The tables at hand, by material name */
+#[derive(Debug, Clone, Default)]
+pub struct Tablebase {
+    tables: HashMap<String, Table>,
+}
+
+impl Tablebase {
+    pub fn new() -> Tablebase {
+        Tablebase::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.tables.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tables.is_empty()
+    }
+
+    pub fn insert(&mut self, table: Table) {
+        self.tables.insert(table.material.to_string(), table);
+    }
+
+    pub fn get(&self, material: &Material) -> Option<&Table> {
+        self.tables.get(&material.to_string())
+    }
+
+    /* Every table file, *.smtb, in the directory */
+    pub fn open(dir: &str) -> Result<Tablebase, TablebaseError> {
+        let io = |e: std::io::Error| TablebaseError::Io(format!("{}: {}", dir, e));
+        let mut tablebase = Tablebase::new();
+        for entry in fs::read_dir(dir).map_err(io)? {
+            let path = entry.map_err(io)?.path();
+            if path.extension().is_some_and(|e| e == "smtb") {
+                let bytes = fs::read(&path)
+                    .map_err(|e| TablebaseError::Io(format!("{}: {}", path.display(), e)))?;
+                tablebase.insert(Table::from_bytes(&bytes)?);
+            }
+        }
+        Ok(tablebase)
+    }
+
+    /* The table of the material as DIR/KQK.smtb */
+    pub fn save(&self, material: &Material, dir: &str) -> Result<(), TablebaseError> {
+        let table = self
+            .get(material)
+            .ok_or_else(|| TablebaseError::Material(material.to_string()))?;
+        let path = Path::new(dir).join(format!("{}.smtb", material));
+        fs::write(&path, table.to_bytes())
+            .map_err(|e| TablebaseError::Io(format!("{}: {}", path.display(), e)))
+    }
+
+    /* Makes the table of the material and every smaller one it needs that
+     * is not at hand yet, and names them smallest first */
+    pub fn generate(&mut self, material: &Material) -> Vec<Material> {
+        let mut made = vec![];
+        self.generate_into(&material.canonical(), &mut made);
+        made
+    }
+
+    fn generate_into(&mut self, material: &Material, made: &mut Vec<Material>) {
+        if material.insufficient() || self.lookup(material).is_some() {
+            return;
+        }
+        for child in material.children() {
+            self.generate_into(&child, made);
+        }
+        let table = self.retrograde(material);
+        self.insert(table);
+        made.push(material.clone());
+    }
+
+    /* The table for the material, and whether it is kept with the colours swapped */
+    fn lookup(&self, material: &Material) -> Option<(&Table, bool)> {
+        match self.get(material) {
+            Some(table) => Some((table, false)),
+            None => self.get(&material.mirrored()).map(|table| (table, true)),
+        }
+    }
+
+    fn probe_setup(&self, setup: &Setup) -> Option<Probe> {
+        let material = setup.material();
+        if material.insufficient() {
+            return Some(Probe::draw());
+        }
+        match self.lookup(&material)? {
+            (table, false) => table.probe_setup(setup),
+            (table, true) => table.probe_setup(&setup.mirrored()),
+        }
+    }
+
+    /* None for positions without a table, with castling rights or that
+     * cannot come up */
+    pub fn probe(&self, notation: &AlgebraicNotation) -> Option<Probe> {
+        /* the tables know nothing of en passant, so positions where it
+         * can be taken are not in them */
+        if let Some(target) = notation.state().en_passant {
+            let takes = |m: &LegalMove| m.piece == 'P' && m.to == target;
+            if notation.successors().iter().any(|(m, _)| takes(m)) {
+                return None;
+            }
+        }
+        let setup = Setup::from_notation(notation)?;
+        if !setup.valid() {
+            return None;
+        }
+        self.probe_setup(&setup)
+    }
+
+    /* The move that keeps the value of the position, mating as quickly as
+     * possible when winning and holding out longest when losing */
+    pub fn best_move(&self, notation: &AlgebraicNotation) -> Option<(LegalMove, Probe)> {
+        self.probe(notation)?;
+        let scored: Option<Vec<(LegalMove, Probe)>> = notation
+            .legal_moves()
+            .into_iter()
+            .map(|m| {
+                let mut next = notation.clone();
+                next.try_move(&m.notation).ok()?;
+                Some((m, self.probe(&next)?.before()))
+            })
+            .collect();
+        scored?
+            .into_iter()
+            .rev()
+            .max_by_key(|(_, probe)| probe.preference())
+    }
+
+    fn retrograde(&self, material: &Material) -> Table {
+        let size = material.size();
+        let mut values = vec![0u8; size];
+        /* moves not known to lose yet, within the table */
+        let mut open = vec![0u8; size];
+        /* the best capture or promotion, INVALID when there is none */
+        let mut converts = vec![INVALID; size];
+        let mut done = vec![false; size];
+        let mut layers: Vec<Vec<u32>> = vec![];
+        let outside = |child: &Setup| {
+            self.probe_setup(child)
+                .expect("smaller tables are made first")
+                .before()
+        };
+
+        for (index, value) in values.iter_mut().enumerate() {
+            let setup = Setup::decode(material, index);
+            if !setup.valid() {
+                *value = INVALID;
+                continue;
+            }
+            let mut quiet = 0;
+            let mut best: Option<Probe> = None;
+            setup.each_move(|child, same| {
+                if same {
+                    quiet += 1;
+                } else {
+                    let probe = outside(child);
+                    if best.is_none_or(|b| probe.preference() > b.preference()) {
+                        best = Some(probe);
+                    }
+                }
+            });
+            open[index] = quiet;
+            if let Some(p) = best {
+                converts[index] = p.to_byte();
+            }
+            match best {
+                None if quiet == 0 && setup.in_check(setup.turn) => {
+                    *value = Probe::loss(0).to_byte();
+                    schedule(&mut layers, 0, index);
+                }
+                /* left at 0 until its layer, in case a quiet move mates sooner */
+                Some(p) if p.wdl == Wdl::Win => schedule(&mut layers, p.dtm, index),
+                Some(p) if p.wdl == Wdl::Loss && quiet == 0 => {
+                    *value = p.to_byte();
+                    schedule(&mut layers, p.dtm, index);
+                }
+                _ => (),
+            }
+        }
+
+        let mut dtm = 0;
+        while dtm < layers.len() {
+            for index in std::mem::take(&mut layers[dtm]) {
+                let index = index as usize;
+                if done[index] {
+                    continue;
+                }
+                done[index] = true;
+                if values[index] == 0 {
+                    values[index] = Probe::win(dtm as u32).to_byte();
+                }
+                let won = Probe::from_byte(values[index]).unwrap().wdl == Wdl::Win;
+                Setup::decode(material, index).each_unmove(|earlier| {
+                    /* invalid positions were marked at the start */
+                    let e = earlier.index();
+                    if values[e] != 0 {
+                        return;
+                    }
+                    if !won {
+                        values[e] = Probe::win(dtm as u32 + 1).to_byte();
+                        schedule(&mut layers, dtm as u32 + 1, e);
+                        return;
+                    }
+                    open[e] -= 1;
+                    if open[e] > 0 {
+                        return;
+                    }
+                    /* every quiet move loses; captures and promotions may not */
+                    let longest = match Probe::from_byte(converts[e]) {
+                        None => Some(dtm as u32 + 1),
+                        Some(p) if p.wdl == Wdl::Loss => Some(p.dtm.max(dtm as u32 + 1)),
+                        Some(_) => None,
+                    };
+                    if let Some(longest) = longest {
+                        values[e] = Probe::loss(longest).to_byte();
+                        schedule(&mut layers, longest, e);
+                    }
+                });
+            }
+            dtm += 1;
+        }
+
+        Table {
+            material: material.clone(),
+            values,
+        }
+    }
+}
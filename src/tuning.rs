@@ -0,0 +1,296 @@
+/*
+ * Texel tuning of the evaluation weights. Every position of a dataset is
+ * labelled with how its game ended, and the weights are moved so that
+ *
+ *     sigmoid(score) = 1 / (1 + 10^(-k score / 400))
+ *
+ * of the quiescence score predicts that result as well as it can, in
+ * mean squared error. k is fitted first, for the weights tuning starts
+ * from, so that the error measures the weights and not the scale.
+ *
+ * Quiescence is run once up front; after that the evaluation of each
+ * position is a dot product of the weights with the features of its
+ * quiet leaf, which keeps the many passes over the data cheap. The
+ * weights follow the gradient of the error with Adam steps.
+ */
+
+use crate::datagen::DataPosition;
+use crate::epd::EpdRecord;
+use crate::evaluation::{self, Weights, WEIGHT_COUNT};
+use crate::*;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dataset_formats() {
+        let text = "\
+# comment
+4k3/8/8/8/8/8/8/1N2K3 w - - 0 1 | 300 | 1.0
+4k3/8/8/8/8/8/8/1n2K3 w - - 0 1 [0.0]
+4k3/8/8/8/8/8/8/4K3 w - - c9 \"1/2-1/2\";
+";
+        let positions = load_dataset(text).unwrap();
+        let results: Vec<f64> = positions.iter().map(|(_, r)| *r).collect();
+        assert_eq!(results, vec![1.0, 0.0, 0.5]);
+        assert!(load_dataset("4k3/8/8/8/8/8/8/4K3 w - - 0 1").is_err());
+    }
+
+    #[test]
+    fn sigmoid_error() {
+        assert_eq!(sigmoid(0.0, 1.0), 0.5);
+        assert!((sigmoid(400.0, 1.0) - 10.0 / 11.0).abs() < 1e-9);
+        let positions = prepare(
+            &[(
+                AlgebraicNotation::from_fen("4k3/8/8/8/8/8/8/1N2K3 w - - 0 1").unwrap(),
+                1.0,
+            )],
+            &Weights::default(),
+        );
+        let values = to_f64(&Weights::default());
+        let expected = (1.0 - sigmoid(320.0, 1.0)).powi(2);
+        assert!((error(&positions, &values, 1.0) - expected).abs() < 1e-9);
+    }
+
+    #[test]
+    fn tuning_lowers_the_error() {
+        /* knights win, extra pawns on the second rank only draw */
+        let mut labelled = vec![];
+        for board in &[
+            "4k3/8/8/8/8/8/8/1N2K3",
+            "4k3/8/8/8/8/2N5/8/4K3",
+            "4k3/8/8/8/3N4/8/8/4K3",
+            "4k3/8/8/5N2/8/8/8/4K3",
+            "4k3/8/6N1/8/8/8/8/4K3",
+        ] {
+            let fen = format!("{} w - - 0 1", board);
+            labelled.push((AlgebraicNotation::from_fen(&fen).unwrap(), 1.0));
+            let fen = fen.replacen('N', "n", 1);
+            labelled.push((AlgebraicNotation::from_fen(&fen).unwrap(), 0.0));
+        }
+        labelled.push((
+            AlgebraicNotation::from_fen("4k3/8/8/8/8/8/PP6/4K3 w - - 0 1").unwrap(),
+            0.5,
+        ));
+        labelled.push((
+            AlgebraicNotation::from_fen("4k3/pp6/8/8/8/8/8/4K3 w - - 0 1").unwrap(),
+            0.5,
+        ));
+
+        let positions = prepare(&labelled, &Weights::default());
+        let config = TunerConfig {
+            iterations: 200,
+            ..TunerConfig::default()
+        };
+        let mut reported = 0;
+        let report = tune(&positions, &Weights::default(), &config, |_, _| {
+            reported += 1
+        });
+        assert_eq!(reported, 200);
+        assert!(report.final_error < report.initial_error / 2.0);
+        /* pawns that only draw are worth less now, knights that win more */
+        assert!(report.weights.get("material P").unwrap() < 100);
+        assert!(report.weights.get("material N").unwrap() > 320);
+        assert!(report.to_string().contains("error"));
+    }
+}
+
+/* A position reduced to the features of its quiet leaf */
+#[derive(Debug, Clone, PartialEq)]
+pub struct TuningPosition {
+    pub features: Vec<(usize, i32)>,
+    /* 1 for a white win, 0.5 for a draw and 0 for a loss */
+    pub result: f64,
+}
+
+fn result_value(token: &str) -> Option<f64> {
+    match token.trim().trim_matches('"') {
+        "1.0" | "1" | "1-0" => Some(1.0),
+        "0.5" | "1/2-1/2" | "1/2" => Some(0.5),
+        "0.0" | "0" | "0-1" => Some(0.0),
+        _ => None,
+    }
+}
+
+/* Positions with results, one per line, as datagen writes them
+ * (fen | score | result), as a FEN followed by [1.0], [0.5] or [0.0], or
+ * as EPD with the result in c9. Empty lines and # comments are skipped. */
+pub fn load_dataset(text: &str) -> Result<Vec<(AlgebraicNotation, f64)>, String> {
+    let mut positions = vec![];
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let at = |message: String| format!("line {}: {}", number + 1, message);
+        let (fen, result) = if line.contains('|') {
+            let position = DataPosition::parse(line).map_err(at)?;
+            let result = position.white_score();
+            (position.fen, result)
+        } else if let Some(open) = line.rfind('[') {
+            let label = line[open + 1..].trim_end_matches(']');
+            let result =
+                result_value(label).ok_or_else(|| at(format!("bad result '{}'", label)))?;
+            (line[..open].trim().to_string(), result)
+        } else {
+            let record = EpdRecord::parse(line).map_err(|e| at(e.to_string()))?;
+            let result = record
+                .operation("c9")
+                .and_then(|operands| result_value(&operands.join(" ")))
+                .ok_or_else(|| at("no result in c9".to_string()))?;
+            (record.fen(), result)
+        };
+        let notation = AlgebraicNotation::from_fen(&fen).map_err(|e| at(e.to_string()))?;
+        positions.push((notation, result));
+    }
+    Ok(positions)
+}
+
+/* Resolves the captures of every position with the given weights */
+pub fn prepare(labelled: &[(AlgebraicNotation, f64)], weights: &Weights) -> Vec<TuningPosition> {
+    labelled
+        .iter()
+        .map(|(notation, result)| {
+            let (_, leaf) = evaluation::quiescence(notation, weights);
+            TuningPosition {
+                features: evaluation::features(&leaf),
+                result: *result,
+            }
+        })
+        .collect()
+}
+
+pub fn sigmoid(score: f64, k: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-k * score / 400.0))
+}
+
+fn to_f64(weights: &Weights) -> Vec<f64> {
+    weights.values.iter().map(|v| *v as f64).collect()
+}
+
+/* From white's point of view, like the results */
+fn score(position: &TuningPosition, values: &[f64]) -> f64 {
+    position
+        .features
+        .iter()
+        .map(|(index, value)| values[*index] * *value as f64)
+        .sum()
+}
+
+/* Mean squared difference between results and predictions */
+pub fn error(positions: &[TuningPosition], values: &[f64], k: f64) -> f64 {
+    if positions.is_empty() {
+        return 0.0;
+    }
+    positions
+        .iter()
+        .map(|p| (p.result - sigmoid(score(p, values), k)).powi(2))
+        .sum::<f64>()
+        / positions.len() as f64
+}
+
+/* The k with the least error for these weights, by golden section */
+pub fn fit_k(positions: &[TuningPosition], weights: &Weights) -> f64 {
+    let values = to_f64(weights);
+    let ratio = (5f64.sqrt() - 1.0) / 2.0;
+    let (mut low, mut high) = (0.01, 4.0);
+    for _ in 0..60 {
+        let a = high - ratio * (high - low);
+        let b = low + ratio * (high - low);
+        if error(positions, &values, a) < error(positions, &values, b) {
+            high = b;
+        } else {
+            low = a;
+        }
+    }
+    (low + high) / 2.0
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TunerConfig {
+    /* passes over the whole dataset */
+    pub iterations: usize,
+    /* about how many centipawns a weight moves per pass */
+    pub learning_rate: f64,
+    /* fitted to the starting weights when not given */
+    pub k: Option<f64>,
+}
+
+impl Default for TunerConfig {
+    fn default() -> Self {
+        TunerConfig {
+            iterations: 1000,
+            learning_rate: 2.0,
+            k: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TuneReport {
+    pub k: f64,
+    pub positions: usize,
+    pub initial_error: f64,
+    pub final_error: f64,
+    pub weights: Weights,
+}
+
+impl fmt::Display for TuneReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "# {} positions, k {:.4}, error {:.6} -> {:.6}",
+            self.positions, self.k, self.initial_error, self.final_error
+        )?;
+        write!(f, "{}", self.weights)
+    }
+}
+
+/* Calls back after every pass with its number and the error */
+pub fn tune(
+    positions: &[TuningPosition],
+    start: &Weights,
+    config: &TunerConfig,
+    mut on_iteration: impl FnMut(usize, f64),
+) -> TuneReport {
+    let k = config.k.unwrap_or_else(|| fit_k(positions, start));
+    let mut values = to_f64(start);
+    let initial_error = error(positions, &values, k);
+
+    let (beta1, beta2, epsilon) = (0.9, 0.999, 1e-8);
+    let mut moment = vec![0.0; WEIGHT_COUNT];
+    let mut velocity = vec![0.0; WEIGHT_COUNT];
+    let scale = k * 10f64.ln() / 400.0;
+    for iteration in 1..=config.iterations {
+        let mut gradient = vec![0.0; WEIGHT_COUNT];
+        for p in positions {
+            let predicted = sigmoid(score(p, &values), k);
+            let slope = -2.0 * (p.result - predicted) * predicted * (1.0 - predicted) * scale;
+            for (index, value) in &p.features {
+                gradient[*index] += slope * *value as f64;
+            }
+        }
+        for index in 0..WEIGHT_COUNT {
+            let g = gradient[index] / positions.len().max(1) as f64;
+            moment[index] = beta1 * moment[index] + (1.0 - beta1) * g;
+            velocity[index] = beta2 * velocity[index] + (1.0 - beta2) * g * g;
+            let m = moment[index] / (1.0 - beta1.powi(iteration as i32));
+            let v = velocity[index] / (1.0 - beta2.powi(iteration as i32));
+            values[index] -= config.learning_rate * m / (v.sqrt() + epsilon);
+        }
+        on_iteration(iteration, error(positions, &values, k));
+    }
+
+    let mut weights = start.clone();
+    for (weight, value) in weights.values.iter_mut().zip(values.iter()) {
+        *weight = value.round() as i32;
+    }
+    TuneReport {
+        k,
+        positions: positions.len(),
+        initial_error,
+        final_error: error(positions, &to_f64(&weights), k),
+        weights,
+    }
+}